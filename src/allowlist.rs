@@ -0,0 +1,172 @@
+/* vim: set et ts=4 sw=4: */
+/* allowlist.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Which USB serial devices are worth probing for a GPS receiver.
+
+use crate::config::Config;
+use std::fmt;
+use std::str::FromStr;
+
+// Serial drivers GPS receivers are known to show up through.
+const DRIVERS: &[&str] = &["pl2303", "cdc_acm"];
+
+// GNSS receivers, and the USB serial adapters they're commonly built around.
+const KNOWN_RECEIVERS: &[(u16, u16, &str)] = &[
+    (0x1546, 0x01a5, "u-blox 5"),
+    (0x1546, 0x01a6, "u-blox 6"),
+    (0x1546, 0x01a7, "u-blox 7"),
+    (0x1546, 0x01a8, "u-blox 8"),
+    (0x1546, 0x01a9, "u-blox 9"),
+    (0x0e8d, 0x3329, "MediaTek 3329"),
+    (0x1163, 0x0200, "DeLorme Earthmate LT-20"),
+    (0x067b, 0x2303, "Prolific PL2303, e.g GlobalSat BU-353"),
+    (0x067b, 0xaaa0, "Prolific PL2303"),
+    (0x10c4, 0xea60, "Silicon Labs CP210x"),
+    (0x0403, 0x6001, "FTDI FT232"),
+    (0x0403, 0x6015, "FTDI FT230X"),
+    (0x1a86, 0x7523, "QinHeng CH340"),
+];
+
+/// A USB device, by vendor and product ID, e.g `1546:01a7`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UsbId {
+    pub vendor: u16,
+    pub product: u16,
+}
+
+impl FromStr for UsbId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |id: &str| {
+            if id.len() == 4 {
+                u16::from_str_radix(id, 16).ok()
+            } else {
+                None
+            }
+        };
+
+        match s.split_once(':') {
+            Some((vendor, product)) => match (parse(vendor), parse(product)) {
+                (Some(vendor), Some(product)) => Ok(UsbId { vendor, product }),
+                _ => Err(invalid_usb_id(s)),
+            },
+            None => Err(invalid_usb_id(s)),
+        }
+    }
+}
+
+impl fmt::Display for UsbId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vendor, self.product)
+    }
+}
+
+fn invalid_usb_id(s: &str) -> String {
+    format!("invalid USB ID `{}` (expected VID:PID, e.g `1546:01a7`)", s)
+}
+
+/// A udev property, e.g `ID_MODEL=u-blox_7_-_GPS_GNSS_Receiver`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UdevProperty {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for UdevProperty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(UdevProperty {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(format!(
+                "invalid udev property `{}` (expected KEY=VALUE, e.g `ID_MODEL=GPS`)",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether `device` was marked as a GPS device, with `ENV{GPS_SHARE}="1"` in a udev rule.
+pub fn is_forced(device: &udev::Device) -> bool {
+    device
+        .property_value("GPS_SHARE")
+        .is_some_and(|value| value == "1")
+}
+
+/// The drivers, USB IDs and udev properties of the USB serial devices to probe.
+#[derive(Debug)]
+pub struct Allowlist {
+    drivers: Vec<String>,
+    usb_ids: Vec<UsbId>,
+    properties: Vec<UdevProperty>,
+}
+
+impl Allowlist {
+    /// The built-in table, along with what `config` adds to it.
+    pub fn new(config: &Config) -> Self {
+        let drivers = DRIVERS.iter().map(|d| d.to_string());
+        let usb_ids = KNOWN_RECEIVERS
+            .iter()
+            .map(|&(vendor, product, _)| UsbId { vendor, product });
+
+        Allowlist {
+            drivers: drivers.chain(config.usb_drivers.iter().cloned()).collect(),
+            usb_ids: usb_ids.chain(config.usb_ids.iter().copied()).collect(),
+            properties: config.udev_properties.clone(),
+        }
+    }
+
+    /// Whether the USB serial `device` matches any of the drivers, USB IDs or properties.
+    /// Devices without a driver to go by are always probed.
+    pub fn matches(&self, device: &udev::Device) -> bool {
+        let driver = device
+            .parent()
+            .and_then(|p| p.driver().map(|d| d.to_owned()));
+        match driver {
+            Some(driver) if self.drivers.iter().any(|d| driver == d.as_str()) => return true,
+            Some(_) => {}
+            None => return true,
+        }
+
+        let id = |property| {
+            let value = device.property_value(property)?.to_str()?;
+
+            u16::from_str_radix(value, 16).ok()
+        };
+        if let (Some(vendor), Some(product)) = (id("ID_VENDOR_ID"), id("ID_MODEL_ID")) {
+            if self.usb_ids.contains(&UsbId { vendor, product }) {
+                return true;
+            }
+        }
+
+        self.properties.iter().any(|p| {
+            device
+                .property_value(&p.key)
+                .is_some_and(|value| value == p.value.as_str())
+        })
+    }
+}
@@ -21,52 +21,137 @@
  * Author: Zeeshan Ali <zeeshanak@gnome.org>
  */
 
-use zbus::{blocking::Connection, proxy, zvariant::OwnedObjectPath};
-
-#[proxy(
-    interface = "org.freedesktop.Avahi.Server",
-    default_service = "org.freedesktop.Avahi",
-    default_path = "/",
-    gen_async = false
-)]
-trait Server {
-    fn entry_group_new(&self) -> zbus::Result<OwnedObjectPath>;
-    fn get_network_interface_index_by_name(&self, name: &str) -> zbus::Result<i32>;
-}
+use proxies::{EntryGroupProxy, ServerProxy, ServiceBrowserProxy};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use zbus::blocking::{Connection, MessageIterator};
+use zbus::message::Type;
+use zbus::{MatchRule, zvariant::OwnedObjectPath};
+
+const SERVICE_TYPE: &str = "_nmea-0183._tcp";
+
+// AVAHI_LOOKUP_RESULT_LOCAL, for services on this very machine.
+const LOOKUP_RESULT_LOCAL: u32 = 8;
+
+// The TXT record marking the services gps-share publishes, wherever they run.
+const MARKER: &str = "gps-share";
+
+// Avahi tells us it's done with what it knows of within a second or so.
+const BROWSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The methods mirror the D-Bus ones, argument for argument.
+#[allow(clippy::too_many_arguments)]
+mod proxies {
+    use zbus::{proxy, zvariant::OwnedObjectPath};
+
+    // Interface, protocol, name, type, domain, host, address protocol, address, port, TXT records
+    // and flags.
+    pub type ResolvedService = (
+        i32,
+        i32,
+        String,
+        String,
+        String,
+        String,
+        i32,
+        String,
+        u16,
+        Vec<Vec<u8>>,
+        u32,
+    );
+
+    #[proxy(
+        interface = "org.freedesktop.Avahi.Server",
+        default_service = "org.freedesktop.Avahi",
+        default_path = "/",
+        gen_async = false
+    )]
+    pub trait Server {
+        fn entry_group_new(&self) -> zbus::Result<OwnedObjectPath>;
+        fn get_network_interface_index_by_name(&self, name: &str) -> zbus::Result<i32>;
+        fn service_browser_new(
+            &self,
+            ifindex: i32,
+            protocol: i32,
+            service_type: &str,
+            domain: &str,
+            flags: u32,
+        ) -> zbus::Result<OwnedObjectPath>;
+        fn resolve_service(
+            &self,
+            ifindex: i32,
+            protocol: i32,
+            name: &str,
+            service_type: &str,
+            domain: &str,
+            aprotocol: i32,
+            flags: u32,
+        ) -> zbus::Result<ResolvedService>;
+    }
 
-#[proxy(
-    interface = "org.freedesktop.Avahi.EntryGroup",
-    default_service = "org.freedesktop.Avahi",
-    gen_async = false
-)]
-trait EntryGroup {
-    fn add_service(
-        &self,
-        ifindex: i32,
-        protocol: i32,
-        flags: u32,
-        name: &str,
-        service_type: &str,
-        domain: &str,
-        host: &str,
-        port: u16,
-        text: Vec<Vec<u8>>,
-    ) -> zbus::Result<()>;
-    fn commit(&self) -> zbus::Result<()>;
+    #[proxy(
+        interface = "org.freedesktop.Avahi.ServiceBrowser",
+        default_service = "org.freedesktop.Avahi",
+        gen_async = false
+    )]
+    pub trait ServiceBrowser {
+        fn free(&self) -> zbus::Result<()>;
+    }
+
+    #[proxy(
+        interface = "org.freedesktop.Avahi.EntryGroup",
+        default_service = "org.freedesktop.Avahi",
+        gen_async = false
+    )]
+    pub trait EntryGroup {
+        fn add_service(
+            &self,
+            ifindex: i32,
+            protocol: i32,
+            flags: u32,
+            name: &str,
+            service_type: &str,
+            domain: &str,
+            host: &str,
+            port: u16,
+            text: Vec<Vec<u8>>,
+        ) -> zbus::Result<()>;
+        fn commit(&self) -> zbus::Result<()>;
+        fn free(&self) -> zbus::Result<()>;
+    }
 }
 
 pub struct Avahi {
     connection: Connection,
+    group: Option<OwnedObjectPath>,
 }
 
 impl Avahi {
     pub fn new() -> Result<Self, zbus::Error> {
         let connection = Connection::system()?;
 
-        Ok(Avahi { connection })
+        Ok(Avahi {
+            connection,
+            group: None,
+        })
     }
 
-    pub fn publish(&self, net_iface: Option<&str>, port: u16) -> Result<(), zbus::Error> {
+    /// Withdraws the service published by `publish`, if any.
+    pub fn unpublish(&mut self) -> Result<(), zbus::Error> {
+        let group_path = match self.group.take() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        EntryGroupProxy::builder(&self.connection)
+            .path(group_path)?
+            .build()?
+            .free()
+    }
+
+    pub fn publish(&mut self, net_iface: Option<&str>, port: u16) -> Result<(), zbus::Error> {
+        self.unpublish()?;
         let server = ServerProxy::new(&self.connection)?;
 
         // FIXME: Make this async when it's possible
@@ -74,10 +159,10 @@ impl Avahi {
         println!("group: {}", group_path.as_str());
 
         let group = EntryGroupProxy::builder(&self.connection)
-            .path(group_path)?
+            .path(group_path.clone())?
             .build()?;
         let txt = "accuracy=exact".to_string();
-        let array: Vec<Vec<u8>> = vec![txt.into_bytes()];
+        let array: Vec<Vec<u8>> = vec![txt.into_bytes(), MARKER.as_bytes().to_vec()];
 
         let iface = match net_iface {
             Some(name) => match server.get_network_interface_index_by_name(name) {
@@ -90,19 +175,102 @@ impl Avahi {
             },
             None => -1,
         };
-        group.add_service(
-            iface,
-            -1,
-            0,
-            "gps-share",
-            "_nmea-0183._tcp",
-            "",
-            "",
-            port,
-            array,
-        )?;
+        group.add_service(iface, -1, 0, "gps-share", SERVICE_TYPE, "", "", port, array)?;
         group.commit()?;
+        self.group = Some(group_path);
 
         Ok(())
     }
 }
+
+/// Looks for NMEA services on the network, leaving out those of this machine and those gps-share
+/// publishes (so that two of them don't read from each other), and returns their `host:port`
+/// addresses.
+pub fn find_nmea_services() -> Result<Vec<String>, zbus::Error> {
+    let (sender, receiver) = mpsc::channel();
+    // The browser thread only goes on for as long as Avahi keeps us waiting.
+    thread::spawn(move || {
+        let _ = sender.send(browse());
+    });
+
+    receiver
+        .recv_timeout(BROWSE_TIMEOUT)
+        .unwrap_or_else(|_| Err(zbus::Error::Failure("Avahi didn't get back to us".into())))
+}
+
+fn browse() -> Result<Vec<String>, zbus::Error> {
+    let connection = Connection::system()?;
+    // The browser reports what it finds right away, so we have to listen before it exists.
+    let rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .interface("org.freedesktop.Avahi.ServiceBrowser")?
+        .build();
+    let messages = MessageIterator::for_match_rule(rule, &connection, None)?;
+    let server = ServerProxy::new(&connection)?;
+    let browser = server.service_browser_new(-1, -1, SERVICE_TYPE, "", 0)?;
+
+    let mut services = vec![];
+    for message in messages {
+        let message = message?;
+        let header = message.header();
+        if header.path().map(|p| p.as_str()) != Some(browser.as_str()) {
+            continue;
+        }
+
+        match header.member().map(|m| m.as_str()) {
+            Some("ItemNew") => {
+                let (ifindex, protocol, name, service_type, domain, flags): (
+                    i32,
+                    i32,
+                    String,
+                    String,
+                    String,
+                    u32,
+                ) = message.body().deserialize()?;
+                if flags & LOOKUP_RESULT_LOCAL == 0 {
+                    services.push((ifindex, protocol, name, service_type, domain));
+                }
+            }
+            Some("AllForNow") => break,
+            Some("Failure") => {
+                let error: String = message.body().deserialize()?;
+
+                return Err(zbus::Error::Failure(error));
+            }
+            _ => {}
+        }
+    }
+    let _ = ServiceBrowserProxy::builder(&connection)
+        .path(browser)?
+        .build()?
+        .free();
+
+    let mut addresses = vec![];
+    for (ifindex, protocol, name, service_type, domain) in services {
+        let resolved =
+            server.resolve_service(ifindex, protocol, &name, &service_type, &domain, -1, 0);
+        let (address, port, txt) = match resolved {
+            Ok((_, _, _, _, _, _, _, address, port, txt, _)) => (address, port, txt),
+
+            Err(e) => {
+                println!("Failed to resolve {}: {}", name, e);
+
+                continue;
+            }
+        };
+        if txt.iter().any(|t| t == MARKER.as_bytes()) {
+            continue;
+        }
+
+        let address = if address.contains(':') {
+            format!("[{}]:{}", address, port)
+        } else {
+            format!("{}:{}", address, port)
+        };
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+
+    Ok(addresses)
+}
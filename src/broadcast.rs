@@ -0,0 +1,265 @@
+/* vim: set et ts=4 sw=4: */
+/* broadcast.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// What to do when a client's queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued item to make room for the new one.
+    DropOldest,
+    /// Disconnect the client.
+    DropClient,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "drop-client" => Ok(OverflowPolicy::DropClient),
+            _ => Err(format!(
+                "unknown overflow policy `{}` (expected `drop-oldest` or `drop-client`)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverflowPolicy::DropOldest => f.write_str("drop-oldest"),
+            OverflowPolicy::DropClient => f.write_str("drop-client"),
+        }
+    }
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+struct Queue<T> {
+    state: Mutex<QueueState<T>>,
+    ready: Condvar,
+}
+
+impl<T> Queue<T> {
+    fn close(&self) {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+struct Client<T> {
+    id: usize,
+    queue: Arc<Queue<T>>,
+    wants: fn(&T) -> bool,
+}
+
+struct Clients<T> {
+    list: Vec<Client<T>>,
+    next_id: usize,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+/// Fans items out to any number of subscribers, each with its own bounded queue so that a slow
+/// subscriber can't hold up the producer or the other subscribers.
+pub struct Broadcaster<T> {
+    clients: Mutex<Clients<T>>,
+}
+
+impl<T: Clone> Broadcaster<T> {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Broadcaster {
+            clients: Mutex::new(Clients {
+                list: vec![],
+                next_id: 0,
+                capacity: capacity.max(1),
+                policy,
+            }),
+        }
+    }
+
+    /// Changes the queue capacity and overflow policy.
+    ///
+    /// Queues already holding more than the new capacity are trimmed as items are sent to them.
+    pub fn configure(&self, capacity: usize, policy: OverflowPolicy) {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        let mut clients = self.clients.lock().unwrap();
+        clients.capacity = capacity.max(1);
+        clients.policy = policy;
+    }
+
+    /// Adds a new subscriber, with `backlog` already queued for it. Only the items `wants` is true
+    /// for are queued for it, so that what it has no use for doesn't take room in its queue.
+    pub fn subscribe(&self, backlog: Vec<T>, wants: fn(&T) -> bool) -> Subscription<T> {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        let mut clients = self.clients.lock().unwrap();
+
+        let mut items: VecDeque<T> = backlog.into_iter().filter(wants).collect();
+        if items.len() > clients.capacity {
+            items.drain(..items.len() - clients.capacity);
+        }
+        let queue = Arc::new(Queue {
+            state: Mutex::new(QueueState {
+                items,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        });
+
+        let id = clients.next_id;
+        clients.next_id += 1;
+        clients.list.push(Client {
+            id,
+            queue: queue.clone(),
+            wants,
+        });
+
+        Subscription { queue }
+    }
+
+    /// Queues `item` for every subscriber.
+    pub fn send(&self, item: &T) {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        let mut clients = self.clients.lock().unwrap();
+        let (capacity, policy) = (clients.capacity, clients.policy);
+
+        clients.list.retain(|client| {
+            let mut state = client.queue.state.lock().unwrap();
+            if state.closed {
+                return false;
+            }
+            if !(client.wants)(item) {
+                return true;
+            }
+
+            if state.items.len() >= capacity {
+                match policy {
+                    OverflowPolicy::DropOldest => {
+                        let excess = state.items.len() + 1 - capacity;
+                        state.items.drain(..excess);
+                    }
+
+                    OverflowPolicy::DropClient => {
+                        println!("Client {} can't keep up, disconnecting", client.id);
+                        state.closed = true;
+                        client.queue.ready.notify_all();
+
+                        return false;
+                    }
+                }
+            }
+
+            state.items.push_back(item.clone());
+            client.queue.ready.notify_one();
+
+            true
+        });
+    }
+}
+
+/// The receiving end of a `Broadcaster`.
+pub struct Subscription<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Subscription<T> {
+    /// Blocks until an item is available. Returns `None` once the subscription has been closed.
+    pub fn recv(&self) -> Option<T> {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        let mut state = self.queue.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return None;
+            }
+
+            if let Some(item) = state.items.pop_front() {
+                return Some(item);
+            }
+
+            state = self.queue.ready.wait(state).unwrap();
+        }
+    }
+
+    /// Like `recv`, but gives up once `timeout` has passed.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        let mut state = self.queue.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return None;
+            }
+
+            if let Some(item) = state.items.pop_front() {
+                return Some(item);
+            }
+
+            let left = deadline.checked_duration_since(Instant::now())?;
+            state = self.queue.ready.wait_timeout(state, left).unwrap().0;
+        }
+    }
+}
+
+impl<T> Subscription<T> {
+    /// A handle to close the subscription with from elsewhere, e.g when the client it's for
+    /// hangs up while `recv` waits.
+    pub fn closer(&self) -> Closer<T> {
+        Closer {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+/// Closes a `Subscription`, so that `recv` returns `None`.
+pub struct Closer<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Closer<T> {
+    pub fn close(&self) {
+        self.queue.close();
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
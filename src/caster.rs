@@ -0,0 +1,242 @@
+/* vim: set et ts=4 sw=4: */
+/* caster.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! NTRIP caster, serving the RTCM3 frames of the source (typically a receiver set up as a base
+//! station) to rovers under a single mountpoint.
+
+use crate::client_handler::Stream;
+use crate::clients::Clients;
+use crate::feed::{Event, Feed};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// How long rovers get to send their request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// What rovers asked for.
+struct Request {
+    path: String,
+    // Whether it's an NTRIP 2 request, which gets HTTP answers.
+    v2: bool,
+}
+
+pub struct Caster {
+    listener: TcpListener,
+    feed: Arc<Feed>,
+    clients: Arc<Clients>,
+    mountpoint: Arc<str>,
+}
+
+impl Caster {
+    pub fn new(
+        ip: &str,
+        port: u16,
+        mountpoint: &str,
+        feed: Arc<Feed>,
+        clients: Arc<Clients>,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind((ip, port))?;
+
+        Ok(Caster {
+            listener,
+            feed,
+            clients,
+            mountpoint: Arc::from(mountpoint),
+        })
+    }
+
+    pub fn port(&self) -> io::Result<u16> {
+        self.listener.local_addr().map(|addr| addr.port())
+    }
+
+    pub fn mountpoint(&self) -> &str {
+        &self.mountpoint
+    }
+
+    pub fn run(&self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    println!("NTRIP client connection from {}", addr.ip());
+
+                    let feed = self.feed.clone();
+                    let clients = self.clients.clone();
+                    let mountpoint = self.mountpoint.clone();
+                    thread::spawn(move || {
+                        let address = addr.to_string();
+                        match serve(stream, address, &feed, &clients, &mountpoint) {
+                            Ok(()) => println!("NTRIP client {} disconnected", addr.ip()),
+                            Err(e) => {
+                                println!("Failed to serve NTRIP client {}: {}", addr.ip(), e)
+                            }
+                        }
+                    });
+                }
+                Err(e) => {
+                    eprintln!("Connect from NTRIP client failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+fn serve(
+    mut stream: TcpStream,
+    address: String,
+    feed: &Arc<Feed>,
+    clients: &Arc<Clients>,
+    mountpoint: &str,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = read_request(&mut reader)?;
+    stream.set_read_timeout(None)?;
+
+    // Anything but our mountpoint gets the sourcetable, so rovers can find out what we have.
+    if request.path.trim_start_matches('/') != mountpoint {
+        return send_sourcetable(&mut stream, &request, feed, mountpoint);
+    }
+
+    let _registration = clients.add("ntrip", address, &Stream::Tcp(stream.try_clone()?))?;
+    let subscription = feed.subscribe_live(Event::is_rtcm);
+    if request.v2 {
+        stream.write_all(
+            b"HTTP/1.1 200 OK\r\n\
+              Ntrip-Version: Ntrip/2.0\r\n\
+              Server: gps-share\r\n\
+              Content-Type: gnss/data\r\n\
+              Cache-Control: no-store, no-cache, max-age=0\r\n\
+              Connection: close\r\n\r\n",
+        )?;
+    } else {
+        stream.write_all(b"ICY 200 OK\r\n")?;
+    }
+
+    // Rovers send GGA every now and then, which we have no use for, but reading them tells us
+    // when they hang up.
+    let hangup = stream.try_clone()?;
+    let closer = subscription.closer();
+    thread::spawn(move || {
+        let _ = io::copy(&mut reader, &mut io::sink());
+        let _ = hangup.shutdown(Shutdown::Both);
+        // So the rover goes from the list now, rather than with the next frame.
+        closer.close();
+    });
+
+    while let Some(event) = subscription.recv() {
+        if let Event::Rtcm(frame) = event {
+            match stream.write_all(&frame) {
+                Ok(()) => {}
+                // That's how rovers are done.
+                Err(e) if is_hangup(&e) => break,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Whether `e` only means the rover hung up.
+fn is_hangup(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset | io::ErrorKind::NotConnected
+    )
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let path = match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", path, protocol] if protocol.starts_with("HTTP/1.") => path.to_string(),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad request: {}", line.trim_end()),
+            ));
+        }
+    };
+
+    let mut v2 = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let header = header.trim_end().to_lowercase();
+        if header.is_empty() {
+            return Ok(Request { path, v2 });
+        }
+
+        if let Some(("ntrip-version", version)) =
+            header.split_once(':').map(|(n, v)| (n.trim(), v.trim()))
+        {
+            v2 = version == "ntrip/2.0";
+        }
+    }
+}
+
+fn send_sourcetable(
+    stream: &mut TcpStream,
+    request: &Request,
+    feed: &Feed,
+    mountpoint: &str,
+) -> io::Result<()> {
+    // Where the base station is, if it has found out yet.
+    let (latitude, longitude) = feed
+        .latest_report()
+        .filter(|r| r.fix.has_position())
+        .and_then(|r| Some((r.fix.latitude?, r.fix.longitude?)))
+        .unwrap_or((0.0, 0.0));
+    let body = format!(
+        "STR;{0};{0};RTCM 3;;2;GNSS;gps-share;;{1:.2};{2:.2};0;0;gps-share;none;N;N;0;\r\n\
+         ENDSOURCETABLE\r\n",
+        mountpoint, latitude, longitude,
+    );
+
+    let header = if request.v2 {
+        format!(
+            "HTTP/1.1 200 OK\r\n\
+             Ntrip-Version: Ntrip/2.0\r\n\
+             Server: gps-share\r\n\
+             Content-Type: gnss/sourcetable\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            body.len()
+        )
+    } else {
+        format!(
+            "SOURCETABLE 200 OK\r\n\
+             Server: gps-share\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Length: {}\r\n\r\n",
+            body.len()
+        )
+    };
+    stream.write_all(header.as_bytes())?;
+    stream.write_all(body.as_bytes())
+}
@@ -21,12 +21,18 @@
  * Author: Zeeshan Ali <zeeshanak@gnome.org>
  */
 
-use crate::gps;
+use crate::broadcast::Subscription;
+use crate::clients::Registration;
+use crate::feed::{Event, Feed, Request};
 use std::io;
-use std::io::Write;
-use std::net::TcpStream;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
 use std::os::unix::net::UnixStream;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::thread;
+
+// Commands are NMEA sentences, which are way shorter than this.
+const MAX_COMMAND_LENGTH: usize = 1024;
 
 pub enum Stream {
     Tcp(TcpStream),
@@ -34,82 +40,132 @@ pub enum Stream {
 }
 
 impl Stream {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.write_all(buf),
+            Stream::Unix(s) => s.write_all(buf),
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self {
-            Stream::Tcp(s) => s.write(buf),
-            Stream::Unix(s) => s.write(buf),
+            Stream::Tcp(s) => s.read(buf),
+            Stream::Unix(s) => s.read(buf),
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Tcp(s) => s.try_clone().map(Stream::Tcp),
+            Stream::Unix(s) => s.try_clone().map(Stream::Unix),
+        }
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.shutdown(Shutdown::Both),
+            Stream::Unix(s) => s.shutdown(Shutdown::Both),
         }
     }
 }
 
+/// Writes the NMEA stream to a single client.
+///
+/// Each client gets its own handler (and thread) so a stalled client only ever blocks itself.
 pub struct ClientHandler {
-    gps: Arc<Mutex<dyn gps::GPS>>,
-    streams: Arc<Mutex<Vec<Stream>>>,
+    stream: Stream,
+    subscription: Subscription<Event>,
+    feed: Arc<Feed>,
+    // Keeps the client listed for as long as we serve it.
+    _registration: Registration,
 }
 
 impl ClientHandler {
-    pub fn new(gps: Arc<Mutex<dyn gps::GPS>>, streams: Arc<Mutex<Vec<Stream>>>) -> Self {
+    pub fn new(
+        stream: Stream,
+        subscription: Subscription<Event>,
+        feed: Arc<Feed>,
+        registration: Registration,
+    ) -> Self {
         ClientHandler {
-            gps: gps,
-            streams: streams,
+            stream,
+            subscription,
+            feed,
+            _registration: registration,
         }
     }
 
     pub fn handle(mut self) {
-        let mut buffer = String::new();
-
-        loop {
-            // unwrap cause we don't want a poisoned lock:
-            // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
-            if let Err(e) = self.gps.lock().unwrap().read_line(&mut buffer) {
-                println!("Failed to read from serial port: {}", e);
-
-                continue;
+        match self.stream.try_clone() {
+            Ok(stream) => {
+                let feed = self.feed.clone();
+                let closer = self.subscription.closer();
+
+                thread::spawn(move || {
+                    forward_commands(stream, &feed);
+                    // The client is gone, so it goes from the list now rather than with the
+                    // next write failing.
+                    closer.close();
+                });
             }
+            Err(e) => println!("Failed to read from client: {}", e),
+        }
 
-            let to_delete = self.write_to_clients(&buffer);
-            buffer.clear();
+        while let Some(event) = self.subscription.recv() {
+            let line = match event {
+                Event::Sentence(line) => line,
+                Event::Report(_) | Event::Ack(_) | Event::Version(_) | Event::Rtcm(_) => continue,
+            };
 
-            // unwrap cause we don't want a poisoned lock:
-            // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
-            let mut streams = self.streams.lock().unwrap();
-            for i in to_delete.iter().rev() {
-                streams.remove(*i);
-            }
+            if let Err(e) = self.stream.write_all(line.as_bytes()) {
+                println!("Failed to write NMEA to client: {}", e);
 
-            if streams.len() == 0 {
                 break;
             }
         }
-    }
-
-    fn write_to_clients(&mut self, buffer: &String) -> Vec<usize> {
-        let mut to_delete: Vec<usize> = vec![];
-
-        // unwrap cause we don't want a poisoned lock:
-        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
-        let mut streams = self.streams.lock().unwrap();
-        for i in 0..streams.len() {
-            let stream = &mut streams[i];
-
-            match stream.write(buffer.as_bytes()) {
-                Ok(0) => {
-                    to_delete.push(i);
-
-                    continue;
-                }
 
-                Ok(_) => {}
+        // The clone reading commands would otherwise keep the connection open.
+        let _ = self.stream.shutdown();
+    }
+}
 
-                Err(e) => {
-                    println!("Failed to write NMEA to client: {}", e);
-                    to_delete.push(i);
+// Passes the lines the client sends on to the device, if that's allowed, until the client goes
+// away.
+fn forward_commands(mut stream: Stream, feed: &Feed) {
+    let mut buffer = [0; 1024];
+    let mut pending = vec![];
+
+    loop {
+        let len = match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(len) => len,
+        };
+        if !feed.accepts_commands() {
+            continue;
+        }
+        pending.extend_from_slice(&buffer[..len]);
 
-                    continue;
-                }
+        while let Some(end) = line_end(&pending) {
+            let line: Vec<u8> = pending.drain(..end).collect();
+            if let Err(e) = feed.request(Request::Write(line)) {
+                println!("Failed to pass on command from client: {}", e);
             }
         }
+        if pending.len() > MAX_COMMAND_LENGTH {
+            println!("Dropping overlong command from client");
+            pending.clear();
+        }
+    }
+}
+
+// Returns the length of the first line in `data`, including its CR, LF or CRLF terminator. A CR
+// at the very end may be followed by a LF yet to come.
+fn line_end(data: &[u8]) -> Option<usize> {
+    let end = data.iter().position(|b| *b == b'\r' || *b == b'\n')?;
 
-        to_delete
+    match (data[end], data.get(end + 1)) {
+        (b'\r', Some(b'\n')) => Some(end + 2),
+        (b'\r', None) => None,
+        _ => Some(end + 1),
     }
 }
@@ -0,0 +1,131 @@
+/* vim: set et ts=4 sw=4: */
+/* clients.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+use crate::client_handler::Stream;
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// A connected client, as listed to the outside world.
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
+    pub id: u32,
+    /// The protocol the client speaks: `nmea`, `gpsd` or `ntrip`.
+    pub protocol: &'static str,
+    pub address: String,
+}
+
+struct Client {
+    info: ClientInfo,
+    stream: Stream,
+}
+
+#[derive(Default)]
+struct State {
+    clients: Vec<Client>,
+    next_id: u32,
+}
+
+/// Keeps track of all connected clients, whatever they connected through.
+#[derive(Default)]
+pub struct Clients {
+    state: Mutex<State>,
+}
+
+impl Clients {
+    /// Lists a new client, until the returned `Registration` is dropped.
+    pub fn add(
+        self: &Arc<Self>,
+        protocol: &'static str,
+        address: String,
+        stream: &Stream,
+    ) -> io::Result<Registration> {
+        let stream = stream.try_clone()?;
+
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id = state.next_id.wrapping_add(1);
+        state.clients.push(Client {
+            info: ClientInfo {
+                id,
+                protocol,
+                address,
+            },
+            stream,
+        });
+
+        Ok(Registration {
+            clients: self.clone(),
+            id,
+        })
+    }
+
+    pub fn list(&self) -> Vec<ClientInfo> {
+        let state = self.state.lock().unwrap();
+
+        state.clients.iter().map(|c| c.info.clone()).collect()
+    }
+
+    pub fn count(&self) -> usize {
+        self.state.lock().unwrap().clients.len()
+    }
+
+    /// Disconnects the client with the given `id`, returning `false` if there is no such client.
+    ///
+    /// The client is shut down right away, and goes from the list once its handler notices.
+    pub fn disconnect(&self, id: u32) -> bool {
+        let state = self.state.lock().unwrap();
+        let client = match state.clients.iter().find(|c| c.info.id == id) {
+            Some(client) => client,
+            None => return false,
+        };
+
+        if let Err(e) = client.stream.shutdown() {
+            println!("Failed to disconnect client {}: {}", id, e);
+        }
+        println!("Disconnected client {} ({})", id, client.info.address);
+
+        true
+    }
+
+    fn remove(&self, id: u32) {
+        self.state
+            .lock()
+            .unwrap()
+            .clients
+            .retain(|c| c.info.id != id);
+    }
+}
+
+pub struct Registration {
+    clients: Arc<Clients>,
+    id: u32,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.clients.remove(self.id);
+    }
+}
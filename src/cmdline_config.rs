@@ -21,27 +21,77 @@
  * Author: Zeeshan Ali <zeeshanak@gnome.org>
  */
 
+use crate::allowlist::{UdevProperty, UsbId};
+use crate::broadcast::OverflowPolicy;
 use crate::config::Config;
-use clap::{Arg, ArgAction, Command, value_parser};
+use crate::dbus::Bus;
+use crate::file_config::{self, FileConfig};
+use crate::filter::ChecksumPolicy;
+use crate::ntrip::{Mountpoint, Version};
+use crate::receiver::{self, Constellation, Settings, Vendor};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
+use std::path::{Path, PathBuf};
 
 pub fn config_from_cmdline() -> Config {
-    let matches = Command::new("GPS Share")
+    match config_from_matches(&command().get_matches()) {
+        Ok(config) => config,
+
+        Err(e) => {
+            println!("{}", e);
+
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads the configuration again, for the same command line.
+pub fn reload_config() -> Result<Config, String> {
+    config_from_matches(&command().get_matches())
+}
+
+fn command() -> Command {
+    Command::new("GPS Share")
         .version(env!("CARGO_PKG_VERSION"))
         .author("Zeeshan Ali <zeeshanak@gnome.org>")
         .about("Utility to share your GPS device on local network.")
         .arg(
             Arg::new("device")
-                .help("GPS device node")
+                .help(
+                    "GPS device node, - for standard input, udp://[ADDRESS][:PORT] to listen for \
+                     NMEA datagrams, gpsd://[HOST][:PORT][/json] to read from gpsd, \
+                     modemmanager://[MODEM] for the GNSS of a modem, or a host:port or Unix \
+                     socket to read NMEA from",
+                )
                 .required(false)
-                .value_parser(value_parser!(std::path::PathBuf)),
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .help(
+                    "Configuration file to read options from (default: /etc/gps-share.toml, \
+                     if it exists)",
+                )
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf)),
         )
         .arg(
             Arg::new("disable-announce")
                 .short('a')
                 .long("disable-announce")
                 .action(ArgAction::SetTrue)
+                .overrides_with("announce")
                 .help("Disable announcing through Avahi"),
         )
+        .arg(
+            Arg::new("announce")
+                .long("announce")
+                .action(ArgAction::SetTrue)
+                .overrides_with("disable-announce")
+                .help("Announce through Avahi, even if the configuration file disables it"),
+        )
         .arg(
             Arg::new("port")
                 .short('p')
@@ -63,8 +113,16 @@ pub fn config_from_cmdline() -> Config {
                 .short('x')
                 .long("no-tcp")
                 .action(ArgAction::SetTrue)
+                .overrides_with("tcp")
                 .help("Don't share over TCP"),
         )
+        .arg(
+            Arg::new("tcp")
+                .long("tcp")
+                .action(ArgAction::SetTrue)
+                .overrides_with("no-tcp")
+                .help("Share over TCP, even if the configuration file says not to"),
+        )
         .arg(
             Arg::new("socket")
                 .short('s')
@@ -81,17 +139,293 @@ pub fn config_from_cmdline() -> Config {
                 .default_value("38400")
                 .value_parser(value_parser!(u32)),
         )
-        .get_matches();
+        .arg(
+            Arg::new("usb-drivers")
+                .long("usb-drivers")
+                .help("More USB serial drivers to autodetect GPS devices by")
+                .value_name("DRIVER,..")
+                .value_delimiter(',')
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("usb-ids")
+                .long("usb-ids")
+                .help("More USB vendor and product IDs to autodetect GPS devices by")
+                .value_name("VID:PID,..")
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(UsbId)),
+        )
+        .arg(
+            Arg::new("udev-properties")
+                .long("udev-properties")
+                .help("udev properties of USB serial devices to autodetect GPS devices by")
+                .value_name("KEY=VALUE,..")
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(UdevProperty)),
+        )
+        .arg(
+            Arg::new("discover-network")
+                .long("discover-network")
+                .action(ArgAction::SetTrue)
+                .overrides_with("no-discover-network")
+                .help("Failing any device, autodetect NMEA services advertised on the network"),
+        )
+        .arg(
+            Arg::new("no-discover-network")
+                .long("no-discover-network")
+                .action(ArgAction::SetTrue)
+                .overrides_with("discover-network")
+                .help(
+                    "Don't look for NMEA services on the network, even if the configuration file \
+                     says to",
+                ),
+        )
+        .arg(
+            Arg::new("receiver")
+                .long("receiver")
+                .help(
+                    "Configure the receiver through its vendor's protocol: ublox, mediatek or sirf",
+                )
+                .value_name("VENDOR")
+                .value_parser(value_parser!(Vendor)),
+        )
+        .arg(
+            Arg::new("update-rate")
+                .long("update-rate")
+                .help("Number of fixes per second for the receiver to make")
+                .value_name("HZ")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("sentences")
+                .long("sentences")
+                .help("NMEA sentence types for the receiver to send, turning off the others")
+                .value_name("TYPE,..")
+                .value_delimiter(',')
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("constellations")
+                .long("constellations")
+                .help("Satellite constellations for the receiver to use, turning off the others")
+                .value_name("NAME,..")
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(Constellation)),
+        )
+        .arg(
+            Arg::new("receiver-baudrate")
+                .long("receiver-baudrate")
+                .help("Baudrate for the receiver to switch to")
+                .value_name("BAUDRATE")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("ntrip")
+                .long("ntrip")
+                .help("NTRIP caster mountpoint to feed RTCM corrections into the GPS device from")
+                .value_name("ntrip://[USER:PASSWORD@]HOST[:PORT]/MOUNTPOINT")
+                .value_parser(value_parser!(Mountpoint)),
+        )
+        .arg(
+            Arg::new("ntrip-version")
+                .long("ntrip-version")
+                .help("Version of the NTRIP protocol to speak: 1 or 2")
+                .value_name("VERSION")
+                .default_value("2")
+                .value_parser(value_parser!(Version)),
+        )
+        .arg(
+            Arg::new("ntrip-gga-interval")
+                .long("ntrip-gga-interval")
+                .help("Seconds between positions sent to the NTRIP caster, for VRS (0: don't send)")
+                .value_name("SECONDS")
+                .default_value("10")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("queue-size")
+                .long("queue-size")
+                .help("Number of NMEA sentences to queue for each client")
+                .value_name("SENTENCES")
+                .default_value("128")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("overflow-policy")
+                .long("overflow-policy")
+                .help("What to do when a client's queue is full: drop-oldest or drop-client")
+                .value_name("POLICY")
+                .default_value("drop-oldest")
+                .value_parser(value_parser!(OverflowPolicy)),
+        )
+        .arg(
+            Arg::new("no-replay")
+                .long("no-replay")
+                .action(ArgAction::SetTrue)
+                .overrides_with("replay")
+                .help("Don't send new clients the last epoch of NMEA sentences"),
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .action(ArgAction::SetTrue)
+                .overrides_with("no-replay")
+                .help(
+                    "Send new clients the last epoch, even if the configuration file says not to",
+                ),
+        )
+        .arg(
+            Arg::new("no-nmea-synthesis")
+                .long("no-nmea-synthesis")
+                .action(ArgAction::SetTrue)
+                .overrides_with("nmea-synthesis")
+                .help("Don't make up NMEA sentences from what receivers send in binary protocols"),
+        )
+        .arg(
+            Arg::new("nmea-synthesis")
+                .long("nmea-synthesis")
+                .action(ArgAction::SetTrue)
+                .overrides_with("no-nmea-synthesis")
+                .help("Make up NMEA sentences, even if the configuration file says not to"),
+        )
+        .arg(
+            Arg::new("client-commands")
+                .long("client-commands")
+                .action(ArgAction::SetTrue)
+                .overrides_with("no-client-commands")
+                .help(
+                    "Pass what NMEA clients send on to the GPS device, e.g configuration commands",
+                ),
+        )
+        .arg(
+            Arg::new("no-client-commands")
+                .long("no-client-commands")
+                .action(ArgAction::SetTrue)
+                .overrides_with("client-commands")
+                .help(
+                    "Don't pass on what NMEA clients send, even if the configuration file says to",
+                ),
+        )
+        .arg(
+            Arg::new("bad-checksum")
+                .long("bad-checksum")
+                .help("What to do with sentences with a bad checksum: drop, forward or repair")
+                .value_name("POLICY")
+                .default_value("drop")
+                .value_parser(value_parser!(ChecksumPolicy)),
+        )
+        .arg(
+            Arg::new("gpsd-port")
+                .long("gpsd-port")
+                .help("Port to run gpsd-compatible JSON service on (default: don't run)")
+                .value_name("PORT")
+                .value_parser(value_parser!(u16)),
+        )
+        .arg(
+            Arg::new("caster-port")
+                .long("caster-port")
+                .help(
+                    "Port to serve the RTCM3 corrections of a base station on as an NTRIP caster \
+                     (default: don't)",
+                )
+                .value_name("PORT")
+                .value_parser(value_parser!(u16)),
+        )
+        .arg(
+            Arg::new("caster-mountpoint")
+                .long("caster-mountpoint")
+                .help("Mountpoint to serve the corrections under")
+                .value_name("NAME")
+                .default_value("gps-share"),
+        )
+        .arg(
+            Arg::new("dbus")
+                .long("dbus")
+                .help("Offer the D-Bus service on the session or system bus (default: don't)")
+                .value_name("BUS")
+                .value_parser(value_parser!(Bus)),
+        )
+}
 
-    let announce = !matches.get_flag("disable-announce");
-    let dev_path = matches.get_one::<std::path::PathBuf>("device").cloned();
-    let port = *matches.get_one::<u16>("port").expect("has a default");
-    let no_tcp = matches.get_flag("no-tcp");
-    let iface = matches.get_one::<String>("interface").cloned();
-    let socket_path = matches.get_one::<String>("socket").cloned();
-    let baudrate = *matches.get_one::<u32>("baudrate").expect("has a default");
+fn config_from_matches(matches: &ArgMatches) -> Result<Config, String> {
+    let file = match matches.get_one::<PathBuf>("config") {
+        Some(path) => load_file(path)?,
+        None if Path::new(file_config::SYSTEM_PATH).exists() => {
+            load_file(Path::new(file_config::SYSTEM_PATH))?
+        }
+        None => FileConfig::default(),
+    };
+
+    let announce = !flag(
+        matches,
+        "disable-announce",
+        "announce",
+        file.disable_announce,
+    );
+    let dev_path = value(matches, "device", file.device);
+    let port = value(matches, "port", file.port).expect("has a default");
+    let no_tcp = flag(matches, "no-tcp", "tcp", file.no_tcp);
+    let iface = value(matches, "interface", file.network_interface);
+    let socket_path = value(matches, "socket", file.socket_path);
+    let baudrate = value(matches, "baudrate", file.baudrate).expect("has a default");
+    let usb_drivers = values(matches, "usb-drivers", file.usb_drivers);
+    let usb_ids = values(matches, "usb-ids", file.usb_ids);
+    let udev_properties = values(matches, "udev-properties", file.udev_properties);
+    let discover_network = flag(
+        matches,
+        "discover-network",
+        "no-discover-network",
+        file.discover_network,
+    );
+    let receiver = value(matches, "receiver", file.receiver);
+    let receiver_settings = Settings {
+        update_rate: value(matches, "update-rate", file.update_rate),
+        sentences: optional_values(matches, "sentences", file.sentences),
+        constellations: optional_values(matches, "constellations", file.constellations),
+        baudrate: value(matches, "receiver-baudrate", file.receiver_baudrate),
+    };
+    match receiver {
+        Some(vendor) => receiver::check(vendor, &receiver_settings)
+            .map_err(|e| format!("Can't configure {} receivers: {}", vendor, e))?,
+        None if !receiver_settings.is_empty() => {
+            return Err("Configuring the receiver needs --receiver".to_string());
+        }
+        None => {}
+    }
+    let ntrip = value(matches, "ntrip", file.ntrip);
+    let ntrip_version = value(matches, "ntrip-version", file.ntrip_version).expect("has a default");
+    let ntrip_gga_interval =
+        value(matches, "ntrip-gga-interval", file.ntrip_gga_interval).expect("has a default");
+    let queue_size = value(matches, "queue-size", file.queue_size).expect("has a default");
+    let overflow_policy =
+        value(matches, "overflow-policy", file.overflow_policy).expect("has a default");
+    let replay = !flag(matches, "no-replay", "replay", file.no_replay);
+    let synthesize_nmea = !flag(
+        matches,
+        "no-nmea-synthesis",
+        "nmea-synthesis",
+        file.no_nmea_synthesis,
+    );
+    let client_commands = flag(
+        matches,
+        "client-commands",
+        "no-client-commands",
+        file.client_commands,
+    );
+    let checksum_policy = value(matches, "bad-checksum", file.bad_checksum).expect("has a default");
+    let gpsd_port = value(matches, "gpsd-port", file.gpsd_port);
+    let caster_port = value(matches, "caster-port", file.caster_port);
+    let caster_mountpoint =
+        value(matches, "caster-mountpoint", file.caster_mountpoint).expect("has a default");
+    if caster_mountpoint.is_empty() || caster_mountpoint.contains(['/', ' ']) {
+        return Err(format!("Invalid mountpoint: {}", caster_mountpoint));
+    }
+    let dbus = value(matches, "dbus", file.dbus);
 
-    Config {
+    Ok(Config {
         dev_path,
         announce_on_net: announce,
         port,
@@ -99,5 +433,88 @@ pub fn config_from_cmdline() -> Config {
         no_tcp,
         socket_path,
         baudrate,
+        usb_drivers,
+        usb_ids,
+        udev_properties,
+        discover_network,
+        receiver,
+        receiver_settings,
+        ntrip,
+        ntrip_version,
+        ntrip_gga_interval,
+        queue_size,
+        overflow_policy,
+        replay,
+        synthesize_nmea,
+        client_commands,
+        checksum_policy,
+        gpsd_port,
+        caster_port,
+        caster_mountpoint,
+        dbus,
+    })
+}
+
+fn load_file(path: &Path) -> Result<FileConfig, String> {
+    FileConfig::load(path).map_err(|e| {
+        format!(
+            "Failed to read configuration file {}: {}",
+            path.display(),
+            e
+        )
+    })
+}
+
+// Values given on the command line override those from the configuration file, which override
+// the defaults.
+fn value<T>(matches: &ArgMatches, id: &str, file_value: Option<T>) -> Option<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    if matches.value_source(id) == Some(ValueSource::CommandLine) {
+        return matches.get_one::<T>(id).cloned();
+    }
+
+    file_value.or_else(|| matches.get_one::<T>(id).cloned())
+}
+
+// Like `value`, for options that take a list.
+fn values<T>(matches: &ArgMatches, id: &str, file_value: Option<Vec<T>>) -> Vec<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    if matches.value_source(id) == Some(ValueSource::CommandLine) {
+        return matches
+            .get_many::<T>(id)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+    }
+
+    file_value.unwrap_or_default()
+}
+
+// Like `values`, for lists where being left out isn't the same as being empty.
+fn optional_values<T>(matches: &ArgMatches, id: &str, file_value: Option<Vec<T>>) -> Option<Vec<T>>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    if matches.value_source(id) == Some(ValueSource::CommandLine) {
+        return Some(values(matches, id, None));
     }
+
+    file_value
+}
+
+// The flag `id` or its `negation` on the command line, whichever came last, or else the file.
+fn flag(matches: &ArgMatches, id: &str, negation: &str, file_value: Option<bool>) -> bool {
+    if matches.get_flag(id) {
+        return true;
+    }
+    if matches.get_flag(negation) {
+        return false;
+    }
+
+    file_value.unwrap_or(false)
 }
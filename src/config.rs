@@ -21,6 +21,12 @@
  * Author: Zeeshan Ali <zeeshanak@gnome.org>
  */
 
+use crate::allowlist::{UdevProperty, UsbId};
+use crate::broadcast::OverflowPolicy;
+use crate::dbus::Bus;
+use crate::filter::ChecksumPolicy;
+use crate::ntrip::{Mountpoint, Version};
+use crate::receiver::{Settings, Vendor};
 use std::ffi::CStr;
 use std::mem;
 use std::path::PathBuf;
@@ -34,6 +40,29 @@ pub struct Config {
     pub no_tcp: bool,
     pub socket_path: Option<String>,
     pub baudrate: u32,
+    // Added to the built-in allowlist for autodetection.
+    pub usb_drivers: Vec<String>,
+    pub usb_ids: Vec<UsbId>,
+    pub udev_properties: Vec<UdevProperty>,
+    pub discover_network: bool,
+    // What to configure receivers with, if anything.
+    pub receiver: Option<Vendor>,
+    pub receiver_settings: Settings,
+    // Where to get corrections for the receiver from, if anywhere.
+    pub ntrip: Option<Mountpoint>,
+    pub ntrip_version: Version,
+    pub ntrip_gga_interval: u64,
+    pub queue_size: usize,
+    pub overflow_policy: OverflowPolicy,
+    pub replay: bool,
+    pub synthesize_nmea: bool,
+    pub client_commands: bool,
+    pub checksum_policy: ChecksumPolicy,
+    pub gpsd_port: Option<u16>,
+    // Where to serve the source's RTCM3 frames to rovers, if anywhere.
+    pub caster_port: Option<u16>,
+    pub caster_mountpoint: String,
+    pub dbus: Option<Bus>,
 }
 
 impl Config {
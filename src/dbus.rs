@@ -0,0 +1,441 @@
+/* vim: set et ts=4 sw=4: */
+/* dbus.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+use crate::clients::Clients;
+use crate::feed::{Event, Feed};
+use crate::fix::{Mode, Report};
+use crate::gps;
+use crate::receiver::{self, Constellation, Settings, Vendor};
+use crate::reconnect::Reconnecting;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use zbus::blocking::{Connection, connection};
+use zbus::fdo;
+use zbus::interface;
+
+pub const NAME: &str = "org.freedesktop.GPSShare";
+pub const PATH: &str = "/org/freedesktop/GPSShare";
+pub const LOCATION_PATH: &str = "/org/freedesktop/GPSShare/Location";
+
+// Same as Geoclue, for values we don't know.
+const UNKNOWN_ALTITUDE: f64 = -f64::MAX;
+const UNKNOWN: f64 = -1.0;
+
+/// The message bus to offer our D-Bus service on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bus {
+    Session,
+    System,
+}
+
+impl FromStr for Bus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "session" => Ok(Bus::Session),
+            "system" => Ok(Bus::System),
+            _ => Err(format!(
+                "unknown bus `{}` (expected `session` or `system`)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bus::Session => f.write_str("session"),
+            Bus::System => f.write_str("system"),
+        }
+    }
+}
+
+/// The current location, modelled after Geoclue's `org.freedesktop.GeoClue2.Location`.
+struct Location {
+    latitude: f64,
+    longitude: f64,
+    accuracy: f64,
+    altitude: f64,
+    speed: f64,
+    heading: f64,
+    timestamp: (u64, u64),
+}
+
+impl Default for Location {
+    fn default() -> Self {
+        Location {
+            latitude: 0.0,
+            longitude: 0.0,
+            accuracy: f64::MAX,
+            altitude: UNKNOWN_ALTITUDE,
+            speed: UNKNOWN,
+            heading: UNKNOWN,
+            timestamp: (0, 0),
+        }
+    }
+}
+
+impl Location {
+    fn from_report(report: &Report) -> Option<Self> {
+        let fix = &report.fix;
+        if !fix.has_position() {
+            return None;
+        }
+
+        let time = fix.time.unwrap_or(0.0).max(0.0);
+        Some(Location {
+            latitude: fix.latitude?,
+            longitude: fix.longitude?,
+            accuracy: report.horizontal_accuracy().unwrap_or(f64::MAX),
+            altitude: fix.altitude.unwrap_or(UNKNOWN_ALTITUDE),
+            speed: fix.speed.unwrap_or(UNKNOWN),
+            heading: fix.track.unwrap_or(UNKNOWN),
+            timestamp: (time.trunc() as u64, (time.fract() * 1e6).round() as u64),
+        })
+    }
+}
+
+// `LocationUpdated` carries the whole location, like the `Location` object does.
+#[allow(clippy::too_many_arguments)]
+mod location {
+    use super::Location;
+    use zbus::interface;
+    use zbus::object_server::SignalEmitter;
+
+    #[interface(name = "org.freedesktop.GPSShare.Location")]
+    impl Location {
+        /// Latitude, in degrees.
+        #[zbus(property)]
+        fn latitude(&self) -> f64 {
+            self.latitude
+        }
+
+        /// Longitude, in degrees.
+        #[zbus(property)]
+        fn longitude(&self) -> f64 {
+            self.longitude
+        }
+
+        /// Horizontal accuracy, in meters.
+        #[zbus(property)]
+        fn accuracy(&self) -> f64 {
+            self.accuracy
+        }
+
+        /// Altitude above mean sea level, in meters, or `-DBL_MAX` if unknown.
+        #[zbus(property)]
+        fn altitude(&self) -> f64 {
+            self.altitude
+        }
+
+        /// Speed, in meters per second, or -1 if unknown.
+        #[zbus(property)]
+        fn speed(&self) -> f64 {
+            self.speed
+        }
+
+        /// Heading, in degrees from true north, or -1 if unknown.
+        #[zbus(property)]
+        fn heading(&self) -> f64 {
+            self.heading
+        }
+
+        #[zbus(property)]
+        fn description(&self) -> &str {
+            "GPS"
+        }
+
+        /// Time of the fix, in seconds and microseconds since the Unix epoch.
+        #[zbus(property)]
+        fn timestamp(&self) -> (u64, u64) {
+            self.timestamp
+        }
+
+        #[zbus(signal)]
+        pub(super) async fn location_updated(
+            emitter: &SignalEmitter<'_>,
+            latitude: f64,
+            longitude: f64,
+            accuracy: f64,
+            altitude: f64,
+            speed: f64,
+            heading: f64,
+            timestamp: (u64, u64),
+        ) -> zbus::Result<()>;
+    }
+}
+
+/// Status and control of the daemon itself.
+struct Control {
+    feed: Arc<Feed>,
+    clients: Arc<Clients>,
+    // The baudrate to open devices we switch to at.
+    baudrate: u32,
+}
+
+// The values of these properties change all the time, so they don't get change notifications.
+#[interface(name = "org.freedesktop.GPSShare")]
+impl Control {
+    /// The device node being read from, or an empty string if there is none (e.g stdin).
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn device_path(&self) -> String {
+        self.feed
+            .device()
+            .and_then(|d| d.path)
+            .map(|p| p.display().to_string())
+            .unwrap_or_default()
+    }
+
+    /// The kind of source: `RS232`, `GNSS`, `stdin` or `none` while waiting for a device.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn source_kind(&self) -> String {
+        self.feed
+            .device()
+            .map(|d| d.kind.to_string())
+            .unwrap_or_default()
+    }
+
+    /// The baudrate the device is read at, or 0 if that doesn't apply.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn baudrate(&self) -> u32 {
+        self.feed.device().and_then(|d| d.baudrate).unwrap_or(0)
+    }
+
+    /// The number of connected clients.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn clients(&self) -> u32 {
+        self.clients.count() as u32
+    }
+
+    /// NMEA sentences per second, averaged over the last few seconds.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn sentence_rate(&self) -> f64 {
+        self.feed.sentence_rate()
+    }
+
+    /// The number of sentences dropped for failing checksum validation.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn dropped_sentences(&self) -> u64 {
+        self.feed.checksum_counters().dropped
+    }
+
+    /// The number of sentences passed on despite failing checksum validation.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn forwarded_sentences(&self) -> u64 {
+        self.feed.checksum_counters().forwarded
+    }
+
+    /// The number of sentences passed on with a repaired checksum.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn repaired_sentences(&self) -> u64 {
+        self.feed.checksum_counters().repaired
+    }
+
+    /// One of `unknown`, `no-fix`, `2d` or `3d`.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn fix_state(&self) -> String {
+        let mode = match self.feed.latest_report() {
+            Some(report) if report.fix.has_position() && report.fix.mode == Mode::Unknown => {
+                Mode::Fix2D
+            }
+            Some(report) => report.fix.mode,
+            None => Mode::Unknown,
+        };
+
+        match mode {
+            Mode::Unknown => "unknown",
+            Mode::NoFix => "no-fix",
+            Mode::Fix2D => "2d",
+            Mode::Fix3D => "3d",
+        }
+        .to_string()
+    }
+
+    /// Lists the connected clients as (id, protocol, address) triplets.
+    fn list_clients(&self) -> Vec<(u32, String, String)> {
+        self.clients
+            .list()
+            .into_iter()
+            .map(|c| (c.id, c.protocol.to_string(), c.address))
+            .collect()
+    }
+
+    fn disconnect_client(&self, id: u32) -> fdo::Result<()> {
+        if self.clients.disconnect(id) {
+            Ok(())
+        } else {
+            Err(fdo::Error::InvalidArgs(format!("No client with id {}", id)))
+        }
+    }
+
+    /// Switches to the device at `path`, `-` being standard input.
+    async fn switch_device(&self, path: String) -> fdo::Result<()> {
+        let path = PathBuf::from(path);
+        let feed = self.feed.clone();
+        let baudrate = self.baudrate;
+
+        // Opening a device can take seconds, e.g to find its baudrate.
+        blocking::unblock(move || {
+            let gps = gps::open(&path, baudrate).map_err(|e| {
+                fdo::Error::Failed(format!("Failed to open {}: {}", path.display(), e))
+            })?;
+            if gps.kind() == "stdin" {
+                feed.switch(gps);
+            } else {
+                let reconnecting = Reconnecting::new(gps, move || gps::open(&path, baudrate));
+                feed.switch(Box::new(reconnecting));
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Configures the receiver through the protocol of `vendor` (`ublox`, `mediatek` or `sirf`),
+    /// returning once it acknowledged each setting. Zero or empty arguments leave their setting
+    /// alone.
+    ///
+    /// Unlike the configuration, this only applies until the receiver is reset.
+    async fn configure_receiver(
+        &self,
+        vendor: &str,
+        update_rate: u32,
+        sentences: Vec<String>,
+        constellations: Vec<String>,
+        baudrate: u32,
+    ) -> fdo::Result<()> {
+        let vendor: Vendor = vendor.parse().map_err(fdo::Error::InvalidArgs)?;
+        let constellations = constellations
+            .iter()
+            .map(|c| c.parse())
+            .collect::<Result<Vec<Constellation>, _>>()
+            .map_err(fdo::Error::InvalidArgs)?;
+        let settings = Settings {
+            update_rate: (update_rate != 0).then_some(update_rate),
+            sentences: (!sentences.is_empty()).then_some(sentences),
+            constellations: (!constellations.is_empty()).then_some(constellations),
+            baudrate: (baudrate != 0).then_some(baudrate),
+        };
+        receiver::check(vendor, &settings).map_err(fdo::Error::InvalidArgs)?;
+
+        let feed = self.feed.clone();
+        blocking::unblock(move || receiver::configure(&feed, vendor, &settings))
+            .await
+            .map_err(fdo::Error::Failed)
+    }
+}
+
+pub struct DBus {
+    connection: Connection,
+    feed: Arc<Feed>,
+    bus: Bus,
+}
+
+impl DBus {
+    pub fn new(
+        bus: Bus,
+        feed: Arc<Feed>,
+        clients: Arc<Clients>,
+        baudrate: u32,
+    ) -> Result<Self, zbus::Error> {
+        let builder = match bus {
+            Bus::Session => connection::Builder::session()?,
+            Bus::System => connection::Builder::system()?,
+        };
+        let control = Control {
+            feed: feed.clone(),
+            clients,
+            baudrate,
+        };
+        let connection = builder
+            .name(NAME)?
+            .serve_at(PATH, control)?
+            .serve_at(LOCATION_PATH, Location::default())?
+            .build()?;
+
+        Ok(DBus {
+            connection,
+            feed,
+            bus,
+        })
+    }
+
+    pub fn bus(&self) -> Bus {
+        self.bus
+    }
+
+    /// Keeps the location up to date with the reports from the feed.
+    pub fn run(&self) {
+        let subscription = self.feed.subscribe_live(Event::is_report);
+
+        while let Some(event) = subscription.recv() {
+            let location = match event {
+                Event::Report(report) => match Location::from_report(&report) {
+                    Some(location) => location,
+                    None => continue,
+                },
+                Event::Sentence(_) | Event::Ack(_) | Event::Version(_) | Event::Rtcm(_) => continue,
+            };
+
+            if let Err(e) = self.update_location(location) {
+                println!("Failed to update location on D-Bus: {}", e);
+            }
+        }
+    }
+
+    fn update_location(&self, location: Location) -> Result<(), zbus::Error> {
+        let iface = self
+            .connection
+            .object_server()
+            .interface::<_, Location>(LOCATION_PATH)?;
+        let emitter = iface.signal_emitter();
+
+        let mut current = iface.get_mut();
+        *current = location;
+        zbus::block_on(async {
+            current.latitude_changed(emitter).await?;
+            current.longitude_changed(emitter).await?;
+            current.accuracy_changed(emitter).await?;
+            current.altitude_changed(emitter).await?;
+            current.speed_changed(emitter).await?;
+            current.heading_changed(emitter).await?;
+            current.timestamp_changed(emitter).await?;
+
+            Location::location_updated(
+                emitter,
+                current.latitude,
+                current.longitude,
+                current.accuracy,
+                current.altitude,
+                current.speed,
+                current.heading,
+                current.timestamp,
+            )
+            .await
+        })
+    }
+}
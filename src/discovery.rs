@@ -0,0 +1,564 @@
+/* vim: set et ts=4 sw=4: */
+/* discovery.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Finding GPS devices to read from.
+//!
+//! Each kind of device has a `Prober` that knows how to find and open them, and the `Registry`
+//! asks them in turn, highest priority first.
+
+use crate::allowlist::{self, Allowlist};
+use crate::avahi;
+use crate::gnss::GNSS;
+use crate::gps::GPS;
+use crate::gpsd_client::{self, GpsdClient};
+use crate::modem_manager::{self, ModemManager};
+use crate::network;
+use crate::rs232::RS232;
+use crate::stdin_gps::StdinGPS;
+use crate::udp;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Finds and opens GPS devices of one kind.
+pub trait Prober: Send {
+    /// What we call the devices it finds, e.g `USB serial`.
+    fn name(&self) -> &'static str;
+
+    /// Probers with a higher priority are asked first.
+    fn priority(&self) -> i32;
+
+    /// The udev subsystem its devices belong to, if they show up in udev at all.
+    fn subsystem(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Whether it looks for devices, rather than only opening those it's given.
+    fn detects(&self) -> bool {
+        self.subsystem().is_some()
+    }
+
+    /// Opens `path` if it names one of ours, e.g `udp://`.
+    fn open(&self, _path: &Path, _baudrate: u32) -> Option<io::Result<Box<dyn GPS>>> {
+        None
+    }
+
+    /// Opens `device` if it's one of ours and it talks like a GPS receiver.
+    fn probe(&self, _device: &udev::Device, _baudrate: u32) -> Option<Box<dyn GPS>> {
+        None
+    }
+
+    /// Looks for a GPS device among the ones around.
+    fn scan(&self, baudrate: u32) -> io::Result<Option<Box<dyn GPS>>> {
+        let subsystem = match self.subsystem() {
+            Some(subsystem) => subsystem,
+            None => return Ok(None),
+        };
+
+        let mut enumerator = udev::Enumerator::new()?;
+        enumerator.match_subsystem(subsystem)?;
+        for d in enumerator.scan_devices()? {
+            if !in_dev_dir(&d) {
+                continue;
+            }
+
+            if let Some(gps) = self.probe(&d, baudrate) {
+                return Ok(Some(gps));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// The probers we know of.
+pub struct Registry {
+    probers: Vec<Box<dyn Prober>>,
+}
+
+impl Registry {
+    /// A registry with the probers for all the devices we know of.
+    /// NMEA services on the network are only looked for with `discover_network`.
+    pub fn builtin(allowlist: Allowlist, discover_network: bool) -> Self {
+        let mut registry = Registry::sources();
+        registry.register(Box::new(Marked));
+        registry.register(Box::new(UsbSerial { allowlist }));
+        registry.register(Box::new(KernelGnss));
+        registry.register(Box::new(Bluetooth));
+        registry.register(Box::new(PseudoTerminal));
+        if discover_network {
+            registry.register(Box::new(NetworkService));
+        }
+
+        registry
+    }
+
+    /// A registry with the probers for all the kinds of source that can be given by path.
+    pub fn sources() -> Self {
+        let mut registry = Registry::new();
+        registry.register(Box::new(Stdin));
+        registry.register(Box::new(Udp));
+        registry.register(Box::new(Gpsd));
+        registry.register(Box::new(CellularModem));
+        registry.register(Box::new(Network));
+        registry.register(Box::new(DeviceNode));
+
+        registry
+    }
+
+    /// A registry without any probers.
+    pub fn new() -> Self {
+        Registry { probers: vec![] }
+    }
+
+    pub fn register(&mut self, prober: Box<dyn Prober>) {
+        // Those registered first go first among equals.
+        let index = self
+            .probers
+            .partition_point(|p| p.priority() >= prober.priority());
+        self.probers.insert(index, prober);
+    }
+
+    /// The udev subsystems to watch for devices coming and going.
+    pub fn subsystems(&self) -> Vec<&'static str> {
+        let mut subsystems: Vec<_> = self.probers.iter().filter_map(|p| p.subsystem()).collect();
+        subsystems.sort_unstable();
+        subsystems.dedup();
+
+        subsystems
+    }
+
+    /// Opens the device at `path` with the first prober that knows what it is.
+    pub fn open(&self, path: &Path, baudrate: u32) -> io::Result<Box<dyn GPS>> {
+        self.probers
+            .iter()
+            .find_map(|p| p.open(path, baudrate))
+            .unwrap_or_else(|| {
+                Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Don't know how to open {}", path.display()),
+                ))
+            })
+    }
+
+    /// Looks for a GPS device, asking each prober in turn.
+    pub fn detect(&self, baudrate: u32) -> io::Result<Box<dyn GPS>> {
+        for prober in self.probers.iter().filter(|p| p.detects()) {
+            println!("Looking for a GPS device ({})...", prober.name());
+
+            if let Some(gps) = prober.scan(baudrate)? {
+                return Ok(gps);
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Failed to autodetect GPS device",
+        ))
+    }
+
+    /// Opens `device` if one of the probers recognizes it as a GPS device.
+    pub fn probe(&self, device: &udev::Device, baudrate: u32) -> Option<Box<dyn GPS>> {
+        if !in_dev_dir(device) {
+            return None;
+        }
+        let subsystem = device.subsystem();
+
+        self.probers
+            .iter()
+            .filter(|p| p.subsystem().is_some_and(|s| subsystem == Some(s.as_ref())))
+            .find_map(|p| p.probe(device, baudrate))
+    }
+}
+
+// Where device nodes are looked for. The tests point `GPS_SHARE_DEV_DIR` at a directory of their
+// own, so that the devices of the machine they run on don't get in the way.
+fn dev_dir() -> PathBuf {
+    env::var_os("GPS_SHARE_DEV_DIR").map_or_else(|| PathBuf::from("/dev"), PathBuf::from)
+}
+
+fn in_dev_dir(device: &udev::Device) -> bool {
+    device
+        .devnode()
+        .is_some_and(|devnode| devnode.starts_with(dev_dir()))
+}
+
+/// Serial devices marked as GPS devices in udev, whatever they are.
+struct Marked;
+
+impl Prober for Marked {
+    fn name(&self) -> &'static str {
+        "marked"
+    }
+
+    fn priority(&self) -> i32 {
+        50
+    }
+
+    fn subsystem(&self) -> Option<&'static str> {
+        Some("tty")
+    }
+
+    fn probe(&self, device: &udev::Device, baudrate: u32) -> Option<Box<dyn GPS>> {
+        if !allowlist::is_forced(device) {
+            return None;
+        }
+
+        open_serial(device.devnode()?, baudrate)
+    }
+}
+
+/// USB serial adapters and receivers showing up as a USB modem, that are on the allowlist.
+struct UsbSerial {
+    allowlist: Allowlist,
+}
+
+impl Prober for UsbSerial {
+    fn name(&self) -> &'static str {
+        "USB serial"
+    }
+
+    fn priority(&self) -> i32 {
+        40
+    }
+
+    fn subsystem(&self) -> Option<&'static str> {
+        Some("tty")
+    }
+
+    fn probe(&self, device: &udev::Device, baudrate: u32) -> Option<Box<dyn GPS>> {
+        if device
+            .property_value("ID_BUS")
+            .is_none_or(|bus| bus != "usb")
+        {
+            return None;
+        }
+
+        if !self.allowlist.matches(device) {
+            return None;
+        }
+
+        open_serial(device.devnode()?, baudrate)
+    }
+}
+
+/// Devices of the kernel's GNSS subsystem.
+struct KernelGnss;
+
+impl Prober for KernelGnss {
+    fn name(&self) -> &'static str {
+        "kernel GNSS"
+    }
+
+    fn priority(&self) -> i32 {
+        30
+    }
+
+    fn subsystem(&self) -> Option<&'static str> {
+        Some("gnss")
+    }
+
+    fn probe(&self, device: &udev::Device, _baudrate: u32) -> Option<Box<dyn GPS>> {
+        let path = device.devnode()?;
+
+        match GNSS::new_for_path(path) {
+            Ok(mut gps) => {
+                if gps.verify() {
+                    println!("Detected {} as a GPS device", path.display());
+
+                    return Some(Box::new(gps));
+                }
+            }
+
+            Err(e) => println!("Error openning {}: {}", path.display(), e),
+        }
+
+        None
+    }
+}
+
+/// Bluetooth receivers bound to a serial port with `rfcomm`.
+struct Bluetooth;
+
+impl Prober for Bluetooth {
+    fn name(&self) -> &'static str {
+        "Bluetooth"
+    }
+
+    fn priority(&self) -> i32 {
+        20
+    }
+
+    fn subsystem(&self) -> Option<&'static str> {
+        Some("tty")
+    }
+
+    fn probe(&self, device: &udev::Device, baudrate: u32) -> Option<Box<dyn GPS>> {
+        if !device.sysname().to_string_lossy().starts_with("rfcomm") {
+            return None;
+        }
+
+        open_serial(device.devnode()?, baudrate)
+    }
+}
+
+/// Pseudo-terminals standing in for a receiver, e.g from `socat` or `gpsfake`, linked to as
+/// `/dev/gps*`. Terminals in general are left alone, as they're most likely not GPS devices.
+struct PseudoTerminal;
+
+impl Prober for PseudoTerminal {
+    fn name(&self) -> &'static str {
+        "pseudo-terminal"
+    }
+
+    fn priority(&self) -> i32 {
+        10
+    }
+
+    fn detects(&self) -> bool {
+        true
+    }
+
+    fn scan(&self, baudrate: u32) -> io::Result<Option<Box<dyn GPS>>> {
+        let mut links: Vec<_> = fs::read_dir(dev_dir())?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("gps"))
+            })
+            .collect();
+        links.sort();
+
+        for link in links {
+            let is_pty = fs::canonicalize(&link).is_ok_and(|target| target.starts_with("/dev/pts"));
+            if !is_pty {
+                continue;
+            }
+
+            if let Some(gps) = open_serial(&link, baudrate) {
+                return Ok(Some(gps));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// NMEA sources on the network, given as a Unix socket or `host:port`.
+struct Network;
+
+impl Prober for Network {
+    fn name(&self) -> &'static str {
+        "network"
+    }
+
+    fn priority(&self) -> i32 {
+        5
+    }
+
+    fn open(&self, path: &Path, _baudrate: u32) -> Option<io::Result<Box<dyn GPS>>> {
+        if !network::is_network(path) {
+            return None;
+        }
+
+        Some(network::Network::connect(path).map(|gps| Box::new(gps) as Box<dyn GPS>))
+    }
+}
+
+/// NMEA services other machines advertise through Avahi, other than gps-share's.
+struct NetworkService;
+
+impl Prober for NetworkService {
+    fn name(&self) -> &'static str {
+        "network"
+    }
+
+    fn priority(&self) -> i32 {
+        5
+    }
+
+    fn detects(&self) -> bool {
+        true
+    }
+
+    fn scan(&self, _baudrate: u32) -> io::Result<Option<Box<dyn GPS>>> {
+        let addresses = match avahi::find_nmea_services() {
+            Ok(addresses) => addresses,
+
+            // Not having Avahi around is no reason not to look for other devices.
+            Err(e) => {
+                println!("Failed to look for NMEA services: {}", e);
+
+                return Ok(None);
+            }
+        };
+
+        for address in addresses {
+            match network::Network::connect(Path::new(&address)) {
+                Ok(gps) => {
+                    println!("Found NMEA service at {}", address);
+
+                    return Ok(Some(Box::new(gps)));
+                }
+
+                Err(e) => println!("Error connecting to {}: {}", address, e),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Standard input, given as `-`.
+struct Stdin;
+
+impl Prober for Stdin {
+    fn name(&self) -> &'static str {
+        "standard input"
+    }
+
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    fn open(&self, path: &Path, _baudrate: u32) -> Option<io::Result<Box<dyn GPS>>> {
+        (path.to_str() == Some("-")).then(|| Ok(Box::new(StdinGPS::new()) as Box<dyn GPS>))
+    }
+}
+
+/// NMEA datagrams, given as `udp://[ADDRESS][:PORT]`.
+struct Udp;
+
+impl Prober for Udp {
+    fn name(&self) -> &'static str {
+        "UDP"
+    }
+
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    fn open(&self, path: &Path, _baudrate: u32) -> Option<io::Result<Box<dyn GPS>>> {
+        udp::is_udp(path).then(|| udp::Udp::bind(path).map(|gps| Box::new(gps) as Box<dyn GPS>))
+    }
+}
+
+/// gpsd instances, given as `gpsd://[HOST][:PORT][/json]`.
+struct Gpsd;
+
+impl Prober for Gpsd {
+    fn name(&self) -> &'static str {
+        "gpsd"
+    }
+
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    fn open(&self, path: &Path, _baudrate: u32) -> Option<io::Result<Box<dyn GPS>>> {
+        gpsd_client::is_gpsd(path)
+            .then(|| GpsdClient::connect(path).map(|gps| Box::new(gps) as Box<dyn GPS>))
+    }
+}
+
+/// The GNSS of cellular modems, given as `modemmanager://[MODEM]`.
+struct CellularModem;
+
+impl Prober for CellularModem {
+    fn name(&self) -> &'static str {
+        "ModemManager"
+    }
+
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    fn open(&self, path: &Path, _baudrate: u32) -> Option<io::Result<Box<dyn GPS>>> {
+        modem_manager::is_modem_manager(path)
+            .then(|| ModemManager::connect(path).map(|gps| Box::new(gps) as Box<dyn GPS>))
+    }
+}
+
+/// Whatever else is given, as a serial or kernel GNSS device node.
+struct DeviceNode;
+
+impl Prober for DeviceNode {
+    fn name(&self) -> &'static str {
+        "device node"
+    }
+
+    // Anything goes here, so it's asked last.
+    fn priority(&self) -> i32 {
+        -10
+    }
+
+    fn open(&self, path: &Path, baudrate: u32) -> Option<io::Result<Box<dyn GPS>>> {
+        let gps: io::Result<Box<dyn GPS>> = match RS232::new_for_path(path, baudrate) {
+            Ok(rs232) => Ok(Box::new(rs232)),
+
+            // Kernel GNSS devices aren't TTYs, so they can't be set up as serial ports.
+            Err(e) if e.kind() != io::ErrorKind::NotFound => GNSS::new_for_path(path)
+                .map(|gnss| Box::new(gnss) as Box<dyn GPS>)
+                .map_err(|_| e),
+
+            Err(e) => Err(e),
+        };
+
+        Some(gps)
+    }
+}
+
+fn open_serial(path: &Path, baudrate: u32) -> Option<Box<dyn GPS>> {
+    let mut gps = match RS232::new_for_path(path, baudrate) {
+        Ok(gps) => gps,
+
+        Err(e) => {
+            println!("Error openning {}: {}", path.display(), e);
+
+            return None;
+        }
+    };
+
+    match gps.detect_baudrate() {
+        Ok(Some(baudrate)) => {
+            println!(
+                "Detected {} as a GPS device, at {} baud",
+                path.display(),
+                baudrate
+            );
+
+            Some(Box::new(gps))
+        }
+
+        Ok(None) => None,
+
+        Err(e) => {
+            println!("Error setting up {}: {}", path.display(), e);
+
+            None
+        }
+    }
+}
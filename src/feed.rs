@@ -0,0 +1,613 @@
+/* vim: set et ts=4 sw=4: */
+/* feed.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+use crate::broadcast::{Broadcaster, Subscription};
+use crate::config::Config;
+use crate::filter::{ChecksumFilter, Counters};
+use crate::fix::{Report, Tracker};
+use crate::gps::{Device, GPS};
+use crate::nmea;
+use crate::synthesis;
+use crate::ubx;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+// Receivers don't send anywhere near this many sentences per epoch, so if we get here we must
+// have missed the start of the epoch.
+const MAX_EPOCH_SENTENCES: usize = 64;
+
+// The sentence rate is averaged over this long.
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+// How long a report from the source itself keeps us from making our own from its sentences.
+const SOURCE_REPORT_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Requests are handled in between reads, which time out after `gps::READ_TIMEOUT` at most.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the feed passes on to its subscribers.
+#[derive(Clone)]
+pub enum Event {
+    /// A sentence, as read from the device.
+    Sentence(Arc<str>),
+    /// What we know at the end of an epoch.
+    Report(Arc<Report>),
+    /// The receiver's answer to a UBX configuration message.
+    Ack(ubx::Ack),
+    /// The versions the receiver answered a UBX-MON-VER poll with.
+    Version(ubx::Version),
+    /// An RTCM3 frame, as read from the device.
+    Rtcm(Arc<[u8]>),
+}
+
+impl Event {
+    pub fn is_sentence(&self) -> bool {
+        matches!(self, Event::Sentence(_))
+    }
+
+    pub fn is_report(&self) -> bool {
+        matches!(self, Event::Report(_))
+    }
+
+    pub fn is_ack(&self) -> bool {
+        matches!(self, Event::Ack(_))
+    }
+
+    pub fn is_version(&self) -> bool {
+        matches!(self, Event::Version(_))
+    }
+
+    pub fn is_rtcm(&self) -> bool {
+        matches!(self, Event::Rtcm(_))
+    }
+}
+
+/// What the feed can be asked to do with the device, in between reads.
+pub enum Request {
+    Write(Vec<u8>),
+    SetBaudrate(u32),
+}
+
+type Pending = (Request, mpsc::Sender<io::Result<()>>);
+
+// What a receiver ends its epochs with, e.g the third `GNGSA`.
+type Ender = (String, usize);
+
+/// How a sentence fits in its epoch.
+struct Part {
+    /// Set for sentences that only come once per epoch (e.g `GPGGA`), or that start a group that
+    /// does (e.g the first `GPGSV` of a sequence).
+    unique: Option<String>,
+    /// Set for sentences that complete one of a kind, e.g the last `GPGSV` of a sequence.
+    completes: Option<String>,
+    time: Option<nmea::Time>,
+}
+
+impl Part {
+    fn of(sentence_type: &str, sentence: Option<&nmea::Sentence>) -> Self {
+        let sentence = match sentence {
+            Some(sentence) => sentence,
+            None => {
+                return Part {
+                    unique: Some(sentence_type.to_string()),
+                    completes: Some(sentence_type.to_string()),
+                    time: None,
+                };
+            }
+        };
+
+        let kind = format!("{}{}", sentence.talker().as_str(), sentence_type);
+        match sentence {
+            // GSV is split in several messages, and NMEA 4.10 receivers send a sequence per signal.
+            nmea::Sentence::Gsv(gsv) => {
+                let kind = format!("{}{:?}", kind, gsv.signal_id);
+
+                Part {
+                    unique: (gsv.message_number == 1).then(|| kind.clone()),
+                    completes: (gsv.message_number == gsv.total_messages).then_some(kind),
+                    time: None,
+                }
+            }
+
+            // Before NMEA 4.10, combined receivers send a GNGSA per system, without saying which.
+            nmea::Sentence::Gsa(gsa) => {
+                let unique = match gsa.system_id {
+                    Some(id) => Some(format!("{}{}", kind, id)),
+                    None if gsa.talker == nmea::Talker::Combined => None,
+                    None => Some(kind.clone()),
+                };
+
+                Part {
+                    completes: unique.clone().or(Some(kind)),
+                    unique,
+                    time: None,
+                }
+            }
+
+            // Text messages come whenever the receiver has something to say.
+            nmea::Sentence::Txt(_) => Part {
+                unique: None,
+                completes: None,
+                time: None,
+            },
+
+            _ => Part {
+                unique: Some(kind.clone()),
+                completes: Some(kind),
+                time: match sentence {
+                    nmea::Sentence::Gga(s) => s.time,
+                    nmea::Sentence::Rmc(s) => s.time,
+                    nmea::Sentence::Gll(s) => s.time,
+                    nmea::Sentence::Gns(s) => s.time,
+                    nmea::Sentence::Gst(s) => s.time,
+                    nmea::Sentence::Zda(s) => s.time,
+                    _ => None,
+                },
+            },
+        }
+    }
+}
+
+/// The sentences of one navigation epoch, i-e everything the receiver reports for a single fix.
+///
+/// An epoch ends when a sentence carries a different time than the epoch's, or when something
+/// that only comes once per epoch comes again. Once we have seen a whole epoch, we
+/// also know what the receiver ends its epochs with, so that we don't need to wait for the next
+/// one to start.
+#[derive(Default)]
+struct Epoch {
+    sentences: Vec<(String, Arc<str>)>,
+    time: Option<nmea::Time>,
+    unique: Vec<String>,
+    completed: Vec<String>,
+}
+
+impl Epoch {
+    fn is_next(&self, part: &Part) -> bool {
+        let new_time = matches!((self.time, part.time), (Some(time), Some(t)) if time != t);
+
+        new_time
+            || part
+                .unique
+                .as_ref()
+                .is_some_and(|u| self.unique.contains(u))
+    }
+
+    // Returns what the sentence completes, along with how many of those the epoch has so far.
+    fn push(&mut self, sentence_type: String, line: Arc<str>, part: Part) -> Option<Ender> {
+        self.sentences.push((sentence_type, line));
+        self.time = self.time.or(part.time);
+        self.unique.extend(part.unique);
+
+        let completes = part.completes?;
+        self.completed.push(completes.clone());
+        let count = self.completed.iter().filter(|c| **c == completes).count();
+
+        Some((completes, count))
+    }
+
+    fn ender(&self) -> Option<Ender> {
+        let last = self.completed.last()?;
+        let count = self.completed.iter().filter(|c| *c == last).count();
+
+        Some((last.clone(), count))
+    }
+
+    fn lines(&self) -> impl Iterator<Item = Event> + '_ {
+        self.sentences
+            .iter()
+            .map(|(_, line)| Event::Sentence(line.clone()))
+    }
+}
+
+/// Counts the sentences over the last `RATE_WINDOW`.
+#[derive(Default)]
+struct Rate {
+    times: VecDeque<Instant>,
+}
+
+impl Rate {
+    fn record(&mut self) {
+        let now = Instant::now();
+        self.expire(now);
+        self.times.push_back(now);
+    }
+
+    fn per_second(&mut self) -> f64 {
+        self.expire(Instant::now());
+
+        self.times.len() as f64 / RATE_WINDOW.as_secs_f64()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self
+            .times
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > RATE_WINDOW)
+        {
+            self.times.pop_front();
+        }
+    }
+}
+
+struct State {
+    filter: ChecksumFilter,
+    rate: Rate,
+    tracker: Tracker,
+    last_epoch: Epoch,
+    current_epoch: Epoch,
+    epoch_ender: Option<Ender>,
+    latest_report: Option<Arc<Report>>,
+    // When the source last gave a report of its own.
+    source_reported: Option<Instant>,
+}
+
+impl State {
+    fn update(&mut self, line: Arc<str>, broadcaster: &Broadcaster<Event>) {
+        self.rate.record();
+
+        let sentence_type = match nmea::sentence_type(&line) {
+            Some(t) => t.to_string(),
+            None => {
+                broadcaster.send(&Event::Sentence(line));
+
+                return;
+            }
+        };
+
+        let sentence = nmea::parse(&line).ok();
+        let part = Part::of(&sentence_type, sentence.as_ref());
+        if self.current_epoch.is_next(&part)
+            || self.current_epoch.sentences.len() >= MAX_EPOCH_SENTENCES
+        {
+            self.finish_epoch(broadcaster);
+        }
+
+        if let Some(ref sentence) = sentence {
+            self.tracker.update(sentence);
+        }
+        let completed = self.current_epoch.push(sentence_type, line.clone(), part);
+        broadcaster.send(&Event::Sentence(line));
+
+        if completed.is_some() && completed == self.epoch_ender {
+            self.finish_epoch(broadcaster);
+        }
+    }
+
+    fn finish_epoch(&mut self, broadcaster: &Broadcaster<Event>) {
+        self.epoch_ender = self.current_epoch.ender();
+        self.last_epoch = std::mem::take(&mut self.current_epoch);
+
+        let report = self.tracker.finish_epoch();
+        // What the source reports itself is more precise than what we make of its sentences.
+        if self
+            .source_reported
+            .is_some_and(|t| t.elapsed() < SOURCE_REPORT_TIMEOUT)
+        {
+            return;
+        }
+
+        self.publish(Arc::new(report), broadcaster);
+    }
+
+    // Takes a report the source put together itself, making up sentences from it if `synthesize`.
+    fn source_report(
+        &mut self,
+        report: Report,
+        synthesize: bool,
+        broadcaster: &Broadcaster<Event>,
+    ) {
+        self.source_reported = Some(Instant::now());
+
+        if synthesize {
+            for line in synthesis::sentences(&report) {
+                self.update(Arc::from(line), broadcaster);
+            }
+        }
+
+        self.publish(Arc::new(report), broadcaster);
+    }
+
+    fn publish(&mut self, report: Arc<Report>, broadcaster: &Broadcaster<Event>) {
+        let had_fix = self
+            .latest_report
+            .as_ref()
+            .is_some_and(|r| r.fix.has_position());
+        if report.fix.has_position() != had_fix {
+            if had_fix {
+                println!("GPS fix lost");
+            } else {
+                println!("GPS fix acquired");
+            }
+        }
+
+        self.latest_report = Some(report.clone());
+        broadcaster.send(&Event::Report(report));
+    }
+}
+
+/// Reads from the GPS device and passes everything read on to the clients.
+///
+/// The device is read continuously, whether anyone is connected or not, so that new clients
+/// always start with fresh data rather than whatever had piled up in the device's buffers.
+pub struct Feed {
+    broadcaster: Broadcaster<Event>,
+    state: Mutex<State>,
+    device: Mutex<Option<Device>>,
+    // The device to switch to, once the current read completes.
+    next_gps: Mutex<Option<Box<dyn GPS>>>,
+    requests: Mutex<Vec<Pending>>,
+    replay: AtomicBool,
+    synthesize: AtomicBool,
+    commands: AtomicBool,
+}
+
+impl Feed {
+    pub fn new(config: &Config) -> Self {
+        Feed {
+            broadcaster: Broadcaster::new(config.queue_size, config.overflow_policy),
+            state: Mutex::new(State {
+                filter: ChecksumFilter::new(config.checksum_policy),
+                rate: Rate::default(),
+                tracker: Tracker::default(),
+                last_epoch: Epoch::default(),
+                current_epoch: Epoch::default(),
+                epoch_ender: None,
+                latest_report: None,
+                source_reported: None,
+            }),
+            device: Mutex::new(None),
+            next_gps: Mutex::new(None),
+            requests: Mutex::new(vec![]),
+            replay: AtomicBool::new(config.replay),
+            synthesize: AtomicBool::new(config.synthesize_nmea),
+            commands: AtomicBool::new(config.client_commands),
+        }
+    }
+
+    /// Applies the parts of `config` that concern the feed, without interrupting it.
+    pub fn reconfigure(&self, config: &Config) {
+        self.broadcaster
+            .configure(config.queue_size, config.overflow_policy);
+        self.replay.store(config.replay, Ordering::Relaxed);
+        self.synthesize
+            .store(config.synthesize_nmea, Ordering::Relaxed);
+        self.commands
+            .store(config.client_commands, Ordering::Relaxed);
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        self.state
+            .lock()
+            .unwrap()
+            .filter
+            .set_policy(config.checksum_policy);
+    }
+
+    /// Subscribes a new client to the feed, for the events `wants` is true for.
+    ///
+    /// If replay is enabled, the client first gets the sentences of the last complete epoch,
+    /// followed by what has been received of the current one so far.
+    pub fn subscribe(&self, wants: fn(&Event) -> bool) -> Subscription<Event> {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        let state = self.state.lock().unwrap();
+        let backlog = if self.replay.load(Ordering::Relaxed) {
+            state
+                .last_epoch
+                .lines()
+                .chain(state.current_epoch.lines())
+                .collect()
+        } else {
+            vec![]
+        };
+
+        // The state lock is held until we're subscribed, so that we don't miss or duplicate
+        // anything read in the meantime.
+        self.broadcaster.subscribe(backlog, wants)
+    }
+
+    /// Subscribes a new client to the feed, without any replay.
+    pub fn subscribe_live(&self, wants: fn(&Event) -> bool) -> Subscription<Event> {
+        self.broadcaster.subscribe(vec![], wants)
+    }
+
+    /// The report from the last complete epoch.
+    pub fn latest_report(&self) -> Option<Arc<Report>> {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        self.state.lock().unwrap().latest_report.clone()
+    }
+
+    /// The last sentence of `sentence_type` (e.g `GGA`) from the last complete epoch.
+    pub fn latest_sentence(&self, sentence_type: &str) -> Option<Arc<str>> {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        let state = self.state.lock().unwrap();
+
+        state
+            .last_epoch
+            .sentences
+            .iter()
+            .rev()
+            .find(|(t, _)| t == sentence_type)
+            .map(|(_, line)| line.clone())
+    }
+
+    /// The device being read from, once reading has started.
+    pub fn device(&self) -> Option<Device> {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        self.device.lock().unwrap().clone()
+    }
+
+    /// Whether what clients send is to be passed on to the device.
+    pub fn accepts_commands(&self) -> bool {
+        self.commands.load(Ordering::Relaxed)
+    }
+
+    /// The number of sentences passed on per second, averaged over the last few seconds.
+    pub fn sentence_rate(&self) -> f64 {
+        self.state.lock().unwrap().rate.per_second()
+    }
+
+    /// What became of the sentences that went through checksum validation so far.
+    pub fn checksum_counters(&self) -> Counters {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        self.state.lock().unwrap().filter.counters()
+    }
+
+    /// Switches to reading from `gps`.
+    ///
+    /// Reads from the current device are blocking, so the switch only happens once the current
+    /// device sends its next line (or fails).
+    pub fn switch(&self, gps: Box<dyn GPS>) {
+        *self.next_gps.lock().unwrap() = Some(gps);
+    }
+
+    /// Has `request` carried out on the device, and waits for the outcome.
+    ///
+    /// Reads from the device are blocking, so this only happens once the device sends its next
+    /// line (or its read times out).
+    pub fn request(&self, request: Request) -> io::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        self.requests.lock().unwrap().push((request, sender));
+
+        receiver.recv_timeout(REQUEST_TIMEOUT).unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "GPS device didn't get back to us",
+            ))
+        })
+    }
+
+    fn handle_requests(&self, gps: &mut dyn GPS) {
+        let requests = std::mem::take(&mut *self.requests.lock().unwrap());
+        if requests.is_empty() {
+            return;
+        }
+
+        for (request, reply) in requests {
+            let result = match request {
+                Request::Write(data) => gps.write(&data),
+                Request::SetBaudrate(baudrate) => gps.set_baudrate(baudrate),
+            };
+            // Whoever asked may have given up waiting.
+            let _ = reply.send(result);
+        }
+        self.track_device(gps);
+    }
+
+    // Sources like `Hotplug` change devices on their own.
+    fn track_device(&self, gps: &dyn GPS) {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        let mut device = self.device.lock().unwrap();
+        let changed = match *device {
+            Some(ref d) => {
+                d.kind != gps.kind()
+                    || d.path.as_deref() != gps.path()
+                    || d.baudrate != gps.baudrate()
+            }
+            None => true,
+        };
+
+        if changed {
+            *device = Some(Device::of(gps));
+        }
+    }
+
+    /// Reads from `gps` until it reaches the end of its stream.
+    pub fn run(&self, mut gps: Box<dyn GPS>) {
+        let mut buffer = String::new();
+        *self.device.lock().unwrap() = Some(Device::of(&*gps));
+
+        loop {
+            buffer.clear();
+
+            if let Some(next) = self.next_gps.lock().unwrap().take() {
+                gps = next;
+                let device = Device::of(&*gps);
+                match device.path {
+                    Some(ref path) => println!("Switched to {}", path.display()),
+                    None => println!("Switched to {}", device.kind),
+                }
+                *self.device.lock().unwrap() = Some(device);
+            }
+
+            self.handle_requests(&mut *gps);
+
+            let result = gps.read_line(&mut buffer);
+            while let Some(ack) = gps.take_ack() {
+                self.broadcaster.send(&Event::Ack(ack));
+            }
+            if let Some(version) = gps.take_version() {
+                self.broadcaster.send(&Event::Version(version));
+            }
+            while let Some(frame) = gps.take_rtcm() {
+                self.broadcaster.send(&Event::Rtcm(Arc::from(frame)));
+            }
+            if let Some(report) = gps.take_report() {
+                let synthesize = self.synthesize.load(Ordering::Relaxed);
+                // unwrap cause we don't want a poisoned lock:
+                // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+                self.state
+                    .lock()
+                    .unwrap()
+                    .source_report(report, synthesize, &self.broadcaster);
+            }
+
+            match result {
+                Ok(0) => {
+                    println!("GPS device closed the stream");
+
+                    break;
+                }
+
+                Ok(_) => self.track_device(&*gps),
+
+                // Nothing to read yet, e.g no device is plugged in, or the source sent a report, an
+                // acknowledgement or an RTCM3 frame.
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+
+                Err(e) => {
+                    println!("Failed to read from GPS device: {}", e);
+
+                    continue;
+                }
+            }
+
+            let mut state = self.state.lock().unwrap();
+            let line: Arc<str> = match state.filter.filter(&buffer) {
+                Some(line) => Arc::from(line),
+                None => continue,
+            };
+            state.update(line, &self.broadcaster);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+/* vim: set et ts=4 sw=4: */
+/* file_config.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+use crate::allowlist::{UdevProperty, UsbId};
+use crate::broadcast::OverflowPolicy;
+use crate::dbus::Bus;
+use crate::filter::ChecksumPolicy;
+use crate::ntrip::{Mountpoint, Version};
+use crate::receiver::{Constellation, Vendor};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Read if it exists and no configuration file is given on the command line.
+pub const SYSTEM_PATH: &str = "/etc/gps-share.toml";
+
+/// The contents of a configuration file.
+///
+/// Each key is named after the long command-line option it stands for, e.g `queue-size = 256`
+/// for `--queue-size 256`, and flags are booleans, e.g `no-tcp = true`.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FileConfig {
+    pub device: Option<PathBuf>,
+    pub disable_announce: Option<bool>,
+    pub port: Option<u16>,
+    pub network_interface: Option<String>,
+    pub no_tcp: Option<bool>,
+    pub socket_path: Option<String>,
+    pub baudrate: Option<u32>,
+    pub usb_drivers: Option<Vec<String>>,
+    #[serde(deserialize_with = "from_strs")]
+    pub usb_ids: Option<Vec<UsbId>>,
+    #[serde(deserialize_with = "from_strs")]
+    pub udev_properties: Option<Vec<UdevProperty>>,
+    pub discover_network: Option<bool>,
+    #[serde(deserialize_with = "from_str")]
+    pub receiver: Option<Vendor>,
+    pub update_rate: Option<u32>,
+    pub sentences: Option<Vec<String>>,
+    #[serde(deserialize_with = "from_strs")]
+    pub constellations: Option<Vec<Constellation>>,
+    pub receiver_baudrate: Option<u32>,
+    #[serde(deserialize_with = "from_str")]
+    pub ntrip: Option<Mountpoint>,
+    #[serde(deserialize_with = "from_str")]
+    pub ntrip_version: Option<Version>,
+    pub ntrip_gga_interval: Option<u64>,
+    pub queue_size: Option<usize>,
+    #[serde(deserialize_with = "from_str")]
+    pub overflow_policy: Option<OverflowPolicy>,
+    pub no_replay: Option<bool>,
+    pub no_nmea_synthesis: Option<bool>,
+    pub client_commands: Option<bool>,
+    #[serde(deserialize_with = "from_str")]
+    pub bad_checksum: Option<ChecksumPolicy>,
+    pub gpsd_port: Option<u16>,
+    pub caster_port: Option<u16>,
+    pub caster_mountpoint: Option<String>,
+    #[serde(deserialize_with = "from_str")]
+    pub dbus: Option<Bus>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl FileConfig {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path).map_err(Error::Io)?;
+
+        toml::from_str(&contents).map_err(Error::Parse)
+    }
+}
+
+// For the option values we already know how to parse from the command line.
+fn from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let s = String::deserialize(deserializer)?;
+
+    s.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+fn from_strs<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let strings = Vec::<String>::deserialize(deserializer)?;
+
+    strings
+        .iter()
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
@@ -0,0 +1,186 @@
+/* vim: set et ts=4 sw=4: */
+/* filter.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+use crate::nmea;
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// Rejected sentences are logged at most this often, as a bad link can corrupt many of them.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What to do with sentences that fail checksum validation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumPolicy {
+    /// Don't pass them on.
+    Drop,
+    /// Pass them on as they are.
+    Forward,
+    /// Fix up the checksum and pass them on, tagged as repaired.
+    Repair,
+}
+
+impl FromStr for ChecksumPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(ChecksumPolicy::Drop),
+            "forward" => Ok(ChecksumPolicy::Forward),
+            "repair" => Ok(ChecksumPolicy::Repair),
+            _ => Err(format!(
+                "unknown checksum policy `{}` (expected `drop`, `forward` or `repair`)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumPolicy::Drop => f.write_str("drop"),
+            ChecksumPolicy::Forward => f.write_str("forward"),
+            ChecksumPolicy::Repair => f.write_str("repair"),
+        }
+    }
+}
+
+/// How many sentences went through the filter, and what became of them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Counters {
+    pub valid: u64,
+    /// Sentences without a checksum, which NMEA 0183 makes optional for most of them.
+    pub unchecked: u64,
+    pub dropped: u64,
+    pub forwarded: u64,
+    pub repaired: u64,
+}
+
+impl Counters {
+    /// The number of sentences that failed validation.
+    pub fn rejected(&self) -> u64 {
+        self.dropped + self.forwarded + self.repaired
+    }
+}
+
+/// Validates the checksum of each sentence read from the GPS device.
+///
+/// Sentences without a checksum are passed on as they are.
+pub struct ChecksumFilter {
+    policy: ChecksumPolicy,
+    counters: Counters,
+    last_log: Option<Instant>,
+}
+
+impl ChecksumFilter {
+    pub fn new(policy: ChecksumPolicy) -> Self {
+        ChecksumFilter {
+            policy,
+            counters: Counters::default(),
+            last_log: None,
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: ChecksumPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    /// Returns the line to pass on, if any.
+    pub fn filter<'a>(&mut self, line: &'a str) -> Option<Cow<'a, str>> {
+        let e = match nmea::verify_checksum(line) {
+            Ok(()) => {
+                self.counters.valid += 1;
+
+                return Some(Cow::Borrowed(line));
+            }
+
+            Err(nmea::Error::MissingChecksum) => {
+                self.counters.unchecked += 1;
+
+                return Some(Cow::Borrowed(line));
+            }
+
+            Err(e) => e,
+        };
+
+        if self.policy == ChecksumPolicy::Repair {
+            // Lines that aren't even remotely a sentence can't be repaired, so they get dropped.
+            if let Some(repaired) = repair(line) {
+                self.counters.repaired += 1;
+                self.log("Repaired", &e, line);
+
+                return Some(Cow::Owned(repaired));
+            }
+        }
+
+        if self.policy == ChecksumPolicy::Forward {
+            self.counters.forwarded += 1;
+            self.log("Forwarded", &e, line);
+
+            return Some(Cow::Borrowed(line));
+        }
+
+        self.counters.dropped += 1;
+        self.log("Dropped", &e, line);
+
+        None
+    }
+
+    fn log(&mut self, action: &str, e: &nmea::Error, line: &str) {
+        if self.last_log.is_some_and(|t| t.elapsed() < LOG_INTERVAL) {
+            return;
+        }
+        self.last_log = Some(Instant::now());
+
+        println!(
+            "{} sentence ({}, {} rejected so far): {}",
+            action,
+            e,
+            self.counters.rejected(),
+            line.trim_end()
+        );
+    }
+}
+
+// Recomputes the checksum and prepends an NMEA 4.10 TAG block, marking the sentence as repaired.
+fn repair(line: &str) -> Option<String> {
+    let data = line.trim_end_matches(['\r', '\n']).strip_prefix('$')?;
+    let data = data.rsplit_once('*').map_or(data, |(data, _)| data);
+    if data.is_empty() {
+        return None;
+    }
+    let tag = "t:repaired";
+
+    Some(format!(
+        "\\{}*{:02X}\\{}",
+        tag,
+        nmea::checksum(tag),
+        nmea::with_checksum(data)
+    ))
+}
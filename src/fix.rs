@@ -0,0 +1,354 @@
+/* vim: set et ts=4 sw=4: */
+/* fix.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+use crate::nmea::{self, FixQuality, FixType, Sentence, Talker};
+
+const METERS_PER_SECOND_PER_KNOT: f64 = 1852.0 / 3600.0;
+// A typical figure for a consumer-grade receiver, in meters.
+const USER_EQUIVALENT_RANGE_ERROR: f64 = 5.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Nothing said about the fix yet.
+    #[default]
+    Unknown,
+    NoFix,
+    Fix2D,
+    Fix3D,
+}
+
+/// The position (and velocity) as of the end of an epoch.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fix {
+    /// Seconds since the Unix epoch.
+    pub time: Option<f64>,
+    pub mode: Mode,
+    pub quality: Option<FixQuality>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Altitude above mean sea level, in meters.
+    pub altitude: Option<f64>,
+    /// Height of the geoid above the WGS84 ellipsoid, in meters.
+    pub geoid_separation: Option<f64>,
+    /// Speed over ground, in meters per second.
+    pub speed: Option<f64>,
+    /// Course over ground, in degrees from true north.
+    pub track: Option<f64>,
+    /// Vertical speed, in meters per second.
+    pub climb: Option<f64>,
+    /// Longitude error estimate, in meters.
+    pub epx: Option<f64>,
+    /// Latitude error estimate, in meters.
+    pub epy: Option<f64>,
+    /// Altitude error estimate, in meters.
+    pub epv: Option<f64>,
+    pub satellites_used: Option<u8>,
+}
+
+impl Fix {
+    pub fn has_position(&self) -> bool {
+        self.latitude.is_some() && self.longitude.is_some() && !matches!(self.mode, Mode::NoFix)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Satellite {
+    pub talker: Talker,
+    pub prn: u16,
+    pub elevation: Option<i16>,
+    pub azimuth: Option<u16>,
+    pub snr: Option<u8>,
+    pub used: bool,
+}
+
+/// The satellites in view.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sky {
+    pub satellites: Vec<Satellite>,
+    pub hdop: Option<f64>,
+    pub vdop: Option<f64>,
+    pub pdop: Option<f64>,
+}
+
+/// Everything we know at the end of an epoch.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub fix: Fix,
+    pub sky: Sky,
+}
+
+impl Report {
+    /// Horizontal accuracy, in meters, if known.
+    ///
+    /// Without error estimates from the receiver, this is guessed from the HDOP.
+    pub fn horizontal_accuracy(&self) -> Option<f64> {
+        match (self.fix.epx, self.fix.epy) {
+            (Some(x), Some(y)) => Some(x.max(y)),
+            _ => self.sky.hdop.map(|hdop| hdop * USER_EQUIVALENT_RANGE_ERROR),
+        }
+    }
+}
+
+/// Puts together a `Report` from the sentences of each epoch.
+#[derive(Default)]
+pub struct Tracker {
+    fix: Fix,
+    sky: Sky,
+    // RMC and ZDA carry the date but not every epoch has them.
+    date: Option<nmea::Date>,
+    used: Vec<u16>,
+}
+
+impl Tracker {
+    pub fn update(&mut self, sentence: &Sentence) {
+        match sentence {
+            Sentence::Gga(gga) => {
+                self.set_time(gga.time);
+                self.fix.quality = Some(gga.quality);
+                if gga.quality == FixQuality::Invalid {
+                    self.fix.mode = Mode::NoFix;
+
+                    return;
+                }
+
+                self.set_position(gga.latitude, gga.longitude);
+                self.fix.altitude = gga.altitude.or(self.fix.altitude);
+                self.fix.geoid_separation = gga.geoid_separation.or(self.fix.geoid_separation);
+                self.fix.satellites_used = gga.satellites.or(self.fix.satellites_used);
+                self.sky.hdop = gga.hdop.or(self.sky.hdop);
+                if self.fix.mode == Mode::Unknown {
+                    self.fix.mode = if gga.altitude.is_some() {
+                        Mode::Fix3D
+                    } else {
+                        Mode::Fix2D
+                    };
+                }
+            }
+
+            Sentence::Rmc(rmc) => {
+                self.date = rmc.date.or(self.date);
+                self.set_time(rmc.time);
+                if !rmc.valid {
+                    if self.fix.mode == Mode::Unknown {
+                        self.fix.mode = Mode::NoFix;
+                    }
+
+                    return;
+                }
+
+                self.set_position(rmc.latitude, rmc.longitude);
+                self.fix.speed = rmc
+                    .speed_knots
+                    .map(|s| s * METERS_PER_SECOND_PER_KNOT)
+                    .or(self.fix.speed);
+                self.fix.track = rmc.course.or(self.fix.track);
+                if self.fix.mode == Mode::Unknown {
+                    self.fix.mode = Mode::Fix2D;
+                }
+            }
+
+            Sentence::Gsa(gsa) => {
+                self.fix.mode = match gsa.fix_type {
+                    FixType::NoFix => Mode::NoFix,
+                    FixType::Fix2D => Mode::Fix2D,
+                    FixType::Fix3D => Mode::Fix3D,
+                };
+                self.used.extend(&gsa.satellites);
+                self.sky.pdop = gsa.pdop.or(self.sky.pdop);
+                self.sky.hdop = gsa.hdop.or(self.sky.hdop);
+                self.sky.vdop = gsa.vdop.or(self.sky.vdop);
+            }
+
+            Sentence::Gsv(gsv) => {
+                // The first message of a sequence replaces what we had for the same talker.
+                if gsv.message_number == 1 {
+                    self.sky.satellites.retain(|s| s.talker != gsv.talker);
+                }
+
+                for sat in &gsv.satellites {
+                    self.sky.satellites.push(Satellite {
+                        talker: gsv.talker,
+                        prn: sat.prn,
+                        elevation: sat.elevation,
+                        azimuth: sat.azimuth,
+                        snr: sat.snr,
+                        used: false,
+                    });
+                }
+            }
+
+            Sentence::Vtg(vtg) => {
+                if vtg.mode.is_some_and(|m| !m.is_valid()) {
+                    return;
+                }
+
+                let speed = vtg
+                    .speed_kmh
+                    .map(|s| s / 3.6)
+                    .or(vtg.speed_knots.map(|s| s * METERS_PER_SECOND_PER_KNOT));
+                self.fix.speed = speed.or(self.fix.speed);
+                self.fix.track = vtg.course_true.or(self.fix.track);
+            }
+
+            Sentence::Gll(gll) => {
+                if !gll.valid {
+                    return;
+                }
+
+                self.set_time(gll.time);
+                self.set_position(gll.latitude, gll.longitude);
+            }
+
+            Sentence::Gns(gns) => {
+                self.set_time(gns.time);
+                if gns.modes.iter().all(|m| !m.is_valid()) {
+                    if self.fix.mode == Mode::Unknown {
+                        self.fix.mode = Mode::NoFix;
+                    }
+
+                    return;
+                }
+
+                self.set_position(gns.latitude, gns.longitude);
+                self.fix.altitude = gns.altitude.or(self.fix.altitude);
+                self.fix.geoid_separation = gns.geoid_separation.or(self.fix.geoid_separation);
+                self.fix.satellites_used = gns.satellites.or(self.fix.satellites_used);
+                self.sky.hdop = gns.hdop.or(self.sky.hdop);
+            }
+
+            Sentence::Gst(gst) => {
+                self.fix.epx = gst.longitude_error;
+                self.fix.epy = gst.latitude_error;
+                self.fix.epv = gst.altitude_error;
+            }
+
+            Sentence::Zda(zda) => {
+                self.date = zda.date.or(self.date);
+                self.set_time(zda.time);
+            }
+
+            Sentence::Txt(_) => {}
+        }
+    }
+
+    /// Finishes the current epoch, returning what we learnt from it.
+    pub fn finish_epoch(&mut self) -> Report {
+        let fix = std::mem::take(&mut self.fix);
+        let mut sky = self.sky.clone();
+        for sat in sky.satellites.iter_mut() {
+            sat.used = self.used.contains(&sat.prn);
+        }
+        self.used.clear();
+        // Unlike the satellites in view, DOPs are only valid for the epoch they came with.
+        self.sky.hdop = None;
+        self.sky.vdop = None;
+        self.sky.pdop = None;
+
+        Report { fix, sky }
+    }
+
+    fn set_time(&mut self, time: Option<nmea::Time>) {
+        if let (Some(date), Some(time)) = (self.date, time) {
+            self.fix.time = Some(timestamp(&date, &time));
+        }
+    }
+
+    fn set_position(&mut self, latitude: Option<f64>, longitude: Option<f64>) {
+        if let (Some(lat), Some(lon)) = (latitude, longitude) {
+            self.fix.latitude = Some(lat);
+            self.fix.longitude = Some(lon);
+        }
+    }
+}
+
+/// Converts a UTC date and time to seconds since the Unix epoch.
+pub fn timestamp(date: &nmea::Date, time: &nmea::Time) -> f64 {
+    let days = days_from_civil(date.year.into(), date.month.into(), date.day.into());
+
+    (days * 86_400 + i64::from(time.hour) * 3_600 + i64::from(time.minute) * 60) as f64
+        + time.second
+}
+
+/// Converts seconds since the Unix epoch to a UTC date and time.
+pub fn date_time(timestamp: f64) -> (nmea::Date, nmea::Time) {
+    let days = (timestamp / 86_400.0).floor();
+    let seconds = timestamp - days * 86_400.0;
+    let (year, month, day) = civil_from_days(days as i64);
+    let date = nmea::Date {
+        year: year as u16,
+        month: month as u8,
+        day: day as u8,
+    };
+    let time = nmea::Time {
+        hour: (seconds / 3_600.0) as u8,
+        minute: (seconds / 60.0 % 60.0) as u8,
+        second: seconds % 60.0,
+    };
+
+    (date, time)
+}
+
+/// Formats seconds since the Unix epoch as an ISO 8601 UTC timestamp, with millisecond
+/// precision.
+pub fn format_timestamp(timestamp: f64) -> String {
+    let millis = (timestamp * 1000.0).round() as i64;
+    let (days, millis) = (millis.div_euclid(86_400_000), millis.rem_euclid(86_400_000));
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+// Both of these are from http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
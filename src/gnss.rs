@@ -22,89 +22,134 @@
  * Author: Zeeshan Ali <zeeshanak@gnome.org>
  */
 
-use crate::config::Config;
-use crate::gps::GPS;
-use std::fs;
-use std::fs::File;
+use crate::fix::Report;
+use crate::gps::{GPS, READ_TIMEOUT};
+use crate::ubx;
+use crate::verify::{self, MAX_LINE};
+use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::path::Path;
-use std::rc::Rc;
+use std::io::Read;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+// How long to listen for NMEA when probing.
+const VERIFY_TIMEOUT: Duration = Duration::from_millis(5_000);
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct GNSS {
-    reader: BufReader<fs::File>,
+    reader: BufReader<Device>,
+    path: PathBuf,
+    ubx: ubx::Decoder,
+    // Only opened once there's something to write, as the device being opened for writing would
+    // keep FIFOs standing in for it from ever reaching their end.
+    writer: Option<File>,
 }
 
 impl GNSS {
-    pub fn new(config: Rc<Config>) -> io::Result<Self> {
-        match config.dev_path {
-            Some(ref path) => GNSS::new_for_path(path.as_path()),
-            None => GNSS::new_detect(),
-        }
-    }
-
-    fn new_for_path(path: &Path) -> io::Result<Self> {
+    pub fn new_for_path(path: &Path) -> io::Result<Self> {
         let port = File::open(path.as_os_str())?;
 
         Ok(GNSS {
-            reader: BufReader::new(port),
+            reader: BufReader::new(Device(port)),
+            path: path.to_path_buf(),
+            ubx: ubx::Decoder::default(),
+            writer: None,
         })
     }
 
-    fn new_detect() -> io::Result<Self> {
-        println!("Attempting to autodetect GNSS device...");
-        let mut enumerator = udev::Enumerator::new()?;
-        enumerator.match_subsystem("gnss")?;
-        let devices = enumerator.scan_devices()?;
-        for d in devices {
-            if let Some(p) = d.devnode().and_then(|devnode| devnode.to_str()) {
-                let path = Path::new(p);
-
-                match GNSS::new_for_path(&path) {
-                    Ok(mut gps) => {
-                        if gps.verify() {
-                            println!("Detected {} as a GPS device", p);
-
-                            return Ok(gps);
-                        }
-                    }
+    /// Checks that what comes out of the device is NMEA.
+    pub fn verify(&mut self) -> bool {
+        let deadline = Instant::now() + VERIFY_TIMEOUT;
+        let reader = &mut self.reader;
 
-                    Err(e) => println!("Error openning {}: {}", p, e),
+        verify::verify(
+            |line| {
+                // Reads block, so only read once there's something to read.
+                if reader.buffer().is_empty() {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if !readable(&reader.get_ref().0, timeout)? {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
                 }
-            }
-        }
 
-        Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Failed to autodetect GNSS device",
-        ))
+                reader.take(MAX_LINE).read_until(b'\n', line)
+            },
+            VERIFY_TIMEOUT,
+        )
+    }
+}
+
+impl GPS for GNSS {
+    fn read_line(&mut self, buffer: &mut String) -> io::Result<usize> {
+        self.ubx.read_line(&mut self.reader, buffer)
+    }
+
+    fn kind(&self) -> &'static str {
+        "GNSS"
     }
 
-    fn verify(&mut self) -> bool {
-        let mut buffer = String::new();
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
 
-        for _ in 1..3 {
-            if let Ok(_) = self.read_line(&mut buffer) {
-                if buffer.len() >= 15
-                    && buffer.chars().nth(0) == Some('$')
-                    && buffer.chars().nth(6) == Some(',')
-                {
-                    return true;
-                }
+    fn take_report(&mut self) -> Option<Report> {
+        self.ubx.take_report()
+    }
+
+    fn take_ack(&mut self) -> Option<ubx::Ack> {
+        self.ubx.take_ack()
+    }
+
+    fn take_version(&mut self) -> Option<ubx::Version> {
+        self.ubx.take_version()
+    }
+
+    fn take_rtcm(&mut self) -> Option<Vec<u8>> {
+        self.ubx.take_rtcm()
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let writer = match self.writer {
+            Some(ref mut writer) => writer,
+            None => self
+                .writer
+                .insert(OpenOptions::new().write(true).open(&self.path)?),
+        };
 
-                buffer.clear();
-            } else {
-                println!("Failed to read from serial port");
-            }
+        writer.write_all(data)
+    }
+}
+
+// The device node, whose reads time out like those of serial devices do, rather than block.
+struct Device(File);
+
+impl Read for Device {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !readable(&self.0, READ_TIMEOUT)? {
+            return Err(io::ErrorKind::TimedOut.into());
         }
 
-        false
+        self.0.read(buf)
     }
 }
 
-impl GPS for GNSS {
-    fn read_line(&mut self, buffer: &mut String) -> io::Result<usize> {
-        self.reader.read_line(buffer)
+// Waits up to `timeout` for `file` to have something to read.
+fn readable(file: &File, timeout: Duration) -> io::Result<bool> {
+    let mut fds = [libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+
+    // SAFETY: `fds` is a valid array of one `pollfd`, whose descriptor outlives the call.
+    let ret = unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout.as_millis() as i32) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
     }
+
+    Ok(ret > 0)
 }
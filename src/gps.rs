@@ -21,14 +21,137 @@
  * Author: Zeeshan Ali <zeeshanak@gnome.org>
  */
 
+use crate::discovery::Registry;
+use crate::fix::Report;
+use crate::ubx;
 use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+/// How long reads from a source wait for something to come at most, so that requests (e.g client
+/// commands) get handled in between reads.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[allow(clippy::upper_case_acronyms)]
 pub trait GPS: Send + 'static {
     fn read_line(&mut self, buffer: &mut String) -> io::Result<usize>;
+
+    /// The kind of source, e.g `RS232`.
+    fn kind(&self) -> &'static str;
+
+    /// The device node being read from, if there is one.
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// The baudrate the device is read at, if it has one.
+    fn baudrate(&self) -> Option<u32> {
+        None
+    }
+
+    /// Takes the report the source put together itself, from a binary protocol, once `read_line`
+    /// says it has one by failing with `WouldBlock`.
+    fn take_report(&mut self) -> Option<Report> {
+        None
+    }
+
+    /// Takes the receiver's answer to a UBX configuration message, once `read_line` says it has
+    /// one by failing with `WouldBlock`.
+    fn take_ack(&mut self) -> Option<ubx::Ack> {
+        None
+    }
+
+    /// Takes the versions the receiver answered a UBX-MON-VER poll with, once `read_line` says it
+    /// has them by failing with `WouldBlock`.
+    fn take_version(&mut self) -> Option<ubx::Version> {
+        None
+    }
+
+    /// Takes an RTCM3 frame the source sent, e.g as a base station, once `read_line` says it has
+    /// one by failing with `WouldBlock`.
+    fn take_rtcm(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Sends `data` to the device, e.g configuration commands, corrections or what clients send.
+    fn write(&mut self, _data: &[u8]) -> io::Result<()> {
+        Err(unsupported(self.kind(), "written to"))
+    }
+
+    /// Starts talking to the device at `baudrate`, once it was told to switch to it.
+    fn set_baudrate(&mut self, _baudrate: u32) -> io::Result<()> {
+        Err(unsupported(self.kind(), "switched to another baudrate"))
+    }
 }
 
 impl<T: GPS + 'static + ?Sized> GPS for Box<T> {
     fn read_line(&mut self, buffer: &mut String) -> io::Result<usize> {
         (**self).read_line(buffer)
     }
+
+    fn kind(&self) -> &'static str {
+        (**self).kind()
+    }
+
+    fn path(&self) -> Option<&Path> {
+        (**self).path()
+    }
+
+    fn baudrate(&self) -> Option<u32> {
+        (**self).baudrate()
+    }
+
+    fn take_report(&mut self) -> Option<Report> {
+        (**self).take_report()
+    }
+
+    fn take_ack(&mut self) -> Option<ubx::Ack> {
+        (**self).take_ack()
+    }
+
+    fn take_version(&mut self) -> Option<ubx::Version> {
+        (**self).take_version()
+    }
+
+    fn take_rtcm(&mut self) -> Option<Vec<u8>> {
+        (**self).take_rtcm()
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        (**self).write(data)
+    }
+
+    fn set_baudrate(&mut self, baudrate: u32) -> io::Result<()> {
+        (**self).set_baudrate(baudrate)
+    }
+}
+
+fn unsupported(kind: &str, what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} devices can't be {}", kind, what),
+    )
+}
+
+/// What we tell others about the device we're reading from.
+#[derive(Clone, Debug)]
+pub struct Device {
+    pub kind: &'static str,
+    pub path: Option<PathBuf>,
+    pub baudrate: Option<u32>,
+}
+
+impl Device {
+    pub fn of(gps: &dyn GPS) -> Self {
+        Device {
+            kind: gps.kind(),
+            path: gps.path().map(Path::to_path_buf),
+            baudrate: gps.baudrate(),
+        }
+    }
+}
+
+/// Opens the device at `path`, with the prober of whichever kind of source it names.
+pub fn open(path: &Path, baudrate: u32) -> io::Result<Box<dyn GPS>> {
+    Registry::sources().open(path, baudrate)
 }
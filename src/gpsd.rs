@@ -0,0 +1,441 @@
+/* vim: set et ts=4 sw=4: */
+/* gpsd.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! A subset of the gpsd JSON protocol: https://gpsd.gitlab.io/gpsd/gpsd_json.html
+
+use crate::client_handler::Stream;
+use crate::clients::{Clients, Registration};
+use crate::feed::{Event, Feed};
+use crate::fix::{self, Fix, Mode, Sky};
+use crate::gps::Device;
+use crate::nmea::{FixQuality, Talker};
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The protocol version of the gpsd release we are modelled on (3.25).
+const PROTO_MAJOR: u32 = 3;
+const PROTO_MINOR: u32 = 15;
+
+// What gpsd clients know the device we share as.
+fn device_path(device: &Option<Device>) -> String {
+    match device.as_ref().and_then(|d| d.path.as_ref()) {
+        Some(path) => path.display().to_string(),
+        None => "-".to_string(),
+    }
+}
+
+fn device_json(device: &Option<Device>, activated: f64) -> Value {
+    let mut object = json!({
+        "class": "DEVICE",
+        "path": device_path(device),
+        "driver": "NMEA0183",
+        "activated": fix::format_timestamp(activated),
+        "flags": 1,
+        "native": 0,
+        "cycle": 1.0,
+    });
+    if let Some(bps) = device.as_ref().and_then(|d| d.baudrate) {
+        let object = object.as_object_mut().expect("is an object");
+        object.insert("bps".into(), bps.into());
+        object.insert("parity".into(), "N".into());
+        object.insert("stopbits".into(), 1.into());
+    }
+
+    object
+}
+
+pub struct Gpsd {
+    listener: TcpListener,
+    feed: Arc<Feed>,
+    clients: Arc<Clients>,
+    activated: f64,
+}
+
+impl Gpsd {
+    pub fn new(ip: &str, port: u16, feed: Arc<Feed>, clients: Arc<Clients>) -> io::Result<Self> {
+        let listener = TcpListener::bind((ip, port))?;
+
+        Ok(Gpsd {
+            listener,
+            feed,
+            clients,
+            activated: now(),
+        })
+    }
+
+    pub fn port(&self) -> io::Result<u16> {
+        self.listener.local_addr().map(|addr| addr.port())
+    }
+
+    pub fn run(&self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    println!("gpsd client connection from {}", addr.ip());
+
+                    if let Err(e) = self.launch_client(stream, addr.to_string()) {
+                        eprintln!("Failed to set up gpsd client: {}", e);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Connect from gpsd client failed: {}", e);
+                }
+            }
+        }
+    }
+
+    fn launch_client(&self, stream: TcpStream, address: String) -> io::Result<()> {
+        let reader = BufReader::new(stream.try_clone()?);
+        let registration = self
+            .clients
+            .add("gpsd", address, &Stream::Tcp(stream.try_clone()?))?;
+        let client = Arc::new(Client {
+            stream: Mutex::new(stream),
+            watch: Mutex::new(Watch::default()),
+            feed: self.feed.clone(),
+            activated: self.activated,
+            _registration: registration,
+        });
+        client.send(&version())?;
+
+        let subscription = self
+            .feed
+            .subscribe_live(|event| event.is_sentence() || event.is_report());
+        let events_client = client.clone();
+        let closer = subscription.closer();
+        thread::spawn(move || {
+            while let Some(event) = subscription.recv() {
+                if events_client.handle_event(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        thread::spawn(move || {
+            client.handle_commands(reader);
+            // The client is gone, so it goes from the list now rather than with the next event.
+            closer.close();
+        });
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Watch {
+    enable: bool,
+    json: bool,
+    nmea: bool,
+}
+
+// The parts of the WATCH request we support.
+#[derive(Deserialize)]
+struct WatchRequest {
+    enable: Option<bool>,
+    json: Option<bool>,
+    nmea: Option<bool>,
+}
+
+struct Client {
+    stream: Mutex<TcpStream>,
+    watch: Mutex<Watch>,
+    feed: Arc<Feed>,
+    activated: f64,
+    // Keeps the client listed for as long as we serve it.
+    _registration: Registration,
+}
+
+impl Client {
+    fn handle_commands(&self, reader: BufReader<TcpStream>) {
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+
+            // Several commands can be sent at once, each terminated by a `;`.
+            for command in line.split(';').map(str::trim).filter(|c| !c.is_empty()) {
+                if let Err(e) = self.handle_command(command) {
+                    println!("Failed to reply to gpsd client: {}", e);
+
+                    break;
+                }
+            }
+        }
+
+        // Done with the connection, even if the event thread still writes to it.
+        let _ = self.stream.lock().unwrap().shutdown(Shutdown::Both);
+    }
+
+    fn handle_command(&self, command: &str) -> io::Result<()> {
+        let (name, args) = match command.split_once('=') {
+            Some((name, args)) => (name, Some(args)),
+            None => (command, None),
+        };
+
+        match name {
+            "?VERSION" => self.send(&version()),
+
+            "?DEVICES" => self.send(&self.devices()),
+
+            "?WATCH" => {
+                let request = match args.map(serde_json::from_str::<WatchRequest>) {
+                    Some(Ok(request)) => Some(request),
+                    Some(Err(e)) => return self.send(&error(&format!("Invalid WATCH: {}", e))),
+                    None => None,
+                };
+
+                // unwrap cause we don't want a poisoned lock:
+                // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+                let watch = {
+                    let mut watch = self.watch.lock().unwrap();
+                    match request {
+                        Some(request) => {
+                            watch.enable = request.enable.unwrap_or(true);
+                            watch.json = request.json.unwrap_or(watch.json);
+                            watch.nmea = request.nmea.unwrap_or(watch.nmea);
+                            // Like gpsd, default to JSON reports if nothing was asked for.
+                            if watch.enable && !watch.nmea && request.json.is_none() {
+                                watch.json = true;
+                            }
+                        }
+                        None => {
+                            watch.enable = true;
+                            watch.json = true;
+                        }
+                    }
+
+                    *watch
+                };
+
+                self.send(&self.devices())?;
+                self.send(&json!({
+                    "class": "WATCH",
+                    "enable": watch.enable,
+                    "json": watch.json,
+                    "nmea": watch.nmea,
+                    "raw": 0,
+                    "scaled": false,
+                    "timing": false,
+                    "split24": false,
+                    "pps": false,
+                }))
+            }
+
+            "?POLL" => {
+                let report = self.feed.latest_report();
+                let path = device_path(&self.feed.device());
+                let (tpv, sky) = match report {
+                    Some(ref r) => (vec![tpv(&path, &r.fix)], vec![sky(&path, &r.fix, &r.sky)]),
+                    None => (vec![], vec![]),
+                };
+
+                self.send(&json!({
+                    "class": "POLL",
+                    "time": fix::format_timestamp(now()),
+                    "active": 1,
+                    "tpv": tpv,
+                    "sky": sky,
+                }))
+            }
+
+            _ => self.send(&error(&format!("Unrecognized request '{}'", name))),
+        }
+    }
+
+    fn handle_event(&self, event: Event) -> io::Result<()> {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        let watch = *self.watch.lock().unwrap();
+        if !watch.enable {
+            return Ok(());
+        }
+
+        match event {
+            Event::Sentence(line) if watch.nmea => {
+                self.stream.lock().unwrap().write_all(line.as_bytes())
+            }
+
+            Event::Report(report) if watch.json => {
+                let path = device_path(&self.feed.device());
+                self.send(&tpv(&path, &report.fix))?;
+                self.send(&sky(&path, &report.fix, &report.sky))
+            }
+
+            _ => Ok(()),
+        }
+    }
+
+    fn devices(&self) -> Value {
+        json!({
+            "class": "DEVICES",
+            "devices": [device_json(&self.feed.device(), self.activated)],
+        })
+    }
+
+    fn send(&self, object: &Value) -> io::Result<()> {
+        let mut line = object.to_string();
+        line.push_str("\r\n");
+
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        self.stream.lock().unwrap().write_all(line.as_bytes())
+    }
+}
+
+fn version() -> Value {
+    json!({
+        "class": "VERSION",
+        "release": env!("CARGO_PKG_VERSION"),
+        "rev": concat!("gps-share ", env!("CARGO_PKG_VERSION")),
+        "proto_major": PROTO_MAJOR,
+        "proto_minor": PROTO_MINOR,
+    })
+}
+
+fn error(message: &str) -> Value {
+    json!({
+        "class": "ERROR",
+        "message": message,
+    })
+}
+
+fn tpv(device: &str, fix: &Fix) -> Value {
+    let mode = match fix.mode {
+        Mode::Unknown if fix.has_position() => 2,
+        Mode::Unknown => 0,
+        Mode::NoFix => 1,
+        Mode::Fix2D => 2,
+        Mode::Fix3D => 3,
+    };
+    let mut tpv = Map::new();
+    tpv.insert("class".into(), "TPV".into());
+    tpv.insert("device".into(), device.into());
+    tpv.insert("mode".into(), mode.into());
+    if let Some(time) = fix.time {
+        tpv.insert("time".into(), fix::format_timestamp(time).into());
+    }
+    let status = match fix.quality {
+        Some(FixQuality::Dgps) => Some(2),
+        Some(FixQuality::Rtk) => Some(3),
+        Some(FixQuality::FloatRtk) => Some(4),
+        Some(FixQuality::Estimated) => Some(6),
+        Some(FixQuality::Simulation) => Some(8),
+        _ => None,
+    };
+    if let Some(status) = status {
+        tpv.insert("status".into(), status.into());
+    }
+
+    let mut insert = |key: &str, value: Option<f64>| {
+        if let Some(value) = value {
+            tpv.insert(key.into(), value.into());
+        }
+    };
+    if fix.has_position() {
+        insert("lat", fix.latitude);
+        insert("lon", fix.longitude);
+        insert("alt", fix.altitude);
+        insert("altMSL", fix.altitude);
+        insert(
+            "altHAE",
+            fix.altitude.zip(fix.geoid_separation).map(|(a, g)| a + g),
+        );
+        insert("geoidSep", fix.geoid_separation);
+    }
+    insert("speed", fix.speed);
+    insert("track", fix.track);
+    insert("climb", fix.climb);
+    insert("epx", fix.epx);
+    insert("epy", fix.epy);
+    insert("epv", fix.epv);
+
+    Value::Object(tpv)
+}
+
+fn sky(device: &str, fix: &Fix, sky: &Sky) -> Value {
+    let satellites: Vec<Value> = sky
+        .satellites
+        .iter()
+        .map(|sat| {
+            let mut s = Map::new();
+            s.insert("PRN".into(), sat.prn.into());
+            s.insert("gnssid".into(), gnss_id(sat.talker).into());
+            if let Some(el) = sat.elevation {
+                s.insert("el".into(), el.into());
+            }
+            if let Some(az) = sat.azimuth {
+                s.insert("az".into(), az.into());
+            }
+            if let Some(ss) = sat.snr {
+                s.insert("ss".into(), ss.into());
+            }
+            s.insert("used".into(), sat.used.into());
+
+            Value::Object(s)
+        })
+        .collect();
+
+    let mut object = Map::new();
+    object.insert("class".into(), "SKY".into());
+    object.insert("device".into(), device.into());
+    if let Some(time) = fix.time {
+        object.insert("time".into(), fix::format_timestamp(time).into());
+    }
+    for (key, value) in [("hdop", sky.hdop), ("vdop", sky.vdop), ("pdop", sky.pdop)] {
+        if let Some(value) = value {
+            object.insert(key.into(), value.into());
+        }
+    }
+    object.insert("nSat".into(), satellites.len().into());
+    object.insert(
+        "uSat".into(),
+        sky.satellites.iter().filter(|s| s.used).count().into(),
+    );
+    object.insert("satellites".into(), satellites.into());
+
+    Value::Object(object)
+}
+
+// gpsd follows the u-blox GNSS identifiers.
+fn gnss_id(talker: Talker) -> u8 {
+    match talker {
+        Talker::Gps | Talker::Combined => 0,
+        Talker::Galileo => 2,
+        Talker::BeiDou => 3,
+        Talker::Qzss => 5,
+        Talker::Glonass => 6,
+    }
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64())
+}
@@ -0,0 +1,320 @@
+/* vim: set et ts=4 sw=4: */
+/* gpsd_client.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Reading from a gpsd instance that owns the receiver, rather than fighting it for the device.
+//!
+//! gpsd is asked for the NMEA sentences it gets (or makes up, for binary protocols), or for its
+//! JSON reports, which are turned into our own.
+
+use crate::fix::{self, Fix, Mode, Report, Satellite, Sky};
+use crate::gps::{GPS, READ_TIMEOUT};
+use crate::network;
+use crate::nmea::{self, FixQuality, Talker};
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+
+const SCHEME: &str = "gpsd://";
+const DEFAULT_HOST: &str = "localhost";
+const DEFAULT_PORT: u16 = 2947;
+
+/// Whether `path` stands for a gpsd instance, `gpsd://[HOST][:PORT][/json]`.
+pub fn is_gpsd(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with(SCHEME))
+}
+
+// The `host:port` of gpsd, and whether to ask it for JSON reports rather than NMEA.
+fn parse_address(path: &Path) -> Result<(String, bool), String> {
+    let address = path
+        .to_str()
+        .and_then(|path| path.strip_prefix(SCHEME))
+        .ok_or_else(|| format!("{} is not a gpsd address", path.display()))?;
+    let (authority, json) = match address.split_once('/') {
+        Some((authority, "json")) => (authority, true),
+        Some((_, what)) => return Err(format!("can't ask gpsd for `{}`", what)),
+        None => (address, false),
+    };
+
+    let has_port = authority
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    let authority = match authority {
+        "" => format!("{}:{}", DEFAULT_HOST, DEFAULT_PORT),
+        a if a.starts_with(':') => format!("{}{}", DEFAULT_HOST, a),
+        a if has_port => a.to_string(),
+        a => format!("{}:{}", a, DEFAULT_PORT),
+    };
+
+    Ok((authority, json))
+}
+
+// The parts of TPV reports we use.
+#[derive(Deserialize)]
+struct Tpv {
+    time: Option<String>,
+    #[serde(default)]
+    mode: u8,
+    status: Option<u8>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    #[serde(rename = "altMSL")]
+    alt_msl: Option<f64>,
+    alt: Option<f64>,
+    #[serde(rename = "geoidSep")]
+    geoid_sep: Option<f64>,
+    speed: Option<f64>,
+    track: Option<f64>,
+    climb: Option<f64>,
+    epx: Option<f64>,
+    epy: Option<f64>,
+    epv: Option<f64>,
+}
+
+// The parts of SKY reports we use.
+#[derive(Deserialize)]
+struct SkyReport {
+    satellites: Option<Vec<SkySatellite>>,
+    hdop: Option<f64>,
+    vdop: Option<f64>,
+    pdop: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct SkySatellite {
+    #[serde(rename = "PRN")]
+    prn: u16,
+    gnssid: Option<u8>,
+    el: Option<f64>,
+    az: Option<f64>,
+    ss: Option<f64>,
+    #[serde(default)]
+    used: bool,
+}
+
+pub struct GpsdClient {
+    reader: BufReader<TcpStream>,
+    // What came of the line being read, which reads timing out can leave unfinished.
+    line: String,
+    address: PathBuf,
+    // SKY reports come separately, at their own pace.
+    sky: Sky,
+    report: Option<Report>,
+}
+
+impl GpsdClient {
+    /// Connects to the gpsd instance of `path` and starts watching its devices.
+    pub fn connect(path: &Path) -> io::Result<Self> {
+        let (address, json) =
+            parse_address(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut stream = network::connect_tcp(&address)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+        let watch = if json {
+            "?WATCH={\"enable\":true,\"json\":true};\n"
+        } else {
+            "?WATCH={\"enable\":true,\"nmea\":true};\n"
+        };
+        stream.write_all(watch.as_bytes())?;
+
+        Ok(GpsdClient {
+            reader: BufReader::new(stream),
+            line: String::new(),
+            address: path.to_path_buf(),
+            sky: Sky::default(),
+            report: None,
+        })
+    }
+
+    // Returns whether `line` made a report.
+    fn handle_json(&mut self, line: &str) -> bool {
+        let object: Value = match serde_json::from_str(line) {
+            Ok(object) => object,
+            Err(_) => return false,
+        };
+
+        match object["class"].as_str() {
+            Some("TPV") => match Tpv::deserialize(&object) {
+                Ok(tpv) => {
+                    self.report = Some(self.make_report(&tpv));
+
+                    true
+                }
+                Err(_) => false,
+            },
+
+            Some("SKY") => {
+                if let Ok(sky) = SkyReport::deserialize(&object) {
+                    self.update_sky(sky);
+                }
+
+                false
+            }
+
+            Some("ERROR") => {
+                println!("gpsd: {}", object["message"].as_str().unwrap_or("error"));
+
+                false
+            }
+
+            // VERSION, DEVICES, WATCH and the like.
+            _ => false,
+        }
+    }
+
+    fn update_sky(&mut self, report: SkyReport) {
+        // SKY reports with just the DOPs come in between those with the satellites.
+        if let Some(satellites) = report.satellites {
+            self.sky.satellites = satellites.iter().map(satellite).collect();
+        }
+        self.sky.hdop = report.hdop.or(self.sky.hdop);
+        self.sky.vdop = report.vdop.or(self.sky.vdop);
+        self.sky.pdop = report.pdop.or(self.sky.pdop);
+    }
+
+    fn make_report(&self, tpv: &Tpv) -> Report {
+        let mut fix = Fix {
+            time: tpv.time.as_deref().and_then(parse_timestamp),
+            mode: match tpv.mode {
+                1 => Mode::NoFix,
+                2 => Mode::Fix2D,
+                3 => Mode::Fix3D,
+                _ => Mode::Unknown,
+            },
+            speed: tpv.speed,
+            track: tpv.track,
+            climb: tpv.climb,
+            epx: tpv.epx,
+            epy: tpv.epy,
+            epv: tpv.epv,
+            ..Fix::default()
+        };
+        let used = self.sky.satellites.iter().filter(|s| s.used).count();
+        fix.satellites_used = u8::try_from(used).ok();
+
+        if matches!(fix.mode, Mode::Fix2D | Mode::Fix3D) {
+            fix.quality = Some(match tpv.status {
+                Some(2) => FixQuality::Dgps,
+                Some(3) => FixQuality::Rtk,
+                Some(4) => FixQuality::FloatRtk,
+                Some(5 | 6) => FixQuality::Estimated,
+                Some(7) => FixQuality::Manual,
+                Some(8) => FixQuality::Simulation,
+                _ => FixQuality::Gps,
+            });
+            fix.latitude = tpv.lat;
+            fix.longitude = tpv.lon;
+            fix.altitude = tpv.alt_msl.or(tpv.alt);
+            fix.geoid_separation = tpv.geoid_sep;
+        } else if fix.mode == Mode::NoFix {
+            fix.quality = Some(FixQuality::Invalid);
+        }
+
+        Report {
+            fix,
+            sky: self.sky.clone(),
+        }
+    }
+}
+
+// gpsd follows the u-blox GNSS identifiers, and numbers satellites like NMEA does.
+fn satellite(satellite: &SkySatellite) -> Satellite {
+    let talker = match satellite.gnssid {
+        Some(2) => Talker::Galileo,
+        Some(3) => Talker::BeiDou,
+        Some(5) => Talker::Qzss,
+        Some(6) => Talker::Glonass,
+        _ => Talker::Gps,
+    };
+
+    Satellite {
+        talker,
+        prn: satellite.prn,
+        elevation: satellite.el.map(|el| el.round() as i16),
+        azimuth: satellite.az.map(|az| az.round() as u16),
+        snr: satellite
+            .ss
+            .filter(|&ss| ss > 0.0)
+            .map(|ss| ss.round() as u8),
+        used: satellite.used,
+    }
+}
+
+// Parses the ISO 8601 times of gpsd, e.g `2024-03-01T12:35:19.000Z`.
+fn parse_timestamp(time: &str) -> Option<f64> {
+    let (date, time) = time.strip_suffix('Z')?.split_once('T')?;
+    let mut date = date.splitn(3, '-');
+    let date = nmea::Date {
+        year: date.next()?.parse().ok()?,
+        month: date.next()?.parse().ok()?,
+        day: date.next()?.parse().ok()?,
+    };
+    let mut time = time.splitn(3, ':');
+    let time = nmea::Time {
+        hour: time.next()?.parse().ok()?,
+        minute: time.next()?.parse().ok()?,
+        second: time.next()?.parse().ok()?,
+    };
+
+    Some(fix::timestamp(&date, &time))
+}
+
+impl GPS for GpsdClient {
+    fn read_line(&mut self, buffer: &mut String) -> io::Result<usize> {
+        loop {
+            if self.reader.read_line(&mut self.line)? == 0 {
+                return Ok(0);
+            }
+            if !self.line.ends_with('\n') {
+                continue;
+            }
+
+            let line = std::mem::take(&mut self.line);
+            let len = line.len();
+            if !line.starts_with('{') {
+                buffer.push_str(&line);
+
+                return Ok(len);
+            }
+            if self.handle_json(&line) {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "gpsd report decoded",
+                ));
+            }
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "gpsd"
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.address)
+    }
+
+    fn take_report(&mut self) -> Option<Report> {
+        self.report.take()
+    }
+}
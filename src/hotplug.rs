@@ -0,0 +1,245 @@
+/* vim: set et ts=4 sw=4: */
+/* hotplug.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+use crate::discovery::Registry;
+use crate::fix::Report;
+use crate::gps::GPS;
+use crate::reconnect::{INITIAL_BACKOFF, MAX_BACKOFF, is_fatal, notice};
+use crate::ubx;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// How long to wait for udev at a time, so that the feed gets to check for device switches.
+const WAIT_TIMEOUT_MS: i32 = 1_000;
+
+/// Watches udev for GPS devices, picking one up when it's plugged in and letting go of it when
+/// it's removed. This way we can be started before there's any device around.
+///
+/// While there's no device, we also look for one every now and then, backing off, as not every
+/// device shows up in udev (e.g network ones), and one that failed may still be there.
+///
+/// Clients are told about it through `$GPTXT` sentences, like with `Reconnecting`.
+pub struct Hotplug {
+    monitor: udev::MonitorSocket,
+    gps: Option<Box<dyn GPS>>,
+    registry: Registry,
+    baudrate: u32,
+    // When to look for a device next, and how long to wait until the time after.
+    rescan: (Instant, Duration),
+}
+
+impl Hotplug {
+    /// Starts watching, and takes any GPS device that's already around.
+    pub fn new(registry: Registry, baudrate: u32) -> io::Result<Self> {
+        // Listen first so that nothing plugged in during the scan is missed.
+        let mut builder = udev::MonitorBuilder::new()?;
+        for subsystem in registry.subsystems() {
+            builder = builder.match_subsystem(subsystem)?;
+        }
+        let monitor = builder.listen()?;
+
+        let gps = match registry.detect(baudrate) {
+            Ok(gps) => Some(gps),
+
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                println!("{}, waiting for one to be plugged in..", e);
+
+                None
+            }
+
+            Err(e) => return Err(e),
+        };
+
+        Ok(Hotplug {
+            monitor,
+            gps,
+            registry,
+            baudrate,
+            rescan: (Instant::now() + INITIAL_BACKOFF, INITIAL_BACKOFF),
+        })
+    }
+
+    // Handles what happened since we last looked, returning a notice for clients if that
+    // changed our device.
+    fn handle_events(&mut self, buffer: &mut String) -> Option<usize> {
+        let mut notice_len = None;
+
+        let events: Vec<udev::Event> = self.monitor.iter().collect();
+        for event in events {
+            match event.event_type() {
+                udev::EventType::Add if self.gps.is_none() => {
+                    if let Some(gps) = self.registry.probe(&event, self.baudrate) {
+                        self.gps = Some(gps);
+
+                        notice_len = Some(notice(buffer, "03", "GPS device plugged in"));
+                    }
+                }
+
+                udev::EventType::Remove if self.is_current(event.devnode()) => {
+                    notice_len = Some(self.release(&"device removed", buffer));
+                }
+
+                _ => {}
+            }
+        }
+
+        notice_len
+    }
+
+    fn is_current(&self, devnode: Option<&Path>) -> bool {
+        match (&self.gps, devnode) {
+            (Some(gps), Some(devnode)) => gps.path() == Some(devnode),
+            _ => false,
+        }
+    }
+
+    fn release(&mut self, reason: &dyn std::fmt::Display, buffer: &mut String) -> usize {
+        println!(
+            "GPS device lost ({}), waiting for one to be plugged in..",
+            reason
+        );
+        self.gps = None;
+        self.rescan = (Instant::now() + INITIAL_BACKOFF, INITIAL_BACKOFF);
+
+        notice(buffer, "01", "GPS device lost")
+    }
+
+    // Looks for a device if it's time to, returning a notice for clients if there's one.
+    fn rescan(&mut self, buffer: &mut String) -> Option<usize> {
+        let (at, backoff) = self.rescan;
+        if Instant::now() < at {
+            return None;
+        }
+
+        match self.registry.detect(self.baudrate) {
+            Ok(gps) => {
+                self.gps = Some(gps);
+
+                Some(notice(buffer, "03", "GPS device found"))
+            }
+
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    println!("Failed to look for a GPS device: {}", e);
+                }
+                let backoff = (backoff * 2).min(MAX_BACKOFF);
+                self.rescan = (Instant::now() + backoff, backoff);
+
+                None
+            }
+        }
+    }
+
+    fn current(&mut self) -> io::Result<&mut Box<dyn GPS>> {
+        self.gps
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "no GPS device plugged in"))
+    }
+
+    fn wait(&self) -> io::Result<()> {
+        let mut fds = [libc::pollfd {
+            fd: self.monitor.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+
+        // SAFETY: `fds` is a valid array of one `pollfd`, whose descriptor outlives the call.
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), 1, WAIT_TIMEOUT_MS) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+impl GPS for Hotplug {
+    fn read_line(&mut self, buffer: &mut String) -> io::Result<usize> {
+        if let Some(len) = self.handle_events(buffer) {
+            return Ok(len);
+        }
+
+        let gps = match self.gps {
+            Some(ref mut gps) => gps,
+
+            None => {
+                if let Some(len) = self.rescan(buffer) {
+                    return Ok(len);
+                }
+                self.wait()?;
+
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "no GPS device plugged in",
+                ));
+            }
+        };
+
+        match gps.read_line(buffer) {
+            Ok(0) => Ok(self.release(&"end of stream", buffer)),
+
+            Err(e) if is_fatal(&e) => Ok(self.release(&e, buffer)),
+
+            result => result,
+        }
+    }
+
+    // `none` while there's no device.
+    fn kind(&self) -> &'static str {
+        self.gps.as_ref().map_or("none", |gps| gps.kind())
+    }
+
+    fn path(&self) -> Option<&Path> {
+        self.gps.as_ref().and_then(|gps| gps.path())
+    }
+
+    fn baudrate(&self) -> Option<u32> {
+        self.gps.as_ref().and_then(|gps| gps.baudrate())
+    }
+
+    fn take_report(&mut self) -> Option<Report> {
+        self.gps.as_mut().and_then(|gps| gps.take_report())
+    }
+
+    fn take_ack(&mut self) -> Option<ubx::Ack> {
+        self.gps.as_mut().and_then(|gps| gps.take_ack())
+    }
+
+    fn take_version(&mut self) -> Option<ubx::Version> {
+        self.gps.as_mut().and_then(|gps| gps.take_version())
+    }
+
+    fn take_rtcm(&mut self) -> Option<Vec<u8>> {
+        self.gps.as_mut().and_then(|gps| gps.take_rtcm())
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.current()?.write(data)
+    }
+
+    fn set_baudrate(&mut self, baudrate: u32) -> io::Result<()> {
+        self.current()?.set_baudrate(baudrate)
+    }
+}
@@ -21,24 +21,51 @@
  * Author: Zeeshan Ali <zeeshanak@gnome.org>
  */
 
+mod allowlist;
 mod avahi;
+mod broadcast;
+mod caster;
 mod client_handler;
+mod clients;
 mod cmdline_config;
 mod config;
+mod dbus;
+mod discovery;
+mod feed;
+mod file_config;
+mod filter;
+mod fix;
 mod gnss;
 mod gps;
+mod gpsd;
+mod gpsd_client;
+mod hotplug;
+mod modem_manager;
+mod network;
+mod nmea;
+mod ntrip;
+mod receiver;
+mod reconnect;
 mod rs232;
+mod rtcm;
 mod server;
+mod sirf;
 mod stdin_gps;
+mod synthesis;
+mod ubx;
+mod udp;
+mod verify;
 
+use crate::allowlist::Allowlist;
 use crate::config::Config;
-use crate::gnss::GNSS;
+use crate::discovery::Registry;
 use crate::gps::GPS;
-use crate::rs232::RS232;
+use crate::hotplug::Hotplug;
+use crate::reconnect::Reconnecting;
 use crate::server::Server;
-use crate::stdin_gps::StdinGPS;
 use signal_hook::consts as signals;
 use std::io;
+use std::path::Path;
 use std::sync::mpsc;
 use std::thread;
 
@@ -66,79 +93,107 @@ fn main() {
     let config = cmdline_config::config_from_cmdline();
 
     let (sdone, rdone) = mpsc::channel();
-    notify(&[signals::SIGINT, signals::SIGTERM], sdone.clone()).unwrap();
+    notify(
+        &[signals::SIGINT, signals::SIGTERM, signals::SIGHUP],
+        sdone.clone(),
+    )
+    .unwrap();
+
+    let (sreload, rreload) = mpsc::channel();
+    thread::spawn(move || run(sdone, rreload, Rc::new(config)));
+
+    loop {
+        match rdone.recv().unwrap() {
+            DoneReason::Signal(signals::SIGHUP) => {
+                println!("Hangup signal received. Reloading configuration..");
+
+                match cmdline_config::reload_config() {
+                    Ok(config) => {
+                        // The server is gone if this fails, and we'll hear about it.
+                        let _ = sreload.send(config);
+                    }
+                    Err(e) => println!("{}. Keeping the current configuration.", e),
+                }
 
-    thread::spawn(move || run(sdone, Rc::new(config)));
+                continue;
+            }
 
-    match rdone.recv().unwrap() {
-        DoneReason::Signal(signals::SIGINT) => {
-            println!("Interrupt from keyboard. Exitting..");
-        }
+            DoneReason::Signal(signals::SIGINT) => {
+                println!("Interrupt from keyboard. Exitting..");
+            }
 
-        DoneReason::Signal(signals::SIGTERM) => {
-            println!("Kill signal received. Exitting..");
-        }
+            DoneReason::Signal(signals::SIGTERM) => {
+                println!("Kill signal received. Exitting..");
+            }
 
-        DoneReason::Signal(_) => (),
+            DoneReason::Signal(_) => (),
 
-        DoneReason::Success => {
-            println!("Program completed normally.");
-        }
-    };
+            DoneReason::Success => {
+                println!("Program completed normally.");
+            }
+        };
+
+        break;
+    }
 }
 
-fn run(sdone: mpsc::Sender<DoneReason>, config: Rc<Config>) {
+fn run(sdone: mpsc::Sender<DoneReason>, reloads: mpsc::Receiver<Config>, config: Rc<Config>) {
     let gps = get_gps(config.clone());
 
-    run_server_handle_err(gps, config.clone());
+    run_server_handle_err(gps, reloads, config.clone());
     sdone.send(DoneReason::Success).unwrap();
 }
 
 fn get_gps(config: Rc<Config>) -> Box<dyn GPS> {
-    if let Some(ref path) = config.dev_path {
-        if path.to_str() == Some("-") {
-            return Box::new(StdinGPS::new());
-        }
-    }
+    let baudrate = config.baudrate;
+    let gps = match config.dev_path {
+        Some(ref path) => gps::open(path, baudrate).map(|gps| reconnecting(gps, path, baudrate)),
+        None => {
+            let registry = Registry::builtin(Allowlist::new(&config), config.discover_network);
 
-    // FIXME: the discovery part should be separated from the RS232 module so that adding
-    //  more devices doesn't get even more convoluted.
-    match RS232::new(config.clone()) {
-        Ok(rs232) => return Box::new(rs232),
+            Hotplug::new(registry, baudrate).map(|hotplug| Box::new(hotplug) as Box<dyn GPS>)
+        }
+    };
 
-        Err(e) => match e.kind() {
-            ::std::io::ErrorKind::NotFound => match GNSS::new(config.clone()) {
-                Ok(gnss) => return Box::new(gnss),
+    match gps {
+        Ok(gps) => gps,
 
-                Err(e) => {
-                    match e.kind() {
-                        ::std::io::ErrorKind::NotFound => println!("{}", e),
+        Err(e) => {
+            match e.kind() {
+                ::std::io::ErrorKind::NotFound => println!("{}", e),
 
-                        _ => println!("Failed to open GNSS device: {}", e),
-                    }
+                _ => println!("Failed to open GPS device: {}", e),
+            }
 
-                    std::process::exit(1);
-                }
-            },
+            std::process::exit(1);
+        }
+    }
+}
 
-            _ => {
-                println!("Failed to open serial device: {}", e);
-                std::process::exit(1)
-            }
-        },
+fn reconnecting(gps: Box<dyn GPS>, path: &Path, baudrate: u32) -> Box<dyn GPS> {
+    // There's no getting standard input back.
+    if gps.kind() == "stdin" {
+        return gps;
     }
+
+    let path = path.to_path_buf();
+    Box::new(Reconnecting::new(gps, move || gps::open(&path, baudrate)))
 }
 
-fn run_server_handle_err(gps: Box<dyn GPS>, config: Rc<Config>) {
-    if let Err(e) = run_server(gps, config) {
+fn run_server_handle_err(gps: Box<dyn GPS>, reloads: mpsc::Receiver<Config>, config: Rc<Config>) {
+    if let Err(e) = run_server(gps, reloads, config) {
         println!("Failed to start TCP service: {}", e);
 
         std::process::exit(2);
     }
 }
 
-fn run_server(gps: Box<dyn GPS>, config: Rc<Config>) -> ::std::io::Result<()> {
+fn run_server(
+    gps: Box<dyn GPS>,
+    reloads: mpsc::Receiver<Config>,
+    config: Rc<Config>,
+) -> ::std::io::Result<()> {
     let mut server = Server::new(gps, config)?;
 
-    server.run()
+    server.run(reloads)
 }
@@ -0,0 +1,241 @@
+/* vim: set et ts=4 sw=4: */
+/* modem_manager.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Reading the GNSS of cellular modems, which ModemManager owns rather than leaving a free tty.
+//!
+//! The modem's NMEA and raw location sources are enabled through
+//! `org.freedesktop.ModemManager1.Modem.Location`, and the sentences of the NMEA trace it keeps
+//! are sent on each time it signals an update of the location.
+
+use crate::gps::{GPS, READ_TIMEOUT};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use zbus::blocking::Connection;
+use zbus::blocking::fdo::ObjectManagerProxy;
+use zbus::proxy;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+
+const SCHEME: &str = "modemmanager://";
+
+const SERVICE: &str = "org.freedesktop.ModemManager1";
+const PATH: &str = "/org/freedesktop/ModemManager1";
+const MODEM_PATH: &str = "/org/freedesktop/ModemManager1/Modem";
+const LOCATION_INTERFACE: &str = "org.freedesktop.ModemManager1.Modem.Location";
+
+// MMModemLocationSource flags.
+const SOURCE_GPS_RAW: u32 = 1 << 1;
+const SOURCE_GPS_NMEA: u32 = 1 << 2;
+
+#[proxy(
+    interface = "org.freedesktop.ModemManager1.Modem.Location",
+    default_service = "org.freedesktop.ModemManager1",
+    gen_async = false
+)]
+trait Location {
+    fn setup(&self, sources: u32, signal_location: bool) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn capabilities(&self) -> zbus::Result<u32>;
+    #[zbus(property)]
+    fn enabled(&self) -> zbus::Result<u32>;
+    #[zbus(property)]
+    fn signals_location(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn location(&self) -> zbus::Result<HashMap<u32, OwnedValue>>;
+}
+
+/// Whether `path` stands for a ModemManager modem, `modemmanager://[MODEM]`.
+pub fn is_modem_manager(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with(SCHEME))
+}
+
+pub struct ModemManager {
+    path: PathBuf,
+    // The NMEA trace of each location update, as ModemManager signals them.
+    traces: Receiver<String>,
+    lines: VecDeque<String>,
+}
+
+impl ModemManager {
+    /// Sets up the location sources of the modem `path` names (by its index, as `mmcli -m`
+    /// does), or of the first one with a GNSS if it doesn't name any.
+    pub fn connect(path: &Path) -> io::Result<Self> {
+        let modem = path
+            .to_str()
+            .and_then(|path| path.strip_prefix(SCHEME))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a ModemManager modem", path.display()),
+                )
+            })?;
+
+        let connection = Connection::system().map_err(io::Error::other)?;
+        let modem = find_modem(&connection, modem)?;
+        let location = LocationProxy::builder(&connection)
+            .path(modem)
+            .and_then(|builder| builder.build())
+            .map_err(io::Error::other)?;
+        setup(&location)?;
+
+        let (sender, traces) = mpsc::channel();
+        let updates = location.receive_location_changed();
+        thread::spawn(move || {
+            // Each change is an update of the location, even if its sentences are the same as
+            // the last ones, as they are while standing still.
+            for update in updates {
+                let location = match update.get() {
+                    Ok(location) => location,
+                    Err(e) => {
+                        println!("Failed to read the location from ModemManager: {}", e);
+
+                        continue;
+                    }
+                };
+                let trace = match location.get(&SOURCE_GPS_NMEA) {
+                    Some(value) => match <&str>::try_from(value) {
+                        Ok(trace) => trace.to_string(),
+                        Err(_) => continue,
+                    },
+                    None => continue,
+                };
+
+                if sender.send(trace).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(ModemManager {
+            path: path.to_path_buf(),
+            traces,
+            lines: VecDeque::new(),
+        })
+    }
+}
+
+fn find_modem(connection: &Connection, modem: &str) -> io::Result<OwnedObjectPath> {
+    if !modem.is_empty() {
+        let index: u32 = modem.parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid modem `{}` (expected its index)", modem),
+            )
+        })?;
+
+        return OwnedObjectPath::try_from(format!("{}/{}", MODEM_PATH, index))
+            .map_err(io::Error::other);
+    }
+
+    let objects = ObjectManagerProxy::builder(connection)
+        .destination(SERVICE)
+        .and_then(|builder| builder.path(PATH))
+        .and_then(|builder| builder.build())
+        .map_err(io::Error::other)?
+        .get_managed_objects()
+        .map_err(io::Error::other)?;
+    let mut modems: Vec<_> = objects
+        .into_iter()
+        .filter(|(_, interfaces)| {
+            interfaces
+                .keys()
+                .any(|name| name.as_str() == LOCATION_INTERFACE)
+        })
+        .map(|(path, _)| path)
+        .collect();
+    // The lowest index first, as the first modem is the likeliest to be built in.
+    modems.sort_by_key(|path| {
+        path.rsplit('/')
+            .next()
+            .and_then(|index| index.parse::<u32>().ok())
+    });
+
+    modems.into_iter().next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "ModemManager doesn't know of any modem with location support",
+        )
+    })
+}
+
+// Enables the GNSS sources on top of those already enabled, and has the location signalled.
+fn setup(location: &LocationProxy) -> io::Result<()> {
+    let capabilities = location.capabilities().map_err(io::Error::other)?;
+    if capabilities & SOURCE_GPS_NMEA == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the modem can't send NMEA",
+        ));
+    }
+
+    let enabled = location.enabled().map_err(io::Error::other)?;
+    let signals = location.signals_location().map_err(io::Error::other)?;
+    let sources = enabled | (capabilities & (SOURCE_GPS_NMEA | SOURCE_GPS_RAW));
+    if sources != enabled || !signals {
+        location.setup(sources, true).map_err(io::Error::other)?;
+    }
+
+    Ok(())
+}
+
+impl GPS for ModemManager {
+    fn read_line(&mut self, buffer: &mut String) -> io::Result<usize> {
+        if self.lines.is_empty() {
+            match self.traces.recv_timeout(READ_TIMEOUT) {
+                Ok(trace) => self.lines.extend(
+                    trace
+                        .lines()
+                        .map(str::trim_end)
+                        .filter(|line| !line.is_empty())
+                        .map(|line| format!("{}\r\n", line)),
+                ),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+
+        match self.lines.pop_front() {
+            Some(line) => {
+                buffer.push_str(&line);
+
+                Ok(line.len())
+            }
+
+            None => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "no new sentences from ModemManager",
+            )),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "ModemManager"
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
@@ -0,0 +1,179 @@
+/* vim: set et ts=4 sw=4: */
+/* network.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Reading NMEA from the network, e.g from another gps-share or any NMEA-over-TCP feed.
+
+use crate::fix::Report;
+use crate::gps::{GPS, READ_TIMEOUT};
+use crate::ubx;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.read(buf),
+            Connection::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.write(buf),
+            Connection::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.flush(),
+            Connection::Unix(s) => s.flush(),
+        }
+    }
+}
+
+/// Whether `path` stands for a network source rather than a device: a Unix socket, or a TCP
+/// `host:port`.
+pub fn is_network(path: &Path) -> bool {
+    is_socket(path) || tcp_address(path).is_some()
+}
+
+fn is_socket(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.file_type().is_socket())
+}
+
+fn tcp_address(path: &Path) -> Option<&str> {
+    let address = path.to_str()?;
+    if address.contains('/') {
+        return None;
+    }
+    let (host, port) = address.rsplit_once(':')?;
+    if host.is_empty() || port.parse::<u16>().is_err() {
+        return None;
+    }
+
+    Some(address)
+}
+
+pub struct Network {
+    reader: BufReader<Connection>,
+    address: PathBuf,
+    ubx: ubx::Decoder,
+}
+
+impl Network {
+    /// Connects to the Unix socket at `path`, or to the TCP `host:port` it is.
+    pub fn connect(path: &Path) -> io::Result<Self> {
+        let connection = if is_socket(path) {
+            let stream = UnixStream::connect(path)?;
+            stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+            Connection::Unix(stream)
+        } else {
+            let address = tcp_address(path).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a host:port address", path.display()),
+                )
+            })?;
+            let stream = connect_tcp(address)?;
+            stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+            Connection::Tcp(stream)
+        };
+
+        Ok(Network {
+            reader: BufReader::new(connection),
+            address: path.to_path_buf(),
+            ubx: ubx::Decoder::default(),
+        })
+    }
+}
+
+/// Connects to `host:port`, trying each address the host resolves to in turn.
+pub fn connect_tcp(address: &str) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} didn't resolve to any address", address),
+    );
+
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+impl GPS for Network {
+    fn read_line(&mut self, buffer: &mut String) -> io::Result<usize> {
+        self.ubx.read_line(&mut self.reader, buffer)
+    }
+
+    fn kind(&self) -> &'static str {
+        "network"
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.address)
+    }
+
+    fn take_report(&mut self) -> Option<Report> {
+        self.ubx.take_report()
+    }
+
+    fn take_ack(&mut self) -> Option<ubx::Ack> {
+        self.ubx.take_ack()
+    }
+
+    fn take_version(&mut self) -> Option<ubx::Version> {
+        self.ubx.take_version()
+    }
+
+    fn take_rtcm(&mut self) -> Option<Vec<u8>> {
+        self.ubx.take_rtcm()
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let connection = self.reader.get_mut();
+        connection.write_all(data)?;
+
+        connection.flush()
+    }
+}
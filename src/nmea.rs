@@ -0,0 +1,1411 @@
+/* vim: set et ts=4 sw=4: */
+/* nmea.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Parser for the NMEA 0183 sentences GPS receivers send.
+
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The sentence doesn't start with `$`.
+    MissingStart,
+    /// The address field is too short to contain a talker ID and a sentence type.
+    InvalidAddress(String),
+    /// The sentence doesn't end with a `*hh` checksum.
+    MissingChecksum,
+    /// The checksum isn't two hex digits.
+    InvalidChecksum(String),
+    /// The checksum doesn't match the contents of the sentence.
+    ChecksumMismatch {
+        expected: u8,
+        found: u8,
+    },
+    UnknownTalker(String),
+    UnsupportedSentence(String),
+    MissingField {
+        sentence: &'static str,
+        field: &'static str,
+    },
+    InvalidField {
+        sentence: &'static str,
+        field: &'static str,
+        value: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingStart => f.write_str("sentence doesn't start with `$`"),
+            Error::InvalidAddress(a) => write!(f, "invalid address field `{}`", a),
+            Error::MissingChecksum => f.write_str("sentence has no checksum"),
+            Error::InvalidChecksum(c) => write!(f, "invalid checksum `{}`", c),
+            Error::ChecksumMismatch { expected, found } => write!(
+                f,
+                "checksum mismatch: expected {:02X}, found {:02X}",
+                expected, found
+            ),
+            Error::UnknownTalker(t) => write!(f, "unknown talker ID `{}`", t),
+            Error::UnsupportedSentence(s) => write!(f, "unsupported sentence type `{}`", s),
+            Error::MissingField { sentence, field } => {
+                write!(f, "{}: missing {} field", sentence, field)
+            }
+            Error::InvalidField {
+                sentence,
+                field,
+                value,
+            } => write!(f, "{}: invalid {} `{}`", sentence, field, value),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// The system a sentence originates from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Talker {
+    /// `GP`
+    Gps,
+    /// `GL`
+    Glonass,
+    /// `GA`
+    Galileo,
+    /// `GB` or `BD`
+    BeiDou,
+    /// `QZ`
+    Qzss,
+    /// `GN`, i-e a solution combining several systems.
+    Combined,
+}
+
+impl Talker {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Talker::Gps => "GP",
+            Talker::Glonass => "GL",
+            Talker::Galileo => "GA",
+            Talker::BeiDou => "GB",
+            Talker::Qzss => "QZ",
+            Talker::Combined => "GN",
+        }
+    }
+}
+
+impl FromStr for Talker {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "GP" => Ok(Talker::Gps),
+            "GL" => Ok(Talker::Glonass),
+            "GA" => Ok(Talker::Galileo),
+            "GB" | "BD" => Ok(Talker::BeiDou),
+            "QZ" => Ok(Talker::Qzss),
+            "GN" => Ok(Talker::Combined),
+            _ => Err(Error::UnknownTalker(s.to_string())),
+        }
+    }
+}
+
+/// UTC time of day.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+/// The GGA fix quality indicator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FixQuality {
+    Invalid,
+    Gps,
+    Dgps,
+    Pps,
+    Rtk,
+    FloatRtk,
+    Estimated,
+    Manual,
+    Simulation,
+}
+
+/// The FAA mode indicator added in NMEA 2.3.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Autonomous,
+    Differential,
+    Estimated,
+    FloatRtk,
+    Manual,
+    NotValid,
+    Precise,
+    Rtk,
+    Simulator,
+}
+
+impl Mode {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            'A' => Some(Mode::Autonomous),
+            'D' => Some(Mode::Differential),
+            'E' => Some(Mode::Estimated),
+            'F' => Some(Mode::FloatRtk),
+            'M' => Some(Mode::Manual),
+            'N' => Some(Mode::NotValid),
+            'P' => Some(Mode::Precise),
+            'R' => Some(Mode::Rtk),
+            'S' => Some(Mode::Simulator),
+            _ => None,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        !matches!(self, Mode::NotValid)
+    }
+}
+
+/// The GSA fix type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FixType {
+    NoFix,
+    Fix2D,
+    Fix3D,
+}
+
+/// Global Positioning System fix data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gga {
+    pub talker: Talker,
+    pub time: Option<Time>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub quality: FixQuality,
+    pub satellites: Option<u8>,
+    pub hdop: Option<f64>,
+    /// Altitude above mean sea level, in meters.
+    pub altitude: Option<f64>,
+    /// Height of the geoid above the WGS84 ellipsoid, in meters.
+    pub geoid_separation: Option<f64>,
+    pub dgps_age: Option<f64>,
+    pub dgps_station: Option<u16>,
+}
+
+/// Recommended minimum specific GNSS data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rmc {
+    pub talker: Talker,
+    pub time: Option<Time>,
+    pub valid: bool,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub speed_knots: Option<f64>,
+    /// Course over ground, in degrees from true north.
+    pub course: Option<f64>,
+    pub date: Option<Date>,
+    pub magnetic_variation: Option<f64>,
+    pub mode: Option<Mode>,
+}
+
+/// GNSS DOP and active satellites.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gsa {
+    pub talker: Talker,
+    pub automatic: bool,
+    pub fix_type: FixType,
+    /// PRNs of the satellites used in the solution.
+    pub satellites: Vec<u16>,
+    pub pdop: Option<f64>,
+    pub hdop: Option<f64>,
+    pub vdop: Option<f64>,
+    /// The GNSS system ID, added in NMEA 4.1.
+    pub system_id: Option<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SatelliteInfo {
+    pub prn: u16,
+    /// Elevation, in degrees.
+    pub elevation: Option<i16>,
+    /// Azimuth, in degrees from true north.
+    pub azimuth: Option<u16>,
+    /// Signal to noise ratio, in dB-Hz. Not set if the satellite isn't being tracked.
+    pub snr: Option<u8>,
+}
+
+/// GNSS satellites in view.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gsv {
+    pub talker: Talker,
+    pub total_messages: u8,
+    pub message_number: u8,
+    pub satellites_in_view: u16,
+    /// At most 4 satellites, the rest are in the other messages of the same sequence.
+    pub satellites: Vec<SatelliteInfo>,
+    /// The signal ID, added in NMEA 4.1.
+    pub signal_id: Option<u8>,
+}
+
+/// Course over ground and ground speed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vtg {
+    pub talker: Talker,
+    pub course_true: Option<f64>,
+    pub course_magnetic: Option<f64>,
+    pub speed_knots: Option<f64>,
+    pub speed_kmh: Option<f64>,
+    pub mode: Option<Mode>,
+}
+
+/// Geographic position, latitude and longitude.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gll {
+    pub talker: Talker,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub time: Option<Time>,
+    pub valid: bool,
+    pub mode: Option<Mode>,
+}
+
+/// GNSS fix data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gns {
+    pub talker: Talker,
+    pub time: Option<Time>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// One mode for each of GPS, GLONASS, Galileo, BeiDou and QZSS, in that order.
+    pub modes: Vec<Mode>,
+    pub satellites: Option<u8>,
+    pub hdop: Option<f64>,
+    pub altitude: Option<f64>,
+    pub geoid_separation: Option<f64>,
+    pub dgps_age: Option<f64>,
+    pub dgps_station: Option<u16>,
+}
+
+/// GNSS pseudorange error statistics. All errors are 1-sigma, in meters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gst {
+    pub talker: Talker,
+    pub time: Option<Time>,
+    pub rms: Option<f64>,
+    pub semi_major_error: Option<f64>,
+    pub semi_minor_error: Option<f64>,
+    /// Orientation of the semi-major axis of the error ellipse, in degrees from true north.
+    pub orientation: Option<f64>,
+    pub latitude_error: Option<f64>,
+    pub longitude_error: Option<f64>,
+    pub altitude_error: Option<f64>,
+}
+
+/// Time and date.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Zda {
+    pub talker: Talker,
+    pub time: Option<Time>,
+    pub date: Option<Date>,
+    pub local_zone_hours: Option<i8>,
+    pub local_zone_minutes: Option<u8>,
+}
+
+/// Text transmission.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Txt {
+    pub talker: Talker,
+    pub total_messages: u8,
+    pub message_number: u8,
+    pub identifier: u8,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sentence {
+    Gga(Gga),
+    Rmc(Rmc),
+    Gsa(Gsa),
+    Gsv(Gsv),
+    Vtg(Vtg),
+    Gll(Gll),
+    Gns(Gns),
+    Gst(Gst),
+    Zda(Zda),
+    Txt(Txt),
+}
+
+impl Sentence {
+    pub fn talker(&self) -> Talker {
+        match self {
+            Sentence::Gga(s) => s.talker,
+            Sentence::Rmc(s) => s.talker,
+            Sentence::Gsa(s) => s.talker,
+            Sentence::Gsv(s) => s.talker,
+            Sentence::Vtg(s) => s.talker,
+            Sentence::Gll(s) => s.talker,
+            Sentence::Gns(s) => s.talker,
+            Sentence::Gst(s) => s.talker,
+            Sentence::Zda(s) => s.talker,
+            Sentence::Txt(s) => s.talker,
+        }
+    }
+}
+
+/// XORs all characters of `data`, which is the part of the sentence between `$` and `*`.
+pub fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, b| sum ^ b)
+}
+
+/// Returns the sentence type of `line`, e.g `GGA` for `$GPGGA,...`.
+///
+/// Proprietary sentences (`$P...`) don't have a talker ID, so for them the whole address is
+/// returned.
+pub fn sentence_type(line: &str) -> Option<&str> {
+    let address = strip_tag_block(line)
+        .strip_prefix('$')?
+        .split([',', '*'])
+        .next()?;
+
+    if address.starts_with('P') {
+        Some(address)
+    } else {
+        address.get(2..).filter(|t| !t.is_empty())
+    }
+}
+
+/// Checks the `*hh` checksum at the end of `line`, which must be there.
+pub fn verify_checksum(line: &str) -> Result<(), Error> {
+    let (_, checksum) = split_checksum(line)?;
+
+    checksum.map(|_| ()).ok_or(Error::MissingChecksum)
+}
+
+/// Formats a sentence from `data`, the part between `$` and `*`, adding the checksum.
+pub fn with_checksum(data: &str) -> String {
+    format!("${}*{:02X}\r\n", data, checksum(data))
+}
+
+// Strips the NMEA 4.10 TAG block (`\\...*hh\\`) from the start of `line`, if there is one.
+fn strip_tag_block(line: &str) -> &str {
+    line.strip_prefix('\\')
+        .and_then(|rest| rest.split_once('\\'))
+        .map_or(line, |(_, sentence)| sentence)
+}
+
+// Splits `line` into the part between `$` and `*`, and the verified checksum, if any.
+fn split_checksum(line: &str) -> Result<(&str, Option<u8>), Error> {
+    let line = strip_tag_block(line).trim_end_matches(['\r', '\n']);
+    let data = line.strip_prefix('$').ok_or(Error::MissingStart)?;
+
+    match data.rsplit_once('*') {
+        Some((data, sum)) => {
+            let found = u8::from_str_radix(sum, 16)
+                .ok()
+                .filter(|_| sum.len() == 2)
+                .ok_or_else(|| Error::InvalidChecksum(sum.to_string()))?;
+            let expected = checksum(data);
+            if expected != found {
+                return Err(Error::ChecksumMismatch { expected, found });
+            }
+
+            Ok((data, Some(found)))
+        }
+        None => Ok((data, None)),
+    }
+}
+
+/// Parses a single sentence. Trailing line terminators are ignored and the checksum, if present,
+/// is verified.
+pub fn parse(line: &str) -> Result<Sentence, Error> {
+    let (data, _) = split_checksum(line)?;
+
+    let mut fields = data.split(',');
+    let address = fields.next().unwrap_or_default();
+    if address.len() != 5 || !address.is_ascii() {
+        return Err(Error::InvalidAddress(address.to_string()));
+    }
+    let (talker, sentence_type) = address.split_at(2);
+    if talker.starts_with('P') {
+        return Err(Error::UnsupportedSentence(address.to_string()));
+    }
+    let talker = talker.parse()?;
+
+    match sentence_type {
+        "GGA" => parse_gga(talker, Fields::new("GGA", fields)).map(Sentence::Gga),
+        "RMC" => parse_rmc(talker, Fields::new("RMC", fields)).map(Sentence::Rmc),
+        "GSA" => parse_gsa(talker, Fields::new("GSA", fields)).map(Sentence::Gsa),
+        "GSV" => parse_gsv(talker, Fields::new("GSV", fields)).map(Sentence::Gsv),
+        "VTG" => parse_vtg(talker, Fields::new("VTG", fields)).map(Sentence::Vtg),
+        "GLL" => parse_gll(talker, Fields::new("GLL", fields)).map(Sentence::Gll),
+        "GNS" => parse_gns(talker, Fields::new("GNS", fields)).map(Sentence::Gns),
+        "GST" => parse_gst(talker, Fields::new("GST", fields)).map(Sentence::Gst),
+        "ZDA" => parse_zda(talker, Fields::new("ZDA", fields)).map(Sentence::Zda),
+        "TXT" => parse_txt(talker, Fields::new("TXT", fields)).map(Sentence::Txt),
+        _ => Err(Error::UnsupportedSentence(sentence_type.to_string())),
+    }
+}
+
+fn parse_gga(talker: Talker, mut f: Fields<'_>) -> Result<Gga, Error> {
+    let time = f.time()?;
+    let latitude = f.latitude()?;
+    let longitude = f.longitude()?;
+    let quality = match f.required::<u8>("fix quality")? {
+        0 => FixQuality::Invalid,
+        1 => FixQuality::Gps,
+        2 => FixQuality::Dgps,
+        3 => FixQuality::Pps,
+        4 => FixQuality::Rtk,
+        5 => FixQuality::FloatRtk,
+        6 => FixQuality::Estimated,
+        7 => FixQuality::Manual,
+        8 => FixQuality::Simulation,
+        q => return Err(f.invalid("fix quality", &q.to_string())),
+    };
+    let satellites = f.optional("satellite count")?;
+    let hdop = f.optional("HDOP")?;
+    let altitude = f.optional("altitude")?;
+    f.skip_field(); // Altitude unit, always M.
+    let geoid_separation = f.optional("geoid separation")?;
+    f.skip_field(); // Geoid separation unit, always M.
+    let dgps_age = f.optional("DGPS age")?;
+    let dgps_station = f.optional("DGPS station")?;
+
+    Ok(Gga {
+        talker,
+        time,
+        latitude,
+        longitude,
+        quality,
+        satellites,
+        hdop,
+        altitude,
+        geoid_separation,
+        dgps_age,
+        dgps_station,
+    })
+}
+
+fn parse_rmc(talker: Talker, mut f: Fields<'_>) -> Result<Rmc, Error> {
+    let time = f.time()?;
+    let valid = f.status()?;
+    let latitude = f.latitude()?;
+    let longitude = f.longitude()?;
+    let speed_knots = f.optional("speed")?;
+    let course = f.optional("course")?;
+    let date = f.date()?;
+    let magnetic_variation = f.signed_optional("magnetic variation", 'E', 'W')?;
+    let mode = f.mode()?;
+
+    Ok(Rmc {
+        talker,
+        time,
+        valid,
+        latitude,
+        longitude,
+        speed_knots,
+        course,
+        date,
+        magnetic_variation,
+        mode,
+    })
+}
+
+fn parse_gsa(talker: Talker, mut f: Fields<'_>) -> Result<Gsa, Error> {
+    let automatic = match f.field() {
+        "A" => true,
+        "M" => false,
+        s => return Err(f.invalid("selection mode", s)),
+    };
+    let fix_type = match f.required::<u8>("fix type")? {
+        1 => FixType::NoFix,
+        2 => FixType::Fix2D,
+        3 => FixType::Fix3D,
+        t => return Err(f.invalid("fix type", &t.to_string())),
+    };
+    let mut satellites = vec![];
+    for _ in 0..12 {
+        if let Some(prn) = f.optional("satellite PRN")? {
+            satellites.push(prn);
+        }
+    }
+    let pdop = f.optional("PDOP")?;
+    let hdop = f.optional("HDOP")?;
+    let vdop = f.optional("VDOP")?;
+    let system_id = f.optional("system ID")?;
+
+    Ok(Gsa {
+        talker,
+        automatic,
+        fix_type,
+        satellites,
+        pdop,
+        hdop,
+        vdop,
+        system_id,
+    })
+}
+
+fn parse_gsv(talker: Talker, mut f: Fields<'_>) -> Result<Gsv, Error> {
+    let total_messages = f.required("message count")?;
+    let message_number = f.required("message number")?;
+    let satellites_in_view = f.required("satellite count")?;
+
+    let rest = f.rest();
+    let mut satellites = vec![];
+    for sat in rest.chunks_exact(4) {
+        let mut sat = Fields::new("GSV", sat.iter().copied());
+        let prn = match sat.optional("satellite PRN")? {
+            Some(prn) => prn,
+            // Some receivers pad the last message with empty satellite fields.
+            None => continue,
+        };
+
+        satellites.push(SatelliteInfo {
+            prn,
+            elevation: sat.optional("elevation")?,
+            azimuth: sat.optional("azimuth")?,
+            snr: sat.optional("SNR")?,
+        });
+    }
+    let signal_id = match rest.chunks_exact(4).remainder() {
+        [id] => Fields::new("GSV", [*id].into_iter()).optional("signal ID")?,
+        _ => None,
+    };
+
+    Ok(Gsv {
+        talker,
+        total_messages,
+        message_number,
+        satellites_in_view,
+        satellites,
+        signal_id,
+    })
+}
+
+fn parse_vtg(talker: Talker, mut f: Fields<'_>) -> Result<Vtg, Error> {
+    let course_true = f.optional("true course")?;
+    f.skip_field(); // T
+    let course_magnetic = f.optional("magnetic course")?;
+    f.skip_field(); // M
+    let speed_knots = f.optional("speed in knots")?;
+    f.skip_field(); // N
+    let speed_kmh = f.optional("speed in km/h")?;
+    f.skip_field(); // K
+    let mode = f.mode()?;
+
+    Ok(Vtg {
+        talker,
+        course_true,
+        course_magnetic,
+        speed_knots,
+        speed_kmh,
+        mode,
+    })
+}
+
+fn parse_gll(talker: Talker, mut f: Fields<'_>) -> Result<Gll, Error> {
+    let latitude = f.latitude()?;
+    let longitude = f.longitude()?;
+    let time = f.time()?;
+    let valid = f.status()?;
+    let mode = f.mode()?;
+
+    Ok(Gll {
+        talker,
+        latitude,
+        longitude,
+        time,
+        valid,
+        mode,
+    })
+}
+
+fn parse_gns(talker: Talker, mut f: Fields<'_>) -> Result<Gns, Error> {
+    let time = f.time()?;
+    let latitude = f.latitude()?;
+    let longitude = f.longitude()?;
+    let modes = f.field();
+    let modes = modes
+        .chars()
+        .map(|c| Mode::from_char(c).ok_or_else(|| f.invalid("mode", modes)))
+        .collect::<Result<_, _>>()?;
+    let satellites = f.optional("satellite count")?;
+    let hdop = f.optional("HDOP")?;
+    let altitude = f.optional("altitude")?;
+    let geoid_separation = f.optional("geoid separation")?;
+    let dgps_age = f.optional("DGPS age")?;
+    let dgps_station = f.optional("DGPS station")?;
+
+    Ok(Gns {
+        talker,
+        time,
+        latitude,
+        longitude,
+        modes,
+        satellites,
+        hdop,
+        altitude,
+        geoid_separation,
+        dgps_age,
+        dgps_station,
+    })
+}
+
+fn parse_gst(talker: Talker, mut f: Fields<'_>) -> Result<Gst, Error> {
+    Ok(Gst {
+        talker,
+        time: f.time()?,
+        rms: f.optional("RMS")?,
+        semi_major_error: f.optional("semi-major error")?,
+        semi_minor_error: f.optional("semi-minor error")?,
+        orientation: f.optional("orientation")?,
+        latitude_error: f.optional("latitude error")?,
+        longitude_error: f.optional("longitude error")?,
+        altitude_error: f.optional("altitude error")?,
+    })
+}
+
+fn parse_zda(talker: Talker, mut f: Fields<'_>) -> Result<Zda, Error> {
+    let time = f.time()?;
+    let day: Option<u8> = f.optional("day")?;
+    let month: Option<u8> = f.optional("month")?;
+    let year: Option<u16> = f.optional("year")?;
+    let date = match (year, month, day) {
+        (Some(year), Some(month @ 1..=12), Some(day @ 1..=31)) => Some(Date { year, month, day }),
+        (None, None, None) => None,
+        _ => return Err(f.invalid("date", &format!("{:?}-{:?}-{:?}", year, month, day))),
+    };
+    let local_zone_hours = f.optional("local zone hours")?;
+    let local_zone_minutes = f.optional("local zone minutes")?;
+
+    Ok(Zda {
+        talker,
+        time,
+        date,
+        local_zone_hours,
+        local_zone_minutes,
+    })
+}
+
+fn parse_txt(talker: Talker, mut f: Fields<'_>) -> Result<Txt, Error> {
+    let total_messages = f.required("message count")?;
+    let message_number = f.required("message number")?;
+    let identifier = f.required("text identifier")?;
+    // The text itself may contain commas.
+    let text = f.rest().join(",");
+
+    Ok(Txt {
+        talker,
+        total_messages,
+        message_number,
+        identifier,
+        text,
+    })
+}
+
+// The comma-separated fields of a sentence, after the address.
+struct Fields<'a> {
+    sentence: &'static str,
+    iter: Box<dyn Iterator<Item = &'a str> + 'a>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(sentence: &'static str, iter: impl Iterator<Item = &'a str> + 'a) -> Self {
+        Fields {
+            sentence,
+            iter: Box::new(iter),
+        }
+    }
+
+    // Missing trailing fields are treated the same as empty ones, as many receivers omit them.
+    fn field(&mut self) -> &'a str {
+        self.iter.next().unwrap_or_default()
+    }
+
+    fn skip_field(&mut self) {
+        self.field();
+    }
+
+    fn rest(&mut self) -> Vec<&'a str> {
+        self.iter.by_ref().collect()
+    }
+
+    fn invalid(&self, field: &'static str, value: &str) -> Error {
+        Error::InvalidField {
+            sentence: self.sentence,
+            field,
+            value: value.to_string(),
+        }
+    }
+
+    fn optional<T: FromStr>(&mut self, field: &'static str) -> Result<Option<T>, Error> {
+        let value = self.field();
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        value
+            .parse()
+            .map(Some)
+            .map_err(|_| self.invalid(field, value))
+    }
+
+    fn required<T: FromStr>(&mut self, field: &'static str) -> Result<T, Error> {
+        self.optional(field)?.ok_or(Error::MissingField {
+            sentence: self.sentence,
+            field,
+        })
+    }
+
+    // A value followed by a field with the direction, e.g `1.5,W`.
+    fn signed_optional(
+        &mut self,
+        field: &'static str,
+        positive: char,
+        negative: char,
+    ) -> Result<Option<f64>, Error> {
+        let value = self.optional::<f64>(field)?;
+        let direction = self.field();
+
+        match (value, direction.chars().next()) {
+            (None, _) => Ok(None),
+            (Some(v), Some(d)) if d == positive => Ok(Some(v)),
+            (Some(v), Some(d)) if d == negative => Ok(Some(-v)),
+            _ => Err(self.invalid(field, direction)),
+        }
+    }
+
+    // `(d)ddmm.mmmm` followed by the hemisphere.
+    fn coordinate(
+        &mut self,
+        field: &'static str,
+        degree_digits: usize,
+        positive: char,
+        negative: char,
+    ) -> Result<Option<f64>, Error> {
+        let value = self.field();
+        let hemisphere = self.field();
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        let degrees: Option<f64> = value.get(..degree_digits).and_then(|d| d.parse().ok());
+        let minutes: Option<f64> = value.get(degree_digits..).and_then(|m| m.parse().ok());
+        let (degrees, minutes) = match (degrees, minutes) {
+            (Some(d), Some(m)) if m < 60.0 => (d, m),
+            _ => return Err(self.invalid(field, value)),
+        };
+        let coordinate = degrees + minutes / 60.0;
+
+        match hemisphere.chars().next() {
+            Some(h) if h == positive => Ok(Some(coordinate)),
+            Some(h) if h == negative => Ok(Some(-coordinate)),
+            _ => Err(self.invalid(field, hemisphere)),
+        }
+    }
+
+    fn latitude(&mut self) -> Result<Option<f64>, Error> {
+        self.coordinate("latitude", 2, 'N', 'S')
+    }
+
+    fn longitude(&mut self) -> Result<Option<f64>, Error> {
+        self.coordinate("longitude", 3, 'E', 'W')
+    }
+
+    // `hhmmss.ss`
+    fn time(&mut self) -> Result<Option<Time>, Error> {
+        let value = self.field();
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        let hour = value.get(0..2).and_then(|h| h.parse().ok());
+        let minute = value.get(2..4).and_then(|m| m.parse().ok());
+        let second = value.get(4..).and_then(|s| s.parse().ok());
+        match (hour, minute, second) {
+            (Some(hour @ 0..=23), Some(minute @ 0..=59), Some(second)) if second < 61.0 => {
+                Ok(Some(Time {
+                    hour,
+                    minute,
+                    second,
+                }))
+            }
+            _ => Err(self.invalid("time", value)),
+        }
+    }
+
+    // `ddmmyy`
+    fn date(&mut self) -> Result<Option<Date>, Error> {
+        let value = self.field();
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        let day = value.get(0..2).and_then(|d| d.parse().ok());
+        let month = value.get(2..4).and_then(|m| m.parse().ok());
+        let year: Option<u16> = value.get(4..6).and_then(|y| y.parse().ok());
+        match (day, month, year) {
+            (Some(day @ 1..=31), Some(month @ 1..=12), Some(year)) if value.len() == 6 => {
+                // Two digit years: assume we're not dealing with data from before GPS existed.
+                let year = if year < 80 { 2000 + year } else { 1900 + year };
+
+                Ok(Some(Date { year, month, day }))
+            }
+            _ => Err(self.invalid("date", value)),
+        }
+    }
+
+    // `A`(ctive) or `V`(oid).
+    fn status(&mut self) -> Result<bool, Error> {
+        match self.field() {
+            "A" => Ok(true),
+            "V" | "" => Ok(false),
+            s => Err(self.invalid("status", s)),
+        }
+    }
+
+    fn mode(&mut self) -> Result<Option<Mode>, Error> {
+        let value = self.field();
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        let mut chars = value.chars();
+        match (chars.next().and_then(Mode::from_char), chars.next()) {
+            (Some(mode), None) => Ok(Some(mode)),
+            _ => Err(self.invalid("mode", value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: Option<f64>, expected: f64) {
+        let value = value.expect("no value");
+        assert!((value - expected).abs() < 1e-6, "{} != {}", value, expected);
+    }
+
+    fn time(hour: u8, minute: u8, second: f64) -> Option<Time> {
+        Some(Time {
+            hour,
+            minute,
+            second,
+        })
+    }
+
+    fn date(year: u16, month: u8, day: u8) -> Option<Date> {
+        Some(Date { year, month, day })
+    }
+
+    #[test]
+    fn checksums() {
+        let gga = "$GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\r\n";
+        assert_eq!(verify_checksum(gga), Ok(()));
+        assert_eq!(
+            with_checksum("GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000"),
+            gga
+        );
+
+        assert_eq!(
+            verify_checksum("$GPVTG,0.0,T,,M,0.0,N,0.0,K,A\r\n"),
+            Err(Error::MissingChecksum)
+        );
+        assert_eq!(
+            parse("$GPVTG,0.0,T,,M,0.0,N,0.0,K,A*0C"),
+            Err(Error::ChecksumMismatch {
+                expected: 0x0D,
+                found: 0x0C
+            })
+        );
+        assert_eq!(
+            parse("$GPVTG,0.0,T,,M,0.0,N,0.0,K,A*D"),
+            Err(Error::InvalidChecksum("D".to_string()))
+        );
+        assert_eq!(
+            parse("$GPVTG,0.0,T,,M,0.0,N,0.0,K,A*0G"),
+            Err(Error::InvalidChecksum("0G".to_string()))
+        );
+        // The TAG block has a checksum of its own, which isn't the sentence's.
+        assert!(parse("\\t:repaired*52\\$GPVTG,0.0,T,,M,0.0,N,0.0,K,A*0D\r\n").is_ok());
+    }
+
+    #[test]
+    fn sentence_types() {
+        assert_eq!(sentence_type("$GPGGA,122732.000,,,,,0*7A"), Some("GGA"));
+        assert_eq!(sentence_type("$GNRMC*00"), Some("RMC"));
+        assert_eq!(sentence_type("$PUBX,00,122732.00"), Some("PUBX"));
+        assert_eq!(sentence_type("\\t:repaired*52\\$GPVTG,0.0,T"), Some("VTG"));
+        assert_eq!(sentence_type("$GP,"), None);
+        assert_eq!(sentence_type("GPGGA,"), None);
+    }
+
+    #[test]
+    fn talkers() {
+        for (id, talker) in [
+            ("GP", Talker::Gps),
+            ("GL", Talker::Glonass),
+            ("GA", Talker::Galileo),
+            ("GB", Talker::BeiDou),
+            ("BD", Talker::BeiDou),
+            ("QZ", Talker::Qzss),
+            ("GN", Talker::Combined),
+        ] {
+            let sentence = parse(&format!("${}GLL,,,,,,V,N", id)).unwrap();
+            assert_eq!(sentence.talker(), talker, "{}", id);
+        }
+        assert_eq!(Talker::BeiDou.as_str(), "GB");
+
+        assert_eq!(
+            parse("$XXGLL,,,,,,V,N"),
+            Err(Error::UnknownTalker("XX".to_string()))
+        );
+    }
+
+    #[test]
+    fn invalid_sentences() {
+        assert_eq!(parse("GPGGA,,,,,,0"), Err(Error::MissingStart));
+        assert_eq!(
+            parse("$PUBX,00,,,,"),
+            Err(Error::InvalidAddress("PUBX".to_string()))
+        );
+        assert_eq!(
+            parse("$PGRME,15.0,M,45.0,M,25.0,M"),
+            Err(Error::UnsupportedSentence("PGRME".to_string()))
+        );
+        assert_eq!(
+            parse("$GPXTE,A,A,0.67,L,N"),
+            Err(Error::UnsupportedSentence("XTE".to_string()))
+        );
+        assert_eq!(
+            parse("$GPGSV,,1,04"),
+            Err(Error::MissingField {
+                sentence: "GSV",
+                field: "message count"
+            })
+        );
+        assert_eq!(
+            parse("$GPGLL,4960.00,N,12311.12,W,225444,A"),
+            Err(Error::InvalidField {
+                sentence: "GLL",
+                field: "latitude",
+                value: "4960.00".to_string()
+            })
+        );
+        assert_eq!(
+            parse("$GPGLL,4916.45,E,12311.12,W,225444,A"),
+            Err(Error::InvalidField {
+                sentence: "GLL",
+                field: "latitude",
+                value: "E".to_string()
+            })
+        );
+        assert_eq!(
+            parse("$GPGLL,4916.45,N,12311.12,W,245444,A"),
+            Err(Error::InvalidField {
+                sentence: "GLL",
+                field: "time",
+                value: "245444".to_string()
+            })
+        );
+        assert_eq!(
+            parse("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,X"),
+            Err(Error::InvalidField {
+                sentence: "RMC",
+                field: "magnetic variation",
+                value: "X".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn gga() {
+        let sentence =
+            parse("$GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\r\n");
+        let gga = match sentence {
+            Ok(Sentence::Gga(gga)) => gga,
+            s => panic!("{:?}", s),
+        };
+        assert_eq!(gga.talker, Talker::Gps);
+        assert_eq!(gga.time, time(12, 27, 32.0));
+        assert_close(gga.latitude, 57.74130666);
+        assert_close(gga.longitude, 12.02688333);
+        assert_eq!(gga.quality, FixQuality::Gps);
+        assert_eq!(gga.satellites, Some(4));
+        assert_eq!(gga.hdop, Some(6.5));
+        assert_eq!(gga.altitude, Some(61.7));
+        assert_eq!(gga.geoid_separation, Some(44.5));
+        assert_eq!(gga.dgps_age, None);
+        assert_eq!(gga.dgps_station, Some(0));
+    }
+
+    #[test]
+    fn gga_without_fix() {
+        assert_eq!(
+            parse("$GPGGA,,,,,,0,,,,,,,,"),
+            Ok(Sentence::Gga(Gga {
+                talker: Talker::Gps,
+                time: None,
+                latitude: None,
+                longitude: None,
+                quality: FixQuality::Invalid,
+                satellites: None,
+                hdop: None,
+                altitude: None,
+                geoid_separation: None,
+                dgps_age: None,
+                dgps_station: None,
+            }))
+        );
+        assert_eq!(
+            parse("$GPGGA,,,,,,9,,,,,,,,"),
+            Err(Error::InvalidField {
+                sentence: "GGA",
+                field: "fix quality",
+                value: "9".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn rmc() {
+        let sentence =
+            parse("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A");
+        let rmc = match sentence {
+            Ok(Sentence::Rmc(rmc)) => rmc,
+            s => panic!("{:?}", s),
+        };
+        assert_eq!(rmc.time, time(12, 35, 19.0));
+        assert!(rmc.valid);
+        assert_close(rmc.latitude, 48.1173);
+        assert_close(rmc.longitude, 11.51666666);
+        assert_eq!(rmc.speed_knots, Some(22.4));
+        assert_eq!(rmc.course, Some(84.4));
+        assert_eq!(rmc.date, date(1994, 3, 23));
+        assert_eq!(rmc.magnetic_variation, Some(-3.1));
+        assert_eq!(rmc.mode, None);
+
+        let sentence = parse("$GNRMC,001122.50,V,3345.1234,S,07015.5000,W,,,010125,,,N");
+        let rmc = match sentence {
+            Ok(Sentence::Rmc(rmc)) => rmc,
+            s => panic!("{:?}", s),
+        };
+        assert_eq!(rmc.talker, Talker::Combined);
+        assert_eq!(rmc.time, time(0, 11, 22.5));
+        assert!(!rmc.valid);
+        assert_close(rmc.latitude, -33.75205666);
+        assert_close(rmc.longitude, -70.25833333);
+        assert_eq!(rmc.speed_knots, None);
+        assert_eq!(rmc.course, None);
+        // Two digit years from before GPS existed are in this century.
+        assert_eq!(rmc.date, date(2025, 1, 1));
+        assert_eq!(rmc.magnetic_variation, None);
+        assert_eq!(rmc.mode, Some(Mode::NotValid));
+
+        let sentence = parse("$GPRMC,,V,,,,,,,,,,");
+        let rmc = match sentence {
+            Ok(Sentence::Rmc(rmc)) => rmc,
+            s => panic!("{:?}", s),
+        };
+        assert_eq!(rmc.time, None);
+        assert_eq!(rmc.latitude, None);
+        assert_eq!(rmc.date, None);
+        assert_eq!(
+            parse("$GPRMC,123519,A,,,,,,,320394,,"),
+            Err(Error::InvalidField {
+                sentence: "RMC",
+                field: "date",
+                value: "320394".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn gsa() {
+        assert_eq!(
+            parse("$GNGSA,A,3,02,12,19,24,,,,,,,,,2.0,1.2,1.6,1"),
+            Ok(Sentence::Gsa(Gsa {
+                talker: Talker::Combined,
+                automatic: true,
+                fix_type: FixType::Fix3D,
+                satellites: vec![2, 12, 19, 24],
+                pdop: Some(2.0),
+                hdop: Some(1.2),
+                vdop: Some(1.6),
+                system_id: Some(1),
+            }))
+        );
+        assert_eq!(
+            parse("$GPGSA,M,1,,,,,,,,,,,,,,,"),
+            Ok(Sentence::Gsa(Gsa {
+                talker: Talker::Gps,
+                automatic: false,
+                fix_type: FixType::NoFix,
+                satellites: vec![],
+                pdop: None,
+                hdop: None,
+                vdop: None,
+                system_id: None,
+            }))
+        );
+        assert_eq!(
+            parse("$GPGSA,A,4,,,,,,,,,,,,,,,"),
+            Err(Error::InvalidField {
+                sentence: "GSA",
+                field: "fix type",
+                value: "4".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn gsv() {
+        assert_eq!(
+            parse("$GPGSV,3,1,09,02,45,090,40,12,30,180,35,19,60,270,42,24,15,045,30*7E"),
+            Ok(Sentence::Gsv(Gsv {
+                talker: Talker::Gps,
+                total_messages: 3,
+                message_number: 1,
+                satellites_in_view: 9,
+                satellites: vec![
+                    SatelliteInfo {
+                        prn: 2,
+                        elevation: Some(45),
+                        azimuth: Some(90),
+                        snr: Some(40),
+                    },
+                    SatelliteInfo {
+                        prn: 12,
+                        elevation: Some(30),
+                        azimuth: Some(180),
+                        snr: Some(35),
+                    },
+                    SatelliteInfo {
+                        prn: 19,
+                        elevation: Some(60),
+                        azimuth: Some(270),
+                        snr: Some(42),
+                    },
+                    SatelliteInfo {
+                        prn: 24,
+                        elevation: Some(15),
+                        azimuth: Some(45),
+                        snr: Some(30),
+                    },
+                ],
+                signal_id: None,
+            }))
+        );
+
+        // The last message padded with empty satellites, and a satellite that isn't tracked.
+        let sentence = parse("$GLGSV,2,2,05,65,,,,,,,");
+        let gsv = match sentence {
+            Ok(Sentence::Gsv(gsv)) => gsv,
+            s => panic!("{:?}", s),
+        };
+        assert_eq!(gsv.talker, Talker::Glonass);
+        assert_eq!(
+            gsv.satellites,
+            [SatelliteInfo {
+                prn: 65,
+                elevation: None,
+                azimuth: None,
+                snr: None,
+            }]
+        );
+        assert_eq!(gsv.signal_id, None);
+
+        // NMEA 4.10 adds the signal ID.
+        let sentence = parse("$GAGSV,1,1,01,05,20,100,30,7");
+        let gsv = match sentence {
+            Ok(Sentence::Gsv(gsv)) => gsv,
+            s => panic!("{:?}", s),
+        };
+        assert_eq!(gsv.satellites.len(), 1);
+        assert_eq!(gsv.signal_id, Some(7));
+    }
+
+    #[test]
+    fn vtg() {
+        assert_eq!(
+            parse("$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K,A"),
+            Ok(Sentence::Vtg(Vtg {
+                talker: Talker::Gps,
+                course_true: Some(54.7),
+                course_magnetic: Some(34.4),
+                speed_knots: Some(5.5),
+                speed_kmh: Some(10.2),
+                mode: Some(Mode::Autonomous),
+            }))
+        );
+        // Before NMEA 2.3, there's no mode.
+        assert_eq!(
+            parse("$GPVTG,,T,,M,,N,,K"),
+            Ok(Sentence::Vtg(Vtg {
+                talker: Talker::Gps,
+                course_true: None,
+                course_magnetic: None,
+                speed_knots: None,
+                speed_kmh: None,
+                mode: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn gll() {
+        let sentence = parse("$GPGLL,4916.45,N,12311.12,W,225444,A,D");
+        let gll = match sentence {
+            Ok(Sentence::Gll(gll)) => gll,
+            s => panic!("{:?}", s),
+        };
+        assert_close(gll.latitude, 49.27416666);
+        assert_close(gll.longitude, -123.18533333);
+        assert_eq!(gll.time, time(22, 54, 44.0));
+        assert!(gll.valid);
+        assert_eq!(gll.mode, Some(Mode::Differential));
+
+        assert_eq!(
+            parse("$GPGLL,,,,,,V,N"),
+            Ok(Sentence::Gll(Gll {
+                talker: Talker::Gps,
+                latitude: None,
+                longitude: None,
+                time: None,
+                valid: false,
+                mode: Some(Mode::NotValid),
+            }))
+        );
+    }
+
+    #[test]
+    fn gns() {
+        let sentence = parse("$GNGNS,014035.00,4332.69262,S,17235.48549,E,RR,13,0.9,25.63,11.24,,");
+        let gns = match sentence {
+            Ok(Sentence::Gns(gns)) => gns,
+            s => panic!("{:?}", s),
+        };
+        assert_eq!(gns.time, time(1, 40, 35.0));
+        assert_close(gns.latitude, -43.54487700);
+        assert_close(gns.longitude, 172.59142483);
+        assert_eq!(gns.modes, [Mode::Rtk, Mode::Rtk]);
+        assert_eq!(gns.satellites, Some(13));
+        assert_eq!(gns.hdop, Some(0.9));
+        assert_eq!(gns.altitude, Some(25.63));
+        assert_eq!(gns.geoid_separation, Some(11.24));
+        assert_eq!(gns.dgps_age, None);
+        assert_eq!(gns.dgps_station, None);
+
+        let sentence = parse("$GNGNS,,,,,,NN,,,,,,");
+        let gns = match sentence {
+            Ok(Sentence::Gns(gns)) => gns,
+            s => panic!("{:?}", s),
+        };
+        assert_eq!(gns.modes, [Mode::NotValid, Mode::NotValid]);
+        assert_eq!(gns.latitude, None);
+        assert_eq!(
+            parse("$GNGNS,,,,,,AX,,,,,,"),
+            Err(Error::InvalidField {
+                sentence: "GNS",
+                field: "mode",
+                value: "AX".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn gst() {
+        assert_eq!(
+            parse("$GPGST,172814.0,0.006,0.023,0.020,273.6,0.023,0.020,0.031"),
+            Ok(Sentence::Gst(Gst {
+                talker: Talker::Gps,
+                time: time(17, 28, 14.0),
+                rms: Some(0.006),
+                semi_major_error: Some(0.023),
+                semi_minor_error: Some(0.020),
+                orientation: Some(273.6),
+                latitude_error: Some(0.023),
+                longitude_error: Some(0.020),
+                altitude_error: Some(0.031),
+            }))
+        );
+        let sentence = parse("$GPGST,172814.0,,,,,,,");
+        let gst = match sentence {
+            Ok(Sentence::Gst(gst)) => gst,
+            s => panic!("{:?}", s),
+        };
+        assert_eq!(gst.rms, None);
+        assert_eq!(gst.altitude_error, None);
+    }
+
+    #[test]
+    fn zda() {
+        assert_eq!(
+            parse("$GPZDA,201530.00,04,07,2002,00,00"),
+            Ok(Sentence::Zda(Zda {
+                talker: Talker::Gps,
+                time: time(20, 15, 30.0),
+                date: date(2002, 7, 4),
+                local_zone_hours: Some(0),
+                local_zone_minutes: Some(0),
+            }))
+        );
+        assert_eq!(
+            parse("$GPZDA,201530.00,,,,,"),
+            Ok(Sentence::Zda(Zda {
+                talker: Talker::Gps,
+                time: time(20, 15, 30.0),
+                date: None,
+                local_zone_hours: None,
+                local_zone_minutes: None,
+            }))
+        );
+        assert!(matches!(
+            parse("$GPZDA,201530.00,04,,2002,,"),
+            Err(Error::InvalidField { field: "date", .. })
+        ));
+    }
+
+    #[test]
+    fn txt() {
+        assert_eq!(
+            parse("$GPTXT,01,01,02,u-blox AG, www.u-blox.com"),
+            Ok(Sentence::Txt(Txt {
+                talker: Talker::Gps,
+                total_messages: 1,
+                message_number: 1,
+                identifier: 2,
+                text: "u-blox AG, www.u-blox.com".to_string(),
+            }))
+        );
+    }
+}
@@ -0,0 +1,728 @@
+/* vim: set et ts=4 sw=4: */
+/* receiver.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Configuration of receivers through their vendors' protocols: UBX for u-blox, `$PMTK`
+//! commands for MediaTek and `$PSRF` commands for SiRF.
+//!
+//! Settings only go to the receiver's RAM, so they're sent again whenever a receiver shows up.
+
+use crate::broadcast::Subscription;
+use crate::feed::{Event, Feed, Request};
+use crate::nmea;
+use crate::reconnect;
+use crate::sirf;
+use crate::ubx;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long receivers get to answer a command.
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// How long receivers get to send out a command before we switch to the baudrate it sets.
+const BAUDRATE_SWITCH_DELAY: Duration = Duration::from_millis(100);
+
+const MAX_UPDATE_RATE: u32 = 50;
+
+// The NMEA sentence types we know to turn on or off.
+const SENTENCES: &[&str] = &[
+    "GGA", "GLL", "GNS", "GSA", "GST", "GSV", "RMC", "VTG", "ZDA",
+];
+
+const UBX_CLASS_CFG: u8 = 0x06;
+const UBX_CLASS_MON: u8 = 0x0a;
+const UBX_CLASS_NMEA: u8 = 0xf0;
+const UBX_CFG_PRT: u8 = 0x00;
+const UBX_CFG_MSG: u8 = 0x01;
+const UBX_CFG_RATE: u8 = 0x08;
+const UBX_CFG_GNSS: u8 = 0x3e;
+const UBX_CFG_VALSET: u8 = 0x8a;
+const UBX_MON_VER: u8 = 0x04;
+
+// The protocol version of u-blox 9, the first to take configuration keys.
+const UBX_KEYS_PROTOCOL: u8 = 27;
+
+// Configuration keys of u-blox 9 and later.
+const UBX_RATE_MEAS: u32 = 0x3021_0001;
+const UBX_UART1_BAUDRATE: u32 = 0x4052_0001;
+const UBX_SIGNAL_ENA: &[(Constellation, u32)] = &[
+    (Constellation::Gps, 0x1031_001f),
+    (Constellation::Sbas, 0x1031_0020),
+    (Constellation::Galileo, 0x1031_0021),
+    (Constellation::Beidou, 0x1031_0022),
+    (Constellation::Qzss, 0x1031_0024),
+    (Constellation::Glonass, 0x1031_0025),
+];
+// The CFG-MSGOUT-NMEA_ID_*_I2C keys, the ones for UART1 and USB following at +1 and +3.
+const UBX_MSGOUT_NMEA: &[(&str, u32)] = &[
+    ("GGA", 0x2091_00ba),
+    ("GLL", 0x2091_00c9),
+    ("GNS", 0x2091_00b5),
+    ("GSA", 0x2091_00bf),
+    ("GST", 0x2091_00d3),
+    ("GSV", 0x2091_00c4),
+    ("RMC", 0x2091_00ab),
+    ("VTG", 0x2091_00b0),
+    ("ZDA", 0x2091_00d8),
+];
+
+// The ids of the NMEA messages before u-blox 9, whose rates CFG-MSG sets.
+const UBX_NMEA_IDS: &[(&str, u8)] = &[
+    ("GGA", 0x00),
+    ("GLL", 0x01),
+    ("GSA", 0x02),
+    ("GSV", 0x03),
+    ("RMC", 0x04),
+    ("VTG", 0x05),
+    ("GST", 0x07),
+    ("ZDA", 0x08),
+    ("GNS", 0x0d),
+];
+
+// The CFG-GNSS ids of the constellations, with the tracking channels u-blox 8 reserves for each
+// and lets each use at most by default.
+const UBX_GNSS: &[(Constellation, u8, u8, u8)] = &[
+    (Constellation::Gps, 0, 8, 16),
+    (Constellation::Sbas, 1, 1, 3),
+    (Constellation::Galileo, 2, 4, 8),
+    (Constellation::Beidou, 3, 8, 16),
+    (Constellation::Qzss, 5, 0, 3),
+    (Constellation::Glonass, 6, 8, 14),
+];
+
+// The fields of `$PMTK314`, by sentence type.
+const MTK_314_FIELDS: &[(&str, usize)] = &[
+    ("GLL", 0),
+    ("RMC", 1),
+    ("VTG", 2),
+    ("GGA", 3),
+    ("GSA", 4),
+    ("GSV", 5),
+    ("ZDA", 17),
+];
+const MTK_314_LEN: usize = 19;
+
+// The message numbers of `$PSRF103`, by sentence type.
+const SIRF_103_MESSAGES: &[(&str, u8)] = &[
+    ("GGA", 0),
+    ("GLL", 1),
+    ("GSA", 2),
+    ("GSV", 3),
+    ("RMC", 4),
+    ("VTG", 5),
+    ("ZDA", 8),
+];
+
+/// Whose protocol to configure the receiver with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vendor {
+    Ublox,
+    Mediatek,
+    Sirf,
+}
+
+impl FromStr for Vendor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ublox" => Ok(Vendor::Ublox),
+            "mediatek" => Ok(Vendor::Mediatek),
+            "sirf" => Ok(Vendor::Sirf),
+            _ => Err(format!(
+                "unknown receiver `{}` (expected `ublox`, `mediatek` or `sirf`)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Vendor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Vendor::Ublox => write!(f, "ublox"),
+            Vendor::Mediatek => write!(f, "mediatek"),
+            Vendor::Sirf => write!(f, "sirf"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Constellation {
+    Gps,
+    Glonass,
+    Galileo,
+    Beidou,
+    Qzss,
+    Sbas,
+}
+
+impl FromStr for Constellation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gps" => Ok(Constellation::Gps),
+            "glonass" => Ok(Constellation::Glonass),
+            "galileo" => Ok(Constellation::Galileo),
+            "beidou" => Ok(Constellation::Beidou),
+            "qzss" => Ok(Constellation::Qzss),
+            "sbas" => Ok(Constellation::Sbas),
+            _ => Err(format!(
+                "unknown constellation `{}` (expected `gps`, `glonass`, `galileo`, `beidou`, \
+                 `qzss` or `sbas`)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Constellation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constellation::Gps => write!(f, "gps"),
+            Constellation::Glonass => write!(f, "glonass"),
+            Constellation::Galileo => write!(f, "galileo"),
+            Constellation::Beidou => write!(f, "beidou"),
+            Constellation::Qzss => write!(f, "qzss"),
+            Constellation::Sbas => write!(f, "sbas"),
+        }
+    }
+}
+
+/// What to set up on the receiver. Settings left out are left alone.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    /// Navigation epochs per second.
+    pub update_rate: Option<u32>,
+    /// The NMEA sentence types to send, all others being turned off.
+    pub sentences: Option<Vec<String>>,
+    /// The constellations to use, all others being turned off.
+    pub constellations: Option<Vec<Constellation>>,
+    pub baudrate: Option<u32>,
+}
+
+impl Settings {
+    pub fn is_empty(&self) -> bool {
+        *self == Settings::default()
+    }
+}
+
+// How a u-blox receiver takes its configuration, which depends on its protocol version.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum UbloxConfig {
+    // CFG-VALSET, with the configuration keys of u-blox 9 and later.
+    Keys,
+    // A message for each setting, e.g CFG-RATE, as before u-blox 9.
+    Messages,
+}
+
+// How the receiver tells us whether it took a command.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Answer {
+    Ubx { class: u8, id: u8 },
+    // `$PMTK001`, for the number of the command.
+    Mediatek(u16),
+    // SiRF receivers don't answer, and baudrate switches get answered at a rate we may not be at
+    // yet.
+    None,
+}
+
+impl Answer {
+    // Whether `event` says the command was taken, if it's about the command at all.
+    fn check(&self, event: &Event) -> Option<bool> {
+        match (self, event) {
+            (Answer::Ubx { class, id }, Event::Ack(ack))
+                if ack.class == *class && ack.id == *id =>
+            {
+                Some(ack.acknowledged)
+            }
+
+            (Answer::Mediatek(command), Event::Sentence(line)) => {
+                let fields = line.trim_end().strip_prefix("$PMTK001,")?;
+                let mut fields = fields.split([',', '*']);
+                if fields.next()?.parse::<u16>().ok()? != *command {
+                    return None;
+                }
+
+                // 0 is for invalid commands, 1 for unsupported ones and 2 for failures.
+                Some(fields.next()? == "3")
+            }
+
+            _ => None,
+        }
+    }
+}
+
+struct Command {
+    name: String,
+    data: Vec<u8>,
+    answer: Answer,
+    // The rate to switch to once the command is sent.
+    baudrate: Option<u32>,
+}
+
+impl Command {
+    fn ubx(name: &str, id: u8, payload: &[u8], answer: bool) -> Self {
+        Command {
+            name: format!("UBX-CFG-{}", name),
+            data: ubx::frame(UBX_CLASS_CFG, id, payload),
+            answer: if answer {
+                Answer::Ubx {
+                    class: UBX_CLASS_CFG,
+                    id,
+                }
+            } else {
+                Answer::None
+            },
+            baudrate: None,
+        }
+    }
+
+    fn nmea(data: &str, answer: Answer) -> Self {
+        Command {
+            name: data.split(',').next().unwrap_or(data).to_string(),
+            data: nmea::with_checksum(data).into_bytes(),
+            answer,
+            baudrate: None,
+        }
+    }
+
+    // Switch To NMEA Protocol, staying at `baudrate`.
+    fn sirf_nmea(baudrate: u32) -> Self {
+        Command {
+            name: "SiRF Switch To NMEA Protocol".to_string(),
+            data: sirf::switch_to_nmea(baudrate),
+            answer: Answer::None,
+            baudrate: None,
+        }
+        .switching_to(baudrate)
+    }
+
+    fn switching_to(mut self, baudrate: u32) -> Self {
+        self.baudrate = Some(baudrate);
+
+        self
+    }
+}
+
+/// Checks that `settings` can be applied to receivers of `vendor`.
+pub fn check(vendor: Vendor, settings: &Settings) -> Result<(), String> {
+    // Both ways of configuring u-blox receivers take the same settings.
+    commands(vendor, settings, UbloxConfig::Keys).map(|_| ())
+}
+
+/// Sends `settings` to the receiver the feed reads from, one command at a time, making sure each
+/// is taken before sending the next.
+pub fn configure(feed: &Feed, vendor: Vendor, settings: &Settings) -> Result<(), String> {
+    let ublox = match vendor {
+        Vendor::Ublox => ublox_config(feed)?,
+        Vendor::Mediatek | Vendor::Sirf => UbloxConfig::Keys,
+    };
+    let mut commands = commands(vendor, settings, ublox)?;
+    // Receivers left in SiRF binary mode are switched back to NMEA first, at the rate they're at.
+    if vendor == Vendor::Sirf {
+        if let Some(baudrate) = feed.device().and_then(|device| device.baudrate) {
+            commands.insert(0, Command::sirf_nmea(baudrate));
+        }
+    }
+
+    for command in commands {
+        // Subscribed before sending, so that the answer can't slip through.
+        let subscription = feed.subscribe_live(|event| event.is_sentence() || event.is_ack());
+
+        feed.request(Request::Write(command.data))
+            .map_err(|e| format!("Failed to send {}: {}", command.name, e))?;
+        if let Some(baudrate) = command.baudrate {
+            thread::sleep(BAUDRATE_SWITCH_DELAY);
+            feed.request(Request::SetBaudrate(baudrate))
+                .map_err(|e| format!("Failed to switch to {} baud: {}", baudrate, e))?;
+        }
+
+        wait_for_answer(&subscription, &command.name, command.answer)?;
+    }
+
+    Ok(())
+}
+
+// Asks the receiver for its protocol version, to know how to configure it.
+fn ublox_config(feed: &Feed) -> Result<UbloxConfig, String> {
+    let subscription = feed.subscribe_live(Event::is_version);
+    feed.request(Request::Write(ubx::frame(UBX_CLASS_MON, UBX_MON_VER, &[])))
+        .map_err(|e| format!("Failed to send UBX-MON-VER: {}", e))?;
+
+    let version = match subscription.recv_timeout(ACK_TIMEOUT) {
+        Some(Event::Version(version)) => version,
+        _ => return Err("Receiver didn't answer UBX-MON-VER".to_string()),
+    };
+    match version.protocol {
+        Some((major, _)) if major >= UBX_KEYS_PROTOCOL => Ok(UbloxConfig::Keys),
+        // Only receivers older than u-blox 9 leave it out.
+        Some(_) | None => Ok(UbloxConfig::Messages),
+    }
+}
+
+fn wait_for_answer(
+    subscription: &Subscription<Event>,
+    name: &str,
+    answer: Answer,
+) -> Result<(), String> {
+    if answer == Answer::None {
+        return Ok(());
+    }
+
+    let deadline = Instant::now() + ACK_TIMEOUT;
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        let event = match subscription.recv_timeout(left) {
+            Some(event) => event,
+            None => break,
+        };
+
+        match answer.check(&event) {
+            Some(true) => return Ok(()),
+            Some(false) => return Err(format!("Receiver rejected {}", name)),
+            None => continue,
+        }
+    }
+
+    Err(format!("Receiver didn't acknowledge {}", name))
+}
+
+// `ublox` is how u-blox receivers take their configuration.
+fn commands(
+    vendor: Vendor,
+    settings: &Settings,
+    ublox: UbloxConfig,
+) -> Result<Vec<Command>, String> {
+    if let Some(rate) = settings.update_rate {
+        if !(1..=MAX_UPDATE_RATE).contains(&rate) {
+            return Err(format!(
+                "update rate of {} Hz out of range (1 to {})",
+                rate, MAX_UPDATE_RATE
+            ));
+        }
+    }
+    let sentences = match settings.sentences {
+        Some(ref sentences) => Some(sentence_types(sentences)?),
+        None => None,
+    };
+    let constellations = settings.constellations.as_deref();
+
+    // The baudrate goes last, for the other commands to be answered at the rate we're at.
+    match vendor {
+        Vendor::Ublox if ublox == UbloxConfig::Keys => ublox_keys(
+            settings.update_rate,
+            sentences,
+            constellations,
+            settings.baudrate,
+        ),
+        Vendor::Ublox => ublox_messages(
+            settings.update_rate,
+            sentences,
+            constellations,
+            settings.baudrate,
+        ),
+        Vendor::Mediatek => mediatek(
+            settings.update_rate,
+            sentences,
+            constellations,
+            settings.baudrate,
+        ),
+        Vendor::Sirf => sirf(
+            settings.update_rate,
+            sentences,
+            constellations,
+            settings.baudrate,
+        ),
+    }
+}
+
+fn sentence_types(sentences: &[String]) -> Result<Vec<&'static str>, String> {
+    sentences
+        .iter()
+        .map(|s| {
+            let s = s.to_uppercase();
+
+            SENTENCES
+                .iter()
+                .find(|&&t| t == s)
+                .copied()
+                .ok_or_else(|| format!("unknown sentence type `{}`", s))
+        })
+        .collect()
+}
+
+fn ublox_keys(
+    update_rate: Option<u32>,
+    sentences: Option<Vec<&str>>,
+    constellations: Option<&[Constellation]>,
+    baudrate: Option<u32>,
+) -> Result<Vec<Command>, String> {
+    let mut values = vec![];
+    if let Some(rate) = update_rate {
+        values.extend(UBX_RATE_MEAS.to_le_bytes());
+        values.extend(((1000 / rate) as u16).to_le_bytes());
+    }
+    if let Some(sentences) = sentences {
+        for &(sentence, key) in UBX_MSGOUT_NMEA {
+            let rate = u8::from(sentences.contains(&sentence));
+            for port in [1, 3] {
+                values.extend((key + port).to_le_bytes());
+                values.push(rate);
+            }
+        }
+    }
+    if let Some(constellations) = constellations {
+        for &(constellation, key) in UBX_SIGNAL_ENA {
+            values.extend(key.to_le_bytes());
+            values.push(u8::from(constellations.contains(&constellation)));
+        }
+    }
+
+    let mut commands = vec![];
+    if !values.is_empty() {
+        // Version 0, to the RAM layer only.
+        let mut payload = vec![0x00, 0x01, 0x00, 0x00];
+        payload.extend(values);
+        commands.push(Command::ubx("VALSET", UBX_CFG_VALSET, &payload, true));
+    }
+    if let Some(baudrate) = baudrate {
+        let mut payload = vec![0x00, 0x01, 0x00, 0x00];
+        payload.extend(UBX_UART1_BAUDRATE.to_le_bytes());
+        payload.extend(baudrate.to_le_bytes());
+        let command = Command::ubx("VALSET", UBX_CFG_VALSET, &payload, false);
+        commands.push(command.switching_to(baudrate));
+    }
+
+    Ok(commands)
+}
+
+fn ublox_messages(
+    update_rate: Option<u32>,
+    sentences: Option<Vec<&str>>,
+    constellations: Option<&[Constellation]>,
+    baudrate: Option<u32>,
+) -> Result<Vec<Command>, String> {
+    let mut commands = vec![];
+    if let Some(rate) = update_rate {
+        // The measurement period, a navigation solution for each measurement, aligned to GPS time.
+        let mut payload = vec![];
+        payload.extend(((1000 / rate) as u16).to_le_bytes());
+        payload.extend(1u16.to_le_bytes());
+        payload.extend(1u16.to_le_bytes());
+        commands.push(Command::ubx("RATE", UBX_CFG_RATE, &payload, true));
+    }
+    if let Some(sentences) = sentences {
+        for &(sentence, id) in UBX_NMEA_IDS {
+            // On the port we talk to the receiver through.
+            let payload = [UBX_CLASS_NMEA, id, u8::from(sentences.contains(&sentence))];
+            commands.push(Command::ubx("MSG", UBX_CFG_MSG, &payload, true));
+        }
+    }
+    if let Some(constellations) = constellations {
+        // Version 0, all the tracking channels there are, and a block for each constellation.
+        let mut payload = vec![0x00, 0x00, 0xff, UBX_GNSS.len() as u8];
+        for &(constellation, id, reserved, max) in UBX_GNSS {
+            payload.extend([id, reserved, max, 0x00]);
+            // Enabled or not, on its L1 signal.
+            let enabled = u32::from(constellations.contains(&constellation));
+            payload.extend((0x0001_0000 | enabled).to_le_bytes());
+        }
+        commands.push(Command::ubx("GNSS", UBX_CFG_GNSS, &payload, true));
+    }
+    if let Some(baudrate) = baudrate {
+        // UART1, at 8N1, taking UBX, NMEA and RTCM3 and sending UBX and NMEA.
+        let mut payload = vec![0x01, 0x00, 0x00, 0x00];
+        payload.extend(0x0000_08d0u32.to_le_bytes());
+        payload.extend(baudrate.to_le_bytes());
+        payload.extend(0x0023u16.to_le_bytes());
+        payload.extend(0x0003u16.to_le_bytes());
+        payload.extend([0x00; 4]);
+        commands.push(Command::ubx("PRT", UBX_CFG_PRT, &payload, false).switching_to(baudrate));
+    }
+
+    Ok(commands)
+}
+
+fn mediatek(
+    update_rate: Option<u32>,
+    sentences: Option<Vec<&str>>,
+    constellations: Option<&[Constellation]>,
+    baudrate: Option<u32>,
+) -> Result<Vec<Command>, String> {
+    let mut commands = vec![];
+    if let Some(rate) = update_rate {
+        let data = format!("PMTK220,{}", 1000 / rate);
+        commands.push(Command::nmea(&data, Answer::Mediatek(220)));
+    }
+    if let Some(sentences) = sentences {
+        let mut fields = vec!["0"; MTK_314_LEN];
+        for sentence in sentences {
+            let &(_, field) = MTK_314_FIELDS
+                .iter()
+                .find(|(t, _)| *t == sentence)
+                .ok_or_else(|| format!("MediaTek receivers can't send {}", sentence))?;
+            fields[field] = "1";
+        }
+        let data = format!("PMTK314,{}", fields.join(","));
+        commands.push(Command::nmea(&data, Answer::Mediatek(314)));
+    }
+    if let Some(constellations) = constellations {
+        if constellations.contains(&Constellation::Qzss) {
+            return Err("MediaTek receivers can't be told to use QZSS".to_string());
+        }
+        let on = |c| {
+            if constellations.contains(&c) {
+                "1"
+            } else {
+                "0"
+            }
+        };
+        let data = format!(
+            "PMTK353,{},{},{},0,{}",
+            on(Constellation::Gps),
+            on(Constellation::Glonass),
+            on(Constellation::Galileo),
+            on(Constellation::Beidou)
+        );
+        commands.push(Command::nmea(&data, Answer::Mediatek(353)));
+        let data = format!("PMTK313,{}", on(Constellation::Sbas));
+        commands.push(Command::nmea(&data, Answer::Mediatek(313)));
+    }
+    if let Some(baudrate) = baudrate {
+        let data = format!("PMTK251,{}", baudrate);
+        commands.push(Command::nmea(&data, Answer::None).switching_to(baudrate));
+    }
+
+    Ok(commands)
+}
+
+fn sirf(
+    update_rate: Option<u32>,
+    sentences: Option<Vec<&str>>,
+    constellations: Option<&[Constellation]>,
+    baudrate: Option<u32>,
+) -> Result<Vec<Command>, String> {
+    if update_rate.is_some_and(|rate| rate != 1) {
+        return Err("SiRF receivers only report once per second in NMEA mode".to_string());
+    }
+    if constellations.is_some() {
+        return Err("SiRF receivers can't be told which constellations to use".to_string());
+    }
+
+    let mut commands = vec![];
+    if let Some(sentences) = sentences {
+        for sentence in &sentences {
+            if !SIRF_103_MESSAGES.iter().any(|(t, _)| t == sentence) {
+                return Err(format!("SiRF receivers can't send {}", sentence));
+            }
+        }
+
+        for &(sentence, message) in SIRF_103_MESSAGES {
+            // Query mode 0 sets the rate, in seconds, 0 turning the sentence off.
+            let rate = u8::from(sentences.contains(&sentence));
+            let data = format!("PSRF103,{:02},00,{:02},01", message, rate);
+            commands.push(Command::nmea(&data, Answer::None));
+        }
+    }
+    if let Some(baudrate) = baudrate {
+        let data = format!("PSRF100,1,{},8,1,0", baudrate);
+        commands.push(Command::nmea(&data, Answer::None).switching_to(baudrate));
+    }
+
+    Ok(commands)
+}
+
+/// Configures each receiver the feed reads from, as it shows up.
+pub struct Configurator {
+    feed: Arc<Feed>,
+    receiver: Mutex<Option<(Vendor, Settings)>>,
+    // Whether the receiver is to be configured (again), even though it's the same one.
+    pending: AtomicBool,
+}
+
+impl Configurator {
+    pub fn new(feed: Arc<Feed>, vendor: Vendor, settings: Settings) -> Self {
+        Configurator {
+            feed,
+            receiver: Mutex::new(Some((vendor, settings))),
+            pending: AtomicBool::new(true),
+        }
+    }
+
+    /// Changes what to configure receivers with, applying it to the current one.
+    pub fn set(&self, receiver: Option<(Vendor, Settings)>) {
+        // unwrap cause we don't want a poisoned lock:
+        // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+        *self.receiver.lock().unwrap() = receiver;
+        self.pending.store(true, Ordering::Relaxed);
+    }
+
+    /// Configures receivers once they start talking.
+    pub fn run(&self) {
+        let mut configured: Option<(&'static str, Option<PathBuf>)> = None;
+
+        loop {
+            let subscription = self.feed.subscribe_live(Event::is_sentence);
+
+            while let Some(event) = subscription.recv() {
+                let line = match event {
+                    Event::Sentence(line) => line,
+                    _ => continue,
+                };
+                let device = match self.feed.device() {
+                    Some(device) if device.kind != "none" => device,
+                    // Whatever gets plugged in next needs configuring, even if it's the same.
+                    _ => {
+                        configured = None;
+
+                        continue;
+                    }
+                };
+
+                let current = Some((device.kind, device.path));
+                if configured != current || line.contains(reconnect::RECONNECTED) {
+                    self.pending.store(true, Ordering::Relaxed);
+                }
+                if !self.pending.swap(false, Ordering::Relaxed) {
+                    continue;
+                }
+                configured = current;
+
+                // unwrap cause we don't want a poisoned lock:
+                // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
+                let receiver = self.receiver.lock().unwrap().clone();
+                if let Some((vendor, settings)) = receiver {
+                    match configure(&self.feed, vendor, &settings) {
+                        Ok(()) => println!("Receiver configured"),
+                        Err(e) => println!("Failed to configure receiver: {}", e),
+                    }
+                }
+            }
+        }
+    }
+}
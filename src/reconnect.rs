@@ -0,0 +1,202 @@
+/* vim: set et ts=4 sw=4: */
+/* reconnect.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+use crate::fix::Report;
+use crate::gps::{Device, GPS};
+use crate::nmea;
+use crate::ubx;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// How long to wait for the next attempt at a time, so that the feed gets to check for device
+// switches and requests.
+const WAIT_STEP: Duration = Duration::from_secs(1);
+
+/// What clients are told once the device is back.
+pub const RECONNECTED: &str = "GPS device reconnected";
+
+type Reopen = Box<dyn FnMut() -> io::Result<Box<dyn GPS>> + Send>;
+
+/// Reopens the device it wraps when that goes away, e.g when a USB receiver is unplugged.
+///
+/// Clients are told about it through `$GPTXT` sentences, which are passed on like any other.
+pub struct Reconnecting {
+    gps: Option<Box<dyn GPS>>,
+    // What we know of the device, even while it's gone.
+    device: Device,
+    reopen: Reopen,
+    // When to try reopening the device next, and how long to wait after that if it fails.
+    retry: (Instant, Duration),
+    // Whether reopening failed since the device went away, which only gets reported once.
+    failing: bool,
+}
+
+impl Reconnecting {
+    /// Wraps `gps`, using `reopen` to get it back once it's gone.
+    pub fn new<F>(gps: Box<dyn GPS>, reopen: F) -> Self
+    where
+        F: FnMut() -> io::Result<Box<dyn GPS>> + Send + 'static,
+    {
+        Reconnecting {
+            device: Device::of(&*gps),
+            gps: Some(gps),
+            reopen: Box::new(reopen),
+            retry: (Instant::now(), INITIAL_BACKOFF),
+            failing: false,
+        }
+    }
+
+    fn lost(&mut self, reason: &dyn std::fmt::Display, buffer: &mut String) -> usize {
+        println!("GPS device lost ({}), reconnecting..", reason);
+        self.gps = None;
+        self.retry = (Instant::now() + INITIAL_BACKOFF, INITIAL_BACKOFF);
+        self.failing = false;
+
+        notice(buffer, "01", "GPS device lost")
+    }
+
+    fn current(&mut self) -> io::Result<&mut Box<dyn GPS>> {
+        self.gps
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "GPS device is gone"))
+    }
+
+    fn reconnect(&mut self, buffer: &mut String) -> io::Result<usize> {
+        let (at, backoff) = self.retry;
+        if let Some(left) = at.checked_duration_since(Instant::now()) {
+            thread::sleep(left.min(WAIT_STEP));
+
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "waiting to reopen the GPS device",
+            ));
+        }
+
+        match (self.reopen)() {
+            Ok(gps) => {
+                println!("GPS device reconnected");
+                self.device = Device::of(&*gps);
+                self.gps = Some(gps);
+
+                Ok(notice(buffer, "02", RECONNECTED))
+            }
+
+            Err(e) => {
+                if !self.failing {
+                    println!("Failed to reopen GPS device ({}), retrying..", e);
+                    self.failing = true;
+                }
+                let backoff = (backoff * 2).min(MAX_BACKOFF);
+                self.retry = (Instant::now() + backoff, backoff);
+
+                Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "GPS device still gone",
+                ))
+            }
+        }
+    }
+}
+
+impl GPS for Reconnecting {
+    fn read_line(&mut self, buffer: &mut String) -> io::Result<usize> {
+        let gps = match self.gps {
+            Some(ref mut gps) => gps,
+            None => return self.reconnect(buffer),
+        };
+
+        match gps.read_line(buffer) {
+            Ok(0) => Ok(self.lost(&"end of stream", buffer)),
+
+            Err(e) if is_fatal(&e) => Ok(self.lost(&e, buffer)),
+
+            result => result,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        self.device.kind
+    }
+
+    fn path(&self) -> Option<&Path> {
+        self.device.path.as_deref()
+    }
+
+    fn baudrate(&self) -> Option<u32> {
+        self.device.baudrate
+    }
+
+    fn take_report(&mut self) -> Option<Report> {
+        self.gps.as_mut().and_then(|gps| gps.take_report())
+    }
+
+    fn take_ack(&mut self) -> Option<ubx::Ack> {
+        self.gps.as_mut().and_then(|gps| gps.take_ack())
+    }
+
+    fn take_version(&mut self) -> Option<ubx::Version> {
+        self.gps.as_mut().and_then(|gps| gps.take_version())
+    }
+
+    fn take_rtcm(&mut self) -> Option<Vec<u8>> {
+        self.gps.as_mut().and_then(|gps| gps.take_rtcm())
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.current()?.write(data)
+    }
+
+    fn set_baudrate(&mut self, baudrate: u32) -> io::Result<()> {
+        self.current()?.set_baudrate(baudrate)?;
+        self.device.baudrate = Some(baudrate);
+
+        Ok(())
+    }
+}
+
+// Timeouts just mean the device is quiet and garbage on the line is not the end of the world.
+pub fn is_fatal(e: &io::Error) -> bool {
+    !matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::InvalidData
+    )
+}
+
+// Replaces whatever partial line is in `buffer` with a TXT sentence carrying `text`.
+pub fn notice(buffer: &mut String, severity: &str, text: &str) -> usize {
+    buffer.clear();
+    buffer.push_str(&nmea::with_checksum(&format!(
+        "GPTXT,01,01,{},gps-share: {}",
+        severity, text
+    )));
+
+    buffer.len()
+}
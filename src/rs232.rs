@@ -21,102 +21,140 @@
  * Author: Zeeshan Ali <zeeshanak@gnome.org>
  */
 
-use crate::config::Config;
-use crate::gps::GPS;
-use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
+use crate::fix::Report;
+use crate::gps::{GPS, READ_TIMEOUT};
+use crate::ubx;
+use crate::verify::{self, MAX_LINE};
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
 use std::io;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::path::Path;
-use std::rc::Rc;
+use std::io::Read;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+// The rates GPS receivers commonly talk at, and how long to listen at each when looking for the
+// right one.
+const BAUDRATES: &[u32] = &[4800, 9600, 19200, 38400, 57600, 115200, 230400];
+const BAUDRATE_TIMEOUT: Duration = Duration::from_millis(3_000);
+const PROBE_READ_TIMEOUT: Duration = Duration::from_millis(250);
+
 pub struct RS232 {
     reader: BufReader<Box<dyn SerialPort>>,
+    path: PathBuf,
+    baudrate: u32,
+    ubx: ubx::Decoder,
 }
 
 impl RS232 {
-    pub fn new(config: Rc<Config>) -> io::Result<Self> {
-        match config.dev_path {
-            Some(ref path) => RS232::new_for_path(path.as_path(), &config),
-            None => RS232::new_detect(&config),
-        }
-    }
-
-    fn new_for_path(path: &Path, config: &Config) -> io::Result<Self> {
-        let port = serialport::new(path.to_string_lossy(), config.baudrate)
+    pub fn new_for_path(path: &Path, baudrate: u32) -> io::Result<Self> {
+        let port = serialport::new(path.to_string_lossy(), baudrate)
             .data_bits(DataBits::Eight)
             .parity(Parity::None)
             .stop_bits(StopBits::One)
             .flow_control(FlowControl::None)
-            .timeout(Duration::from_millis(3_000))
+            .timeout(READ_TIMEOUT)
             .open()?;
 
         Ok(RS232 {
             reader: BufReader::new(port),
+            path: path.to_path_buf(),
+            baudrate,
+            ubx: ubx::Decoder::default(),
         })
     }
 
-    fn new_detect(config: &Config) -> io::Result<Self> {
-        println!("Attempting to autodetect GPS device...");
-        let mut enumerator = udev::Enumerator::new()?;
-        enumerator.match_subsystem("tty")?;
-        enumerator.match_property("ID_BUS", "usb")?;
-        let devices = enumerator.scan_devices()?;
-        for d in devices {
-            if let Some(driver) = d.parent().as_ref().and_then(|p| p.driver()) {
-                if driver != "pl2303" && driver != "cdc_acm" {
-                    continue;
-                }
-            }
-
-            if let Some(p) = d.devnode().and_then(|devnode| devnode.to_str()) {
-                let path = Path::new(p);
+    /// Looks for the baudrate the device talks NMEA at, starting with the one it was opened at,
+    /// and stays at it. Returns it, if any.
+    pub fn detect_baudrate(&mut self) -> io::Result<Option<u32>> {
+        let others = BAUDRATES.iter().filter(|&&rate| rate != self.baudrate);
+        let rates: Vec<u32> = std::iter::once(self.baudrate)
+            .chain(others.copied())
+            .collect();
 
-                match RS232::new_for_path(&path, config) {
-                    Ok(mut gps) => {
-                        if gps.verify() {
-                            println!("Detected {} as a GPS device", p);
+        self.reader.get_mut().set_timeout(PROBE_READ_TIMEOUT)?;
+        let mut found = None;
+        for rate in rates {
+            self.change_baudrate(rate)?;
 
-                            return Ok(gps);
-                        }
-                    }
+            if self.talks_nmea() {
+                found = Some(rate);
 
-                    Err(e) => println!("Error openning {}: {}", p, e),
-                }
+                break;
             }
         }
+        self.reader.get_mut().set_timeout(READ_TIMEOUT)?;
 
-        Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Failed to autodetect GPS device",
-        ))
+        Ok(found)
     }
 
-    fn verify(&mut self) -> bool {
-        let mut buffer = String::new();
-
-        for _ in 1..3 {
-            if let Ok(_) = self.read_line(&mut buffer) {
-                if buffer.len() >= 15
-                    && buffer.chars().nth(0) == Some('$')
-                    && buffer.chars().nth(6) == Some(',')
-                {
-                    return true;
-                }
-
-                buffer.clear();
-            } else {
-                println!("Failed to read from serial port");
-            }
-        }
+    fn change_baudrate(&mut self, baudrate: u32) -> io::Result<()> {
+        let port = self.reader.get_mut();
+        port.set_baud_rate(baudrate)?;
+        port.clear(ClearBuffer::Input)?;
 
-        false
+        // Whatever was read at the previous rate is of no use.
+        let buffered = self.reader.buffer().len();
+        self.reader.consume(buffered);
+        self.ubx.discard();
+        self.baudrate = baudrate;
+
+        Ok(())
+    }
+
+    // Whether NMEA comes through at the current baudrate.
+    fn talks_nmea(&mut self) -> bool {
+        let reader = &mut self.reader;
+
+        verify::verify(
+            |line| reader.take(MAX_LINE).read_until(b'\n', line),
+            BAUDRATE_TIMEOUT,
+        )
     }
 }
 
 impl GPS for RS232 {
     fn read_line(&mut self, buffer: &mut String) -> io::Result<usize> {
-        self.reader.read_line(buffer)
+        self.ubx.read_line(&mut self.reader, buffer)
+    }
+
+    fn kind(&self) -> &'static str {
+        "RS232"
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    fn baudrate(&self) -> Option<u32> {
+        Some(self.baudrate)
+    }
+
+    fn take_report(&mut self) -> Option<Report> {
+        self.ubx.take_report()
+    }
+
+    fn take_ack(&mut self) -> Option<ubx::Ack> {
+        self.ubx.take_ack()
+    }
+
+    fn take_version(&mut self) -> Option<ubx::Version> {
+        self.ubx.take_version()
+    }
+
+    fn take_rtcm(&mut self) -> Option<Vec<u8>> {
+        self.ubx.take_rtcm()
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let port = self.reader.get_mut();
+        port.write_all(data)?;
+
+        port.flush()
+    }
+
+    fn set_baudrate(&mut self, baudrate: u32) -> io::Result<()> {
+        self.change_baudrate(baudrate)
     }
 }
@@ -0,0 +1,69 @@
+/* vim: set et ts=4 sw=4: */
+/* rtcm.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! RTCM3 framing, for the corrections that base stations send and rovers take.
+
+use crate::ubx::Frame;
+
+pub const PREAMBLE: u8 = 0xd3;
+
+/// Checksums frames, from preamble to the end of the message.
+pub fn crc24q(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+
+    for &byte in data {
+        crc ^= u32::from(byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x0100_0000 != 0 {
+                crc ^= 0x0186_4cfb;
+            }
+        }
+    }
+
+    crc & 0x00ff_ffff
+}
+
+/// Parses the frame `data` starts with, from its preamble.
+pub fn parse_frame(data: &[u8]) -> Frame<Vec<u8>> {
+    let header = match data.get(1..3) {
+        Some(header) => header,
+        None => return Frame::Partial,
+    };
+    // The 6 bits before the length are reserved, and always 0.
+    if header[0] & 0xfc != 0 {
+        return Frame::Invalid;
+    }
+    let len = 3 + usize::from(u16::from_be_bytes([header[0], header[1]])) + 3;
+
+    let frame = match data.get(..len) {
+        Some(frame) => frame,
+        None => return Frame::Partial,
+    };
+    let (message, crc) = frame.split_at(len - 3);
+    if crc24q(message).to_be_bytes()[1..] != *crc {
+        return Frame::Invalid;
+    }
+
+    Frame::Complete(frame.to_vec(), len)
+}
@@ -22,177 +22,519 @@
  */
 
 use crate::avahi;
+use crate::broadcast::Subscription;
+use crate::caster::Caster;
 use crate::client_handler::{ClientHandler, Stream};
+use crate::clients::Clients;
 use crate::config::Config;
+use crate::dbus::DBus;
+use crate::feed::{Event, Feed};
 use crate::gps;
+use crate::gpsd::Gpsd;
+use crate::ntrip::NtripClient;
+use crate::receiver::Configurator;
 use std::io;
 use std::net::TcpListener;
 use std::os::unix::net::UnixListener;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, mpsc};
 use std::thread;
+use std::time::Duration;
 
 pub struct Server {
-    gps: Arc<Mutex<dyn gps::GPS>>,
-    tcp_listener: Option<Arc<Mutex<TcpListener>>>,
-    unix_listener: Option<Arc<Mutex<UnixListener>>>,
+    gps: Option<Box<dyn gps::GPS>>,
+    feed: Arc<Feed>,
+    clients: Arc<Clients>,
+    tcp_listener: Option<TcpListener>,
+    unix_listener: Option<UnixListener>,
+    gpsd: Option<Arc<Gpsd>>,
+    caster: Option<Arc<Caster>>,
     avahi: Option<avahi::Avahi>,
+    dbus: Option<Arc<DBus>>,
+    receiver: Option<Arc<Configurator>>,
+    ntrip: Option<Arc<NtripClient>>,
+    // What we actually serve on, which can differ from the configuration after a reload.
+    tcp_port: Option<u16>,
+    socket_path: Option<String>,
     config: Rc<Config>,
 }
 
 impl Server {
-    pub fn new<T: gps::GPS>(gps: T, config: Rc<Config>) -> io::Result<Self> {
-        let ip = config.get_ip();
+    pub fn new(gps: Box<dyn gps::GPS>, config: Rc<Config>) -> io::Result<Self> {
         let tcp_listener = if config.no_tcp {
             None
         } else {
-            Some(Arc::new(Mutex::new(TcpListener::bind((
-                ip.as_str(),
-                config.port,
-            ))?)))
+            Some(bind_tcp(&config)?)
         };
 
         let path = &config.socket_path;
         let unix_listener = match path {
-            Some(p) => Some(Arc::new(Mutex::new(UnixListener::bind(p)?))),
+            Some(p) => Some(UnixListener::bind(p)?),
             None => None,
         };
 
         let avahi = if config.announce_on_net {
-            match avahi::Avahi::new() {
-                Ok(avahi) => Some(avahi),
-
-                Err(e) => {
-                    println!("Failed to connect to Avahi: {}", e);
-
-                    None
-                }
-            }
+            connect_avahi()
         } else {
             None
         };
 
+        let feed = Arc::new(Feed::new(&config));
+        let clients = Arc::new(Clients::default());
+
+        let gpsd = match config.gpsd_port {
+            Some(port) => Some(Arc::new(Gpsd::new(
+                &config.get_ip(),
+                port,
+                feed.clone(),
+                clients.clone(),
+            )?)),
+            None => None,
+        };
+
+        let caster = match config.caster_port {
+            Some(port) => Some(Arc::new(Caster::new(
+                &config.get_ip(),
+                port,
+                &config.caster_mountpoint,
+                feed.clone(),
+                clients.clone(),
+            )?)),
+            None => None,
+        };
+
+        let dbus = start_dbus(&config, &feed, &clients);
+        let receiver = config.receiver.map(|vendor| {
+            Arc::new(Configurator::new(
+                feed.clone(),
+                vendor,
+                config.receiver_settings.clone(),
+            ))
+        });
+
+        let ntrip = ntrip_client(&config, &feed);
+
         Ok(Server {
-            gps: Arc::new(Mutex::new(gps)),
-            tcp_listener: tcp_listener,
-            unix_listener: unix_listener,
-            avahi: avahi,
-            config: config,
+            gps: Some(gps),
+            feed,
+            clients,
+            tcp_listener,
+            unix_listener,
+            gpsd,
+            caster,
+            avahi,
+            dbus,
+            receiver,
+            ntrip,
+            tcp_port: None,
+            socket_path: None,
+            config,
         })
     }
 
-    pub fn run(&mut self) -> io::Result<()> {
-        let config = &self.config;
-
-        let streams: Vec<Stream> = vec![];
-        let streams_arc = Arc::new(Mutex::new(streams));
-
-        let unix_thread = self.unix_listener.as_ref().map(|listener| {
-            let listener = listener.clone();
-            let streams_arc = streams_arc.clone();
-            let gps = self.gps.clone();
-            thread::spawn(move || {
-                let listener = listener.lock().unwrap();
-                loop {
-                    match listener.accept() {
-                        Ok((stream, _addr)) => {
-                            let launch_handler;
-                            {
-                                // unwrap cause we don't want a poisoned lock:
-                                // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
-                                let mut streams = streams_arc.lock().unwrap();
-                                streams.push(Stream::Unix(stream));
-                                launch_handler = streams.len() == 1;
-                            }
-
-                            if launch_handler {
-                                let handler = ClientHandler::new(gps.clone(), streams_arc.clone());
-
-                                thread::spawn(move || {
-                                    handler.handle();
-                                });
-                            }
+    /// Serves until the end of times, applying the configurations received from `reloads`.
+    pub fn run(&mut self, reloads: mpsc::Receiver<Config>) -> io::Result<()> {
+        let feed = self.feed.clone();
+        let gps = self.gps.take().expect("server is only run once");
+        thread::spawn(move || {
+            feed.run(gps);
+        });
+
+        if let Some(ref dbus) = self.dbus {
+            serve_dbus(dbus.clone());
+        }
+
+        if let Some(ref receiver) = self.receiver {
+            configure_receivers(receiver.clone());
+        }
+
+        if let Some(ref ntrip) = self.ntrip {
+            receive_corrections(ntrip.clone());
+        }
+
+        if let Some(listener) = self.unix_listener.take() {
+            self.serve_unix(listener);
+        }
+
+        if let Some(listener) = self.tcp_listener.take() {
+            self.serve_tcp(listener)?;
+        }
+
+        if let Some(ref gpsd) = self.gpsd {
+            serve_gpsd(gpsd.clone())?;
+        }
+
+        if let Some(ref caster) = self.caster {
+            serve_caster(caster.clone())?;
+        }
+
+        if self.tcp_port.is_none()
+            && self.socket_path.is_none()
+            && self.gpsd.is_none()
+            && self.caster.is_none()
+            && self.dbus.is_none()
+        {
+            panic!("Sharing not configured");
+        }
+
+        for config in reloads {
+            self.reload(config);
+        }
+
+        // The sending end only goes away when the program is exiting.
+        Ok(())
+    }
+
+    /// Applies what can be applied of `config` without reopening the device or dropping clients.
+    fn reload(&mut self, config: Config) {
+        let old = std::mem::replace(&mut self.config, Rc::new(config));
+        let config = self.config.clone();
+        let mut needs_restart = vec![];
+
+        self.feed.reconfigure(&config);
+
+        if config.dev_path != old.dev_path
+            || config.baudrate != old.baudrate
+            || config.usb_drivers != old.usb_drivers
+            || config.usb_ids != old.usb_ids
+            || config.udev_properties != old.udev_properties
+        {
+            needs_restart.push("device");
+        }
+
+        if config.receiver != old.receiver || config.receiver_settings != old.receiver_settings {
+            let receiver = config
+                .receiver
+                .map(|vendor| (vendor, config.receiver_settings.clone()));
+
+            match (&self.receiver, receiver) {
+                (Some(configurator), receiver) => configurator.set(receiver),
+                (None, Some((vendor, settings))) => {
+                    let configurator =
+                        Arc::new(Configurator::new(self.feed.clone(), vendor, settings));
+                    configure_receivers(configurator.clone());
+                    self.receiver = Some(configurator);
+                }
+                (None, None) => {}
+            }
+        }
+
+        if config.ntrip != old.ntrip
+            || config.ntrip_version != old.ntrip_version
+            || config.ntrip_gga_interval != old.ntrip_gga_interval
+        {
+            if self.ntrip.is_some() {
+                needs_restart.push("NTRIP client");
+            } else {
+                self.ntrip = ntrip_client(&config, &self.feed);
+                if let Some(ref ntrip) = self.ntrip {
+                    receive_corrections(ntrip.clone());
+                }
+            }
+        }
+
+        // Services can be added but moving or stopping them would drop their clients.
+        match self.tcp_port {
+            None if !config.no_tcp => match bind_tcp(&config) {
+                Ok(listener) => {
+                    if let Err(e) = self.serve_tcp(listener) {
+                        println!("Failed to start TCP service: {}", e);
+                    }
+                }
+                Err(e) => println!("Failed to start TCP service: {}", e),
+            },
+            Some(port)
+                if config.no_tcp || port != config.port || config.net_iface != old.net_iface =>
+            {
+                needs_restart.push("TCP service")
+            }
+            _ => {}
+        }
+
+        match (&self.socket_path, &config.socket_path) {
+            (None, Some(path)) => match UnixListener::bind(path) {
+                Ok(listener) => self.serve_unix(listener),
+                Err(e) => println!("Failed to listen on {}: {}", path, e),
+            },
+            (Some(current), path) if Some(current) != path.as_ref() => {
+                needs_restart.push("local socket")
+            }
+            _ => {}
+        }
+
+        match (&self.gpsd, config.gpsd_port) {
+            (None, Some(port)) => {
+                match Gpsd::new(
+                    &config.get_ip(),
+                    port,
+                    self.feed.clone(),
+                    self.clients.clone(),
+                ) {
+                    Ok(gpsd) => {
+                        let gpsd = Arc::new(gpsd);
+                        match serve_gpsd(gpsd.clone()) {
+                            Ok(()) => self.gpsd = Some(gpsd),
+                            Err(e) => println!("Failed to start gpsd service: {}", e),
                         }
-                        Err(e) => {
-                            eprintln!("Local socket failed to accept connection: {}", e);
+                    }
+                    Err(e) => println!("Failed to start gpsd service: {}", e),
+                }
+            }
+            (Some(gpsd), port) if gpsd.port().ok() != port => needs_restart.push("gpsd service"),
+            _ => {}
+        }
+
+        match (&self.caster, config.caster_port) {
+            (None, Some(port)) => {
+                match Caster::new(
+                    &config.get_ip(),
+                    port,
+                    &config.caster_mountpoint,
+                    self.feed.clone(),
+                    self.clients.clone(),
+                ) {
+                    Ok(caster) => {
+                        let caster = Arc::new(caster);
+                        match serve_caster(caster.clone()) {
+                            Ok(()) => self.caster = Some(caster),
+                            Err(e) => println!("Failed to start NTRIP caster: {}", e),
                         }
                     }
+                    Err(e) => println!("Failed to start NTRIP caster: {}", e),
                 }
-            })
-        });
+            }
+            (Some(caster), port)
+                if caster.port().ok() != port
+                    || caster.mountpoint() != config.caster_mountpoint =>
+            {
+                needs_restart.push("NTRIP caster")
+            }
+            _ => {}
+        }
 
-        let tcp_thread: Option<Result<_, io::Error>> = self.tcp_listener.as_ref().map(|listener| {
-            let addr = {
-                let listener = listener.lock().unwrap();
-                listener.local_addr()
-            }?;
-            let port = addr.port();
+        match (&self.dbus, config.dbus) {
+            (None, Some(_)) => {
+                self.dbus = start_dbus(&config, &self.feed, &self.clients);
+                if let Some(ref dbus) = self.dbus {
+                    serve_dbus(dbus.clone());
+                }
+            }
+            (Some(dbus), bus) if Some(dbus.bus()) != bus => needs_restart.push("D-Bus service"),
+            _ => {}
+        }
 
-            match config.net_iface {
-                Some(ref i) => println!("TCP server bound on {} interface", i),
-                None => println!("TCP server bound on all interfaces"),
-            };
-            println!("Port: {}", port);
+        if config.announce_on_net != old.announce_on_net || config.net_iface != old.net_iface {
+            self.announce();
+        }
+
+        if !needs_restart.is_empty() {
+            println!(
+                "Restart needed to apply changes to: {}",
+                needs_restart.join(", ")
+            );
+        }
+        println!("Configuration reloaded");
+    }
+
+    // (Re)publishes or withdraws the TCP service on Avahi, per the configuration.
+    fn announce(&mut self) {
+        if !self.config.announce_on_net {
+            if let Some(mut avahi) = self.avahi.take() {
+                if let Err(e) = avahi.unpublish() {
+                    eprintln!("Failed to withdraw service from Avahi: {}", e);
+                }
+            }
+
+            return;
+        }
 
-            if let Some(ref avahi) = self.avahi {
-                let iface = config.net_iface.as_ref().map(|i| i.as_str());
+        let port = match self.tcp_port {
+            Some(port) => port,
+            None => return,
+        };
+        if self.avahi.is_none() {
+            self.avahi = connect_avahi();
+        }
+
+        if let Some(ref mut avahi) = self.avahi {
+            let iface = self.config.net_iface.as_deref();
 
-                if let Err(e) = avahi.publish(iface, port) {
-                    eprintln!("Failed to publish service on Avahi: {}", e);
-                };
+            if let Err(e) = avahi.publish(iface, port) {
+                eprintln!("Failed to publish service on Avahi: {}", e);
             };
+        };
+    }
 
-            let listener = listener.clone();
-            let streams_arc = streams_arc.clone();
-            let gps = self.gps.clone();
-            Ok(thread::spawn(move || {
-                let listener = listener.lock().unwrap();
-                loop {
-                    match listener.accept() {
-                        Ok((stream, addr)) => {
-                            println!("Connection from {}", addr.ip());
-                            let launch_handler;
-                            {
-                                // unwrap cause we don't want a poisoned lock:
-                                // https://doc.rust-lang.org/std/sync/struct.Mutex.html#poisoning
-                                let mut streams = streams_arc.lock().unwrap();
-                                streams.push(Stream::Tcp(stream));
-                                launch_handler = streams.len() == 1;
-                            }
-
-                            if launch_handler {
-                                let handler = ClientHandler::new(gps.clone(), streams_arc.clone());
-
-                                thread::spawn(move || {
-                                    handler.handle();
-                                });
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Connect from client failed: {}", e);
-                        }
+    fn serve_tcp(&mut self, listener: TcpListener) -> io::Result<()> {
+        let port = listener.local_addr()?.port();
+
+        match self.config.net_iface {
+            Some(ref i) => println!("TCP server bound on {} interface", i),
+            None => println!("TCP server bound on all interfaces"),
+        };
+        println!("Port: {}", port);
+
+        self.tcp_port = Some(port);
+        self.announce();
+
+        let feed = self.feed.clone();
+        let clients = self.clients.clone();
+        thread::spawn(move || {
+            loop {
+                match listener.accept() {
+                    Ok((stream, addr)) => {
+                        println!("Connection from {}", addr.ip());
+                        let subscription = feed.subscribe(Event::is_sentence);
+
+                        launch_client_handler(
+                            Stream::Tcp(stream),
+                            addr.to_string(),
+                            subscription,
+                            &feed,
+                            &clients,
+                        );
+                    }
+                    Err(e) => {
+                        eprintln!("Connect from client failed: {}", e);
                     }
                 }
-            }))
+            }
         });
 
-        // This method must never exit, so it must block on one of the joins.
-        if let Some(thread) = unix_thread {
-            match thread.join() {
-                Ok(_) => {}
-                Err(e) => eprintln!("Unix socket thread failed: {:?}", e),
-            }
-        }
+        Ok(())
+    }
+
+    fn serve_unix(&mut self, listener: UnixListener) {
+        self.socket_path = self.config.socket_path.clone();
 
-        if let Some(thread) = tcp_thread {
-            match thread?.join() {
-                Ok(_) => {}
-                Err(e) => eprintln!("TCP socket thread failed: {:?}", e),
+        let feed = self.feed.clone();
+        let clients = self.clients.clone();
+        thread::spawn(move || {
+            loop {
+                match listener.accept() {
+                    Ok((stream, _addr)) => {
+                        let subscription = feed.subscribe(Event::is_sentence);
+
+                        launch_client_handler(
+                            Stream::Unix(stream),
+                            "local".to_string(),
+                            subscription,
+                            &feed,
+                            &clients,
+                        );
+                    }
+                    Err(e) => {
+                        eprintln!("Local socket failed to accept connection: {}", e);
+                    }
+                }
             }
+        });
+    }
+}
+
+fn bind_tcp(config: &Config) -> io::Result<TcpListener> {
+    TcpListener::bind((config.get_ip().as_str(), config.port))
+}
+
+fn connect_avahi() -> Option<avahi::Avahi> {
+    match avahi::Avahi::new() {
+        Ok(avahi) => Some(avahi),
+
+        Err(e) => {
+            println!("Failed to connect to Avahi: {}", e);
+
+            None
         }
+    }
+}
+
+fn start_dbus(config: &Config, feed: &Arc<Feed>, clients: &Arc<Clients>) -> Option<Arc<DBus>> {
+    let bus = config.dbus?;
+
+    match DBus::new(bus, feed.clone(), clients.clone(), config.baudrate) {
+        Ok(dbus) => Some(Arc::new(dbus)),
 
-        // This can be hit when the TCP socket stops serving (so never),
-        // or when the TCP socket is not even requested.
-        panic!("Sharing ended or not configured");
+        Err(e) => {
+            println!("Failed to offer D-Bus service on the {} bus: {}", bus, e);
+
+            None
+        }
     }
 }
+
+fn serve_dbus(dbus: Arc<DBus>) {
+    thread::spawn(move || {
+        dbus.run();
+    });
+}
+
+fn configure_receivers(configurator: Arc<Configurator>) {
+    thread::spawn(move || {
+        configurator.run();
+    });
+}
+
+fn ntrip_client(config: &Config, feed: &Arc<Feed>) -> Option<Arc<NtripClient>> {
+    let mountpoint = config.ntrip.clone()?;
+    let gga_interval =
+        (config.ntrip_gga_interval > 0).then(|| Duration::from_secs(config.ntrip_gga_interval));
+
+    Some(Arc::new(NtripClient::new(
+        feed.clone(),
+        mountpoint,
+        config.ntrip_version,
+        gga_interval,
+    )))
+}
+
+fn receive_corrections(ntrip: Arc<NtripClient>) {
+    thread::spawn(move || {
+        ntrip.run();
+    });
+}
+
+fn serve_gpsd(gpsd: Arc<Gpsd>) -> io::Result<()> {
+    println!("gpsd JSON service on port {}", gpsd.port()?);
+
+    thread::spawn(move || {
+        gpsd.run();
+    });
+
+    Ok(())
+}
+
+fn serve_caster(caster: Arc<Caster>) -> io::Result<()> {
+    println!(
+        "NTRIP caster on port {}, mountpoint {}",
+        caster.port()?,
+        caster.mountpoint()
+    );
+
+    thread::spawn(move || {
+        caster.run();
+    });
+
+    Ok(())
+}
+
+fn launch_client_handler(
+    stream: Stream,
+    address: String,
+    subscription: Subscription<Event>,
+    feed: &Arc<Feed>,
+    clients: &Arc<Clients>,
+) {
+    let registration = match clients.add("nmea", address, &stream) {
+        Ok(registration) => registration,
+        Err(e) => {
+            eprintln!("Failed to set up client: {}", e);
+
+            return;
+        }
+    };
+    let handler = ClientHandler::new(stream, subscription, feed.clone(), registration);
+
+    thread::spawn(move || {
+        handler.handle();
+    });
+}
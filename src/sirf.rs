@@ -0,0 +1,341 @@
+/* vim: set et ts=4 sw=4: */
+/* sirf.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Decoder for the SiRF binary protocol of SiRFstar receivers.
+//!
+//! SiRF receivers talk either NMEA or SiRF binary, which some are left in after a bad
+//! configuration. A fix is put together from Geodetic Navigation Data, with the satellites in view
+//! from the Measured Tracker Data that comes along. See the SiRF Binary Protocol Reference Manual.
+
+use crate::fix::{self, Fix, Mode, Report, Satellite, Sky};
+use crate::nmea::{self, FixQuality, Talker};
+use crate::ubx::Frame;
+
+pub const START: [u8; 2] = [0xa0, 0xa2];
+const END: [u8; 2] = [0xb0, 0xb3];
+
+const MID_MEASURED_TRACKER: u8 = 4;
+const MID_GEODETIC_NAVIGATION: u8 = 41;
+const MID_SWITCH_TO_NMEA: u8 = 129;
+
+// The length is 15 bits, but no message comes close.
+const MAX_PAYLOAD: usize = 1023;
+
+// Sentences to send once back in NMEA mode, at 1 every second (or every 5 for GSV) with
+// checksums: GGA, GLL, GSA, GSV, RMC, VTG, MSS, EPE and ZDA, as gpsd does.
+const NMEA_RATES: [u8; 18] = [1, 1, 0, 1, 1, 1, 5, 1, 1, 1, 0, 1, 0, 1, 0, 1, 0, 1];
+
+/// Geodetic Navigation Data (MID 41).
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeodeticNavigation {
+    /// The kind of solution in the lower 3 bits, and whether DGPS corrections were used in bit 7.
+    pub nav_type: u16,
+    pub date: Option<nmea::Date>,
+    pub time: Option<nmea::Time>,
+    /// Bit `n` is set if satellite `n + 1` is used.
+    pub satellites_used: u32,
+    /// In degrees.
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Height above the ellipsoid and above mean sea level, in meters.
+    pub altitude: f64,
+    pub altitude_msl: f64,
+    /// Speed over ground in meters per second, and course over ground in degrees.
+    pub speed: f64,
+    pub course: f64,
+    /// In meters per second.
+    pub climb: f64,
+    /// Horizontal and vertical position error estimates, in meters.
+    pub horizontal_error: f64,
+    pub vertical_error: f64,
+    pub satellites: u8,
+    pub hdop: f64,
+}
+
+/// A channel of Measured Tracker Data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Channel {
+    pub prn: u8,
+    /// In degrees.
+    pub azimuth: f64,
+    pub elevation: f64,
+    /// Average carrier to noise ratio over the last second, in dBHz.
+    pub cno: u8,
+}
+
+/// Measured Tracker Data (MID 4): the satellites being tracked.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeasuredTracker {
+    pub channels: Vec<Channel>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    GeodeticNavigation(GeodeticNavigation),
+    MeasuredTracker(MeasuredTracker),
+    /// Anything we don't decode.
+    Other {
+        id: u8,
+    },
+}
+
+/// The 15-bit checksum of `payload`.
+pub fn checksum(payload: &[u8]) -> u16 {
+    payload
+        .iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(byte.into()))
+        & 0x7fff
+}
+
+/// Puts a message together, from start sequence to end sequence.
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = START.to_vec();
+    frame.extend((payload.len() as u16).to_be_bytes());
+    frame.extend(payload);
+    frame.extend(checksum(payload).to_be_bytes());
+    frame.extend(END);
+
+    frame
+}
+
+/// Switch To NMEA Protocol (MID 129), talking at `baudrate` from then on.
+pub fn switch_to_nmea(baudrate: u32) -> Vec<u8> {
+    // Mode 2 keeps the debug messages as they are.
+    let mut payload = vec![MID_SWITCH_TO_NMEA, 2];
+    payload.extend(NMEA_RATES);
+    payload.extend([0, 0]);
+    payload.extend((baudrate as u16).to_be_bytes());
+
+    frame(&payload)
+}
+
+/// Decodes a message, starting at its id. Returns `None` if it's too short for its kind.
+pub fn parse(payload: &[u8]) -> Option<Message> {
+    let p = Payload(payload);
+
+    let message = match *payload.first()? {
+        MID_GEODETIC_NAVIGATION if payload.len() >= 91 => {
+            let year = p.u16(11);
+            let date = nmea::Date {
+                year,
+                month: p.u8(13),
+                day: p.u8(14),
+            };
+            let time = nmea::Time {
+                hour: p.u8(15),
+                minute: p.u8(16),
+                second: f64::from(p.u16(17)) / 1000.0,
+            };
+
+            Message::GeodeticNavigation(GeodeticNavigation {
+                nav_type: p.u16(3),
+                // Receivers that don't know the time yet leave it all zeroes.
+                date: (year != 0).then_some(date),
+                time: (year != 0).then_some(time),
+                satellites_used: p.u32(19),
+                latitude: f64::from(p.i32(23)) * 1e-7,
+                longitude: f64::from(p.i32(27)) * 1e-7,
+                altitude: f64::from(p.i32(31)) / 100.0,
+                altitude_msl: f64::from(p.i32(35)) / 100.0,
+                speed: f64::from(p.u16(40)) / 100.0,
+                course: f64::from(p.u16(42)) / 100.0,
+                climb: f64::from(p.u16(46) as i16) / 100.0,
+                horizontal_error: f64::from(p.u32(50)) / 100.0,
+                vertical_error: f64::from(p.u32(54)) / 100.0,
+                satellites: p.u8(88),
+                hdop: f64::from(p.u8(89)) * 0.2,
+            })
+        }
+
+        MID_MEASURED_TRACKER if payload.len() >= 8 => {
+            let count = usize::from(p.u8(7));
+            if payload.len() < 8 + count * 15 {
+                return None;
+            }
+
+            let channels = (0..count)
+                .map(|i| {
+                    let offset = 8 + i * 15;
+                    let cno = payload[offset + 5..offset + 15]
+                        .iter()
+                        .map(|&c| u16::from(c))
+                        .sum::<u16>()
+                        / 10;
+
+                    Channel {
+                        prn: p.u8(offset),
+                        azimuth: f64::from(p.u8(offset + 1)) * 1.5,
+                        elevation: f64::from(p.u8(offset + 2)) / 2.0,
+                        cno: cno as u8,
+                    }
+                })
+                // Channels that aren't tracking anything are reported with PRN 0.
+                .filter(|channel| channel.prn != 0)
+                .collect();
+
+            Message::MeasuredTracker(MeasuredTracker { channels })
+        }
+
+        MID_GEODETIC_NAVIGATION | MID_MEASURED_TRACKER => return None,
+
+        id => Message::Other { id },
+    };
+
+    Some(message)
+}
+
+// Big-endian fields of a payload, whose length was checked beforehand.
+struct Payload<'a>(&'a [u8]);
+
+impl Payload<'_> {
+    fn u8(&self, offset: usize) -> u8 {
+        self.0[offset]
+    }
+
+    fn u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.0[offset], self.0[offset + 1]])
+    }
+
+    fn u32(&self, offset: usize) -> u32 {
+        let bytes = &self.0[offset..offset + 4];
+
+        u32::from_be_bytes(bytes.try_into().expect("is 4 bytes"))
+    }
+
+    fn i32(&self, offset: usize) -> i32 {
+        self.u32(offset) as i32
+    }
+}
+
+/// Parses the frame `data` starts with, from its first start char. Frames whose message can't be
+/// parsed are complete all the same.
+pub fn parse_frame(data: &[u8]) -> Frame<Option<Message>> {
+    let header = match data.get(1..4) {
+        Some(header) => header,
+        None => return Frame::Partial,
+    };
+    if header[0] != START[1] {
+        return Frame::Invalid;
+    }
+    let len = usize::from(u16::from_be_bytes([header[1], header[2]]));
+    if len > MAX_PAYLOAD {
+        return Frame::Invalid;
+    }
+
+    let rest = match data.get(4..4 + len + 4) {
+        Some(rest) => rest,
+        None => return Frame::Partial,
+    };
+    let (payload, trailer) = rest.split_at(len);
+    if trailer[..2] != checksum(payload).to_be_bytes() || trailer[2..] != END {
+        return Frame::Invalid;
+    }
+
+    Frame::Complete(parse(payload), 4 + len + 4)
+}
+
+/// Makes reports of SiRF messages, one for each Geodetic Navigation Data.
+#[derive(Default)]
+pub struct Decoder {
+    // Measured Tracker Data comes separately, at its own pace.
+    sky: Sky,
+}
+
+impl Decoder {
+    /// Takes `message` in, returning a report if it completes one.
+    pub fn handle(&mut self, message: Message) -> Option<Report> {
+        match message {
+            Message::MeasuredTracker(tracker) => {
+                self.sky.satellites = tracker.channels.iter().map(satellite).collect();
+
+                None
+            }
+
+            Message::GeodeticNavigation(navigation) => {
+                let mut sky = self.sky.clone();
+                for satellite in &mut sky.satellites {
+                    satellite.used = (1..=32).contains(&satellite.prn)
+                        && navigation.satellites_used & (1 << (satellite.prn - 1)) != 0;
+                }
+                sky.hdop = (navigation.hdop > 0.0).then_some(navigation.hdop);
+
+                let mut fix = Fix::default();
+                if let (Some(date), Some(time)) = (navigation.date, navigation.time) {
+                    fix.time = Some(fix::timestamp(&date, &time));
+                }
+                fix.satellites_used = Some(navigation.satellites);
+                set_position(&mut fix, &navigation);
+
+                Some(Report { fix, sky })
+            }
+
+            Message::Other { .. } => None,
+        }
+    }
+}
+
+fn set_position(fix: &mut Fix, navigation: &GeodeticNavigation) {
+    fix.mode = match navigation.nav_type & 0x07 {
+        // 3 satellites in the Kalman filter, or a 2D least squares solution.
+        3 | 5 => Mode::Fix2D,
+        // More than 3 satellites in the Kalman filter, or a 3D least squares solution.
+        4 | 6 => Mode::Fix3D,
+        // Dead reckoning.
+        7 => Mode::Fix2D,
+        _ => Mode::NoFix,
+    };
+    if fix.mode == Mode::NoFix {
+        fix.quality = Some(FixQuality::Invalid);
+
+        return;
+    }
+
+    fix.quality = Some(match navigation.nav_type {
+        t if t & 0x07 == 7 => FixQuality::Estimated,
+        t if t & 0x80 != 0 => FixQuality::Dgps,
+        _ => FixQuality::Gps,
+    });
+    fix.latitude = Some(navigation.latitude);
+    fix.longitude = Some(navigation.longitude);
+    fix.altitude = Some(navigation.altitude_msl);
+    fix.geoid_separation = Some(navigation.altitude - navigation.altitude_msl);
+    fix.speed = Some(navigation.speed);
+    fix.track = Some(navigation.course);
+    fix.climb = Some(navigation.climb);
+    // The receiver only gives a horizontal estimate, which holds for either axis.
+    fix.epx = Some(navigation.horizontal_error);
+    fix.epy = Some(navigation.horizontal_error);
+    fix.epv = Some(navigation.vertical_error);
+}
+
+fn satellite(channel: &Channel) -> Satellite {
+    Satellite {
+        // SBAS satellites are numbered along with GPS ones, and SiRF receivers track nothing else.
+        talker: Talker::Gps,
+        prn: channel.prn.into(),
+        elevation: Some(channel.elevation.round() as i16),
+        azimuth: Some(channel.azimuth.round() as u16),
+        snr: (channel.cno > 0).then_some(channel.cno),
+        used: false,
+    }
+}
@@ -21,21 +21,47 @@
  * Author: Zeeshan Ali <zeeshanak@gnome.org>
  */
 
+use crate::fix::Report;
 use crate::gps::GPS;
+use crate::ubx;
 use std::io;
 
 pub struct StdinGPS {
     stdin: io::Stdin,
+    ubx: ubx::Decoder,
 }
 
 impl StdinGPS {
     pub fn new() -> Self {
-        StdinGPS { stdin: io::stdin() }
+        StdinGPS {
+            stdin: io::stdin(),
+            ubx: ubx::Decoder::default(),
+        }
     }
 }
 
 impl GPS for StdinGPS {
     fn read_line(&mut self, buffer: &mut String) -> io::Result<usize> {
-        self.stdin.read_line(buffer)
+        self.ubx.read_line(&mut self.stdin.lock(), buffer)
+    }
+
+    fn kind(&self) -> &'static str {
+        "stdin"
+    }
+
+    fn take_report(&mut self) -> Option<Report> {
+        self.ubx.take_report()
+    }
+
+    fn take_ack(&mut self) -> Option<ubx::Ack> {
+        self.ubx.take_ack()
+    }
+
+    fn take_version(&mut self) -> Option<ubx::Version> {
+        self.ubx.take_version()
+    }
+
+    fn take_rtcm(&mut self) -> Option<Vec<u8>> {
+        self.ubx.take_rtcm()
     }
 }
@@ -0,0 +1,217 @@
+/* vim: set et ts=4 sw=4: */
+/* synthesis.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Making up NMEA sentences from a `Report`, for clients that only speak NMEA when the receiver
+//! speaks a binary protocol.
+
+use crate::fix::{self, Fix, Mode, Report, Satellite};
+use crate::nmea::{self, FixQuality, Talker};
+
+const METERS_PER_SECOND_PER_KNOT: f64 = 1852.0 / 3600.0;
+
+/// The GGA, GSA, GSV, RMC and GST sentences for `report`, in that order.
+pub fn sentences(report: &Report) -> Vec<String> {
+    let fix = &report.fix;
+    let time = fix.time.map(fix::date_time);
+    let clock = time.map_or(String::new(), |(_, t)| {
+        format!("{:02}{:02}{:05.2}", t.hour, t.minute, t.second)
+    });
+    let position = if fix.has_position() {
+        format!(
+            "{},{}",
+            coordinate(fix.latitude, 2, 'N', 'S'),
+            coordinate(fix.longitude, 3, 'E', 'W')
+        )
+    } else {
+        ",,,".to_string()
+    };
+
+    let mut sentences = vec![gga(report, &clock, &position), gsa(report)];
+    sentences.extend(gsv(&report.sky.satellites));
+
+    let date = time.map_or(String::new(), |(d, _)| {
+        format!("{:02}{:02}{:02}", d.day, d.month, d.year % 100)
+    });
+    sentences.push(rmc(fix, &clock, &position, &date));
+    if fix.epx.is_some() || fix.epy.is_some() || fix.epv.is_some() {
+        sentences.push(nmea::with_checksum(&format!(
+            "GPGST,{},,,,,{},{},{}",
+            clock,
+            number(fix.epy, 1),
+            number(fix.epx, 1),
+            number(fix.epv, 1)
+        )));
+    }
+
+    sentences
+}
+
+fn gga(report: &Report, clock: &str, position: &str) -> String {
+    let fix = &report.fix;
+    let quality = match fix.quality {
+        _ if !fix.has_position() => 0,
+        Some(FixQuality::Invalid) => 0,
+        None | Some(FixQuality::Gps) => 1,
+        Some(FixQuality::Dgps) => 2,
+        Some(FixQuality::Pps) => 3,
+        Some(FixQuality::Rtk) => 4,
+        Some(FixQuality::FloatRtk) => 5,
+        Some(FixQuality::Estimated) => 6,
+        Some(FixQuality::Manual) => 7,
+        Some(FixQuality::Simulation) => 8,
+    };
+    let satellites = fix
+        .satellites_used
+        .map_or(String::new(), |n| format!("{:02}", n));
+    let (altitude, separation) = if fix.has_position() {
+        (number(fix.altitude, 1), number(fix.geoid_separation, 1))
+    } else {
+        (String::new(), String::new())
+    };
+
+    nmea::with_checksum(&format!(
+        "GPGGA,{},{},{},{},{},{},M,{},M,,",
+        clock,
+        position,
+        quality,
+        satellites,
+        number(report.sky.hdop, 1),
+        altitude,
+        separation
+    ))
+}
+
+fn gsa(report: &Report) -> String {
+    let mode = match report.fix.mode {
+        Mode::Fix3D => 3,
+        Mode::Fix2D => 2,
+        Mode::Unknown if report.fix.has_position() => 2,
+        _ => 1,
+    };
+    let mut used: Vec<String> = report
+        .sky
+        .satellites
+        .iter()
+        .filter(|s| s.used)
+        .take(12)
+        .map(|s| format!("{:02}", s.prn))
+        .collect();
+    used.resize(12, String::new());
+
+    nmea::with_checksum(&format!(
+        "GPGSA,A,{},{},{},{},{}",
+        mode,
+        used.join(","),
+        number(report.sky.pdop, 1),
+        number(report.sky.hdop, 1),
+        number(report.sky.vdop, 1)
+    ))
+}
+
+fn gsv(satellites: &[Satellite]) -> Vec<String> {
+    let talkers = [
+        Talker::Gps,
+        Talker::Glonass,
+        Talker::Galileo,
+        Talker::BeiDou,
+        Talker::Qzss,
+    ];
+    let mut sentences = vec![];
+
+    for talker in talkers {
+        let in_view: Vec<_> = satellites.iter().filter(|s| s.talker == talker).collect();
+        let count = in_view.len().div_ceil(4);
+
+        for (i, chunk) in in_view.chunks(4).enumerate() {
+            let mut data = format!(
+                "{}GSV,{},{},{:02}",
+                talker.as_str(),
+                count,
+                i + 1,
+                in_view.len()
+            );
+            for sat in chunk {
+                let field = |value: Option<String>| value.unwrap_or_default();
+                data.push_str(&format!(
+                    ",{:02},{},{},{}",
+                    sat.prn,
+                    field(sat.elevation.map(|e| format!("{:02}", e))),
+                    field(sat.azimuth.map(|a| format!("{:03}", a))),
+                    field(sat.snr.map(|s| format!("{:02}", s)))
+                ));
+            }
+
+            sentences.push(nmea::with_checksum(&data));
+        }
+    }
+
+    sentences
+}
+
+fn rmc(fix: &Fix, clock: &str, position: &str, date: &str) -> String {
+    let (status, mode) = match fix.quality {
+        _ if !fix.has_position() => ('V', 'N'),
+        Some(FixQuality::Dgps) => ('A', 'D'),
+        Some(FixQuality::Rtk) => ('A', 'R'),
+        Some(FixQuality::FloatRtk) => ('A', 'F'),
+        Some(FixQuality::Estimated) => ('A', 'E'),
+        _ => ('A', 'A'),
+    };
+
+    nmea::with_checksum(&format!(
+        "GPRMC,{},{},{},{},{},{},,,{}",
+        clock,
+        status,
+        position,
+        number(fix.speed.map(|s| s / METERS_PER_SECOND_PER_KNOT), 3),
+        number(fix.track, 2),
+        date,
+        mode
+    ))
+}
+
+// Formats a latitude or longitude as NMEA does, e.g `4807.03800,N`.
+fn coordinate(degrees: Option<f64>, width: usize, positive: char, negative: char) -> String {
+    let degrees = match degrees {
+        Some(d) => d,
+        None => return ",".to_string(),
+    };
+
+    // In hundred thousandths of minutes, so that rounding can't give 60 minutes.
+    let total = (degrees.abs() * 60.0 * 100_000.0).round() as u64;
+    let whole_degrees = total / 6_000_000;
+    let minutes = (total % 6_000_000) as f64 / 100_000.0;
+    let hemisphere = if degrees < 0.0 { negative } else { positive };
+
+    format!(
+        "{:0width$}{:08.5},{}",
+        whole_degrees,
+        minutes,
+        hemisphere,
+        width = width
+    )
+}
+
+fn number(value: Option<f64>, decimals: usize) -> String {
+    value.map_or(String::new(), |v| format!("{:.*}", decimals, v))
+}
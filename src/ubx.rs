@@ -0,0 +1,710 @@
+/* vim: set et ts=4 sw=4: */
+/* ubx.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Decoder for the UBX binary protocol of u-blox receivers.
+//!
+//! u-blox receivers can send UBX messages, NMEA sentences and RTCM3 frames (when they're a base
+//! station) on the same port, so `Decoder` passes the sentences on as they are, sets the frames
+//! aside and puts together a `Report` from the navigation messages of each epoch. SiRF binary
+//! messages get handed to `sirf::Decoder`. See the u-blox 8 / M8 receiver description, including
+//! protocol specification.
+
+use crate::fix::{self, Fix, Mode, Report, Satellite, Sky};
+use crate::nmea::{self, FixQuality, Talker};
+use crate::rtcm;
+use crate::sirf;
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+
+pub const SYNC: [u8; 2] = [0xb5, 0x62];
+
+const CLASS_NAV: u8 = 0x01;
+const CLASS_ACK: u8 = 0x05;
+const CLASS_MON: u8 = 0x0a;
+const ACK_NAK: u8 = 0x00;
+const ACK_ACK: u8 = 0x01;
+const NAV_PVT: u8 = 0x07;
+const NAV_TIMEUTC: u8 = 0x21;
+const NAV_SAT: u8 = 0x35;
+const NAV_EOE: u8 = 0x61;
+const MON_VER: u8 = 0x04;
+
+// Way more than any message we care about, e.g NAV-SAT for 255 satellites is about 3 KiB.
+const MAX_PAYLOAD: usize = 8192;
+// Way more than any sentence, so that a stream without line ends doesn't pile up.
+const MAX_LINE: usize = 4096;
+
+/// What bytes starting with a frame's sync chars make up.
+pub enum Frame<T> {
+    /// More bytes are needed to tell.
+    Partial,
+    /// Not a valid frame, e.g a stray byte that looked like the start of one.
+    Invalid,
+    /// A valid frame, of the given length.
+    Complete(T, usize),
+}
+
+impl<T> Frame<T> {
+    fn map<U>(self, f: impl FnOnce(T) -> U) -> Frame<U> {
+        match self {
+            Frame::Partial => Frame::Partial,
+            Frame::Invalid => Frame::Invalid,
+            Frame::Complete(frame, len) => Frame::Complete(f(frame), len),
+        }
+    }
+}
+
+// The frames that come in between NMEA sentences.
+enum Binary {
+    Rtcm(Vec<u8>),
+    Sirf(Option<sirf::Message>),
+    Ubx(Option<Message>),
+}
+
+/// UBX-NAV-PVT: navigation position velocity time solution.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NavPvt {
+    /// GPS time of week of the navigation epoch, in milliseconds.
+    pub itow: u32,
+    pub date: Option<nmea::Date>,
+    pub time: Option<nmea::Time>,
+    /// 0: no fix, 1: dead reckoning only, 2: 2D, 3: 3D, 4: GNSS and dead reckoning, 5: time only.
+    pub fix_type: u8,
+    pub fix_ok: bool,
+    pub differential: bool,
+    /// 0: none, 1: float, 2: fixed.
+    pub carrier_solution: u8,
+    pub satellites: u8,
+    /// In degrees.
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Height above the ellipsoid and above mean sea level, in meters.
+    pub height: f64,
+    pub height_msl: f64,
+    /// Horizontal and vertical accuracy estimates, in meters.
+    pub horizontal_accuracy: f64,
+    pub vertical_accuracy: f64,
+    /// Down velocity, in meters per second.
+    pub velocity_down: f64,
+    /// Ground speed, in meters per second.
+    pub ground_speed: f64,
+    /// Heading of motion, in degrees.
+    pub heading: f64,
+    pub pdop: f64,
+}
+
+/// A satellite of UBX-NAV-SAT.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SatInfo {
+    pub gnss_id: u8,
+    pub sv_id: u8,
+    /// Carrier to noise ratio, in dBHz.
+    pub cno: u8,
+    /// In degrees.
+    pub elevation: i8,
+    pub azimuth: i16,
+    pub used: bool,
+}
+
+/// UBX-NAV-SAT: satellite information.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NavSat {
+    pub itow: u32,
+    pub satellites: Vec<SatInfo>,
+}
+
+/// UBX-NAV-TIMEUTC: UTC time solution.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NavTimeUtc {
+    pub itow: u32,
+    pub date: nmea::Date,
+    pub time: nmea::Time,
+    pub valid: bool,
+}
+
+/// UBX-ACK-ACK or UBX-ACK-NAK: the receiver's answer to a configuration message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ack {
+    /// The class and id of the message being answered.
+    pub class: u8,
+    pub id: u8,
+    pub acknowledged: bool,
+}
+
+/// UBX-MON-VER: the versions of the receiver, as it answers being polled for them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Version {
+    /// The version of the protocol it speaks, e.g 18.00 for u-blox 8, if it says.
+    pub protocol: Option<(u8, u8)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    NavPvt(NavPvt),
+    NavSat(NavSat),
+    NavTimeUtc(NavTimeUtc),
+    /// UBX-NAV-EOE: end of epoch.
+    NavEoe {
+        itow: u32,
+    },
+    Ack(Ack),
+    Version(Version),
+    /// Anything we don't decode.
+    Other {
+        class: u8,
+        id: u8,
+    },
+}
+
+/// The 8-bit Fletcher checksum of `data`, which is everything between the sync chars and the
+/// checksum.
+pub fn checksum(data: &[u8]) -> [u8; 2] {
+    data.iter().fold([0u8, 0u8], |[a, b], &byte| {
+        let a = a.wrapping_add(byte);
+
+        [a, b.wrapping_add(a)]
+    })
+}
+
+/// Puts a message together, from sync chars to checksum.
+pub fn frame(class: u8, id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut data = vec![class, id];
+    data.extend((payload.len() as u16).to_le_bytes());
+    data.extend(payload);
+
+    let mut frame = SYNC.to_vec();
+    frame.extend(&data);
+    frame.extend(checksum(&data));
+
+    frame
+}
+
+/// Decodes the payload of a message. Returns `None` if it's too short for its kind.
+pub fn parse(class: u8, id: u8, payload: &[u8]) -> Option<Message> {
+    let p = Payload(payload);
+
+    let message = match (class, id) {
+        (CLASS_NAV, NAV_PVT) if payload.len() >= 92 => {
+            let valid = p.u8(11);
+            let flags = p.u8(21);
+            let time = nmea::Time {
+                hour: p.u8(8),
+                minute: p.u8(9),
+                second: f64::from(p.u8(10)) + f64::from(p.i32(16)) * 1e-9,
+            };
+
+            Message::NavPvt(NavPvt {
+                itow: p.u32(0),
+                date: (valid & 0x01 != 0).then(|| date(&p, 4)),
+                time: (valid & 0x02 != 0).then_some(time),
+                fix_type: p.u8(20),
+                fix_ok: flags & 0x01 != 0,
+                differential: flags & 0x02 != 0,
+                carrier_solution: flags >> 6,
+                satellites: p.u8(23),
+                longitude: f64::from(p.i32(24)) * 1e-7,
+                latitude: f64::from(p.i32(28)) * 1e-7,
+                height: f64::from(p.i32(32)) / 1000.0,
+                height_msl: f64::from(p.i32(36)) / 1000.0,
+                horizontal_accuracy: f64::from(p.u32(40)) / 1000.0,
+                vertical_accuracy: f64::from(p.u32(44)) / 1000.0,
+                velocity_down: f64::from(p.i32(56)) / 1000.0,
+                ground_speed: f64::from(p.i32(60)) / 1000.0,
+                heading: f64::from(p.i32(64)) * 1e-5,
+                pdop: f64::from(p.u16(76)) * 0.01,
+            })
+        }
+
+        (CLASS_NAV, NAV_SAT) if payload.len() >= 8 => {
+            let count = usize::from(p.u8(5));
+            if payload.len() < 8 + count * 12 {
+                return None;
+            }
+
+            let satellites = (0..count)
+                .map(|i| {
+                    let offset = 8 + i * 12;
+
+                    SatInfo {
+                        gnss_id: p.u8(offset),
+                        sv_id: p.u8(offset + 1),
+                        cno: p.u8(offset + 2),
+                        elevation: p.u8(offset + 3) as i8,
+                        azimuth: p.u16(offset + 4) as i16,
+                        used: p.u32(offset + 8) & 0x08 != 0,
+                    }
+                })
+                .collect();
+
+            Message::NavSat(NavSat {
+                itow: p.u32(0),
+                satellites,
+            })
+        }
+
+        (CLASS_NAV, NAV_TIMEUTC) if payload.len() >= 20 => Message::NavTimeUtc(NavTimeUtc {
+            itow: p.u32(0),
+            date: date(&p, 12),
+            time: nmea::Time {
+                hour: p.u8(16),
+                minute: p.u8(17),
+                second: f64::from(p.u8(18)) + f64::from(p.i32(8)) * 1e-9,
+            },
+            valid: p.u8(19) & 0x04 != 0,
+        }),
+
+        (CLASS_NAV, NAV_EOE) if payload.len() >= 4 => Message::NavEoe { itow: p.u32(0) },
+
+        (CLASS_NAV, NAV_PVT | NAV_SAT | NAV_TIMEUTC | NAV_EOE) => return None,
+
+        (CLASS_ACK, ACK_ACK | ACK_NAK) if payload.len() >= 2 => Message::Ack(Ack {
+            class: p.u8(0),
+            id: p.u8(1),
+            acknowledged: id == ACK_ACK,
+        }),
+
+        (CLASS_ACK, ACK_ACK | ACK_NAK) => return None,
+
+        // The software and hardware versions, then 30 characters for each extension.
+        (CLASS_MON, MON_VER) if payload.len() >= 40 => Message::Version(Version {
+            protocol: payload[40..].chunks(30).find_map(protocol_version),
+        }),
+
+        (CLASS_MON, MON_VER) => return None,
+
+        _ => Message::Other { class, id },
+    };
+
+    Some(message)
+}
+
+// The version of a MON-VER extension reading e.g `PROTVER=18.00`, or `PROTVER 14.00` for older
+// receivers.
+fn protocol_version(extension: &[u8]) -> Option<(u8, u8)> {
+    let len = extension
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(extension.len());
+    let extension = std::str::from_utf8(&extension[..len]).ok()?;
+    let version = extension
+        .strip_prefix("PROTVER")?
+        .trim_start_matches(['=', ' ']);
+    let (major, minor) = version.trim_end().split_once('.')?;
+
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+fn date(p: &Payload, offset: usize) -> nmea::Date {
+    nmea::Date {
+        year: p.u16(offset),
+        month: p.u8(offset + 2),
+        day: p.u8(offset + 3),
+    }
+}
+
+// Little-endian fields of a payload, whose length was checked beforehand.
+struct Payload<'a>(&'a [u8]);
+
+impl Payload<'_> {
+    fn u8(&self, offset: usize) -> u8 {
+        self.0[offset]
+    }
+
+    fn u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.0[offset], self.0[offset + 1]])
+    }
+
+    fn u32(&self, offset: usize) -> u32 {
+        let bytes = &self.0[offset..offset + 4];
+
+        u32::from_le_bytes(bytes.try_into().expect("is 4 bytes"))
+    }
+
+    fn i32(&self, offset: usize) -> i32 {
+        self.u32(offset) as i32
+    }
+}
+
+/// The messages of the epoch being received.
+#[derive(Default)]
+struct Epoch {
+    itow: Option<u32>,
+    pvt: Option<NavPvt>,
+    time_utc: Option<NavTimeUtc>,
+}
+
+/// Splits a mixed stream into NMEA sentences, UBX or SiRF messages and RTCM3 frames, and makes
+/// reports of the messages.
+///
+/// What's read is kept until it makes up a whole sentence or frame, so that nothing is lost to read
+/// timeouts, and bytes that only looked like the start of a frame are gone through again.
+#[derive(Default)]
+pub struct Decoder {
+    input: Vec<u8>,
+    // Set after an invalid frame, until something that can start a sentence or frame comes.
+    resyncing: bool,
+    epoch: Epoch,
+    // Satellites are reported less often than the fix by some configurations.
+    sky: Sky,
+    report: Option<Report>,
+    acks: VecDeque<Ack>,
+    version: Option<Version>,
+    rtcm: VecDeque<Vec<u8>>,
+    sirf: sirf::Decoder,
+}
+
+impl Decoder {
+    /// Reads the next NMEA sentence from `reader` into `buffer`, decoding the UBX or SiRF messages
+    /// that come before it.
+    ///
+    /// Fails with `WouldBlock` when the messages make up an epoch, whose report is then to be
+    /// taken with `take_report`, when the receiver answers a configuration message, which is
+    /// then to be taken with `take_ack` (or `take_version`, for a poll of its versions), or when
+    /// an RTCM3 frame comes, which is then to be taken with `take_rtcm`.
+    pub fn read_line<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        buffer: &mut String,
+    ) -> io::Result<usize> {
+        loop {
+            if self.input.is_empty() && !self.fill(reader)? {
+                return Ok(0);
+            }
+            if self.resyncing {
+                match self.input.iter().position(|&byte| is_start(byte)) {
+                    Some(start) => {
+                        self.input.drain(..start);
+                        self.resyncing = false;
+                    }
+
+                    None => {
+                        self.input.clear();
+
+                        continue;
+                    }
+                }
+            }
+
+            let frame = match self.input[0] {
+                rtcm::PREAMBLE => rtcm::parse_frame(&self.input).map(Binary::Rtcm),
+                byte if byte == sirf::START[0] => sirf::parse_frame(&self.input).map(Binary::Sirf),
+                byte if byte == SYNC[0] => parse_frame(&self.input).map(Binary::Ubx),
+                _ => return self.read_sentence(reader, buffer),
+            };
+
+            match frame {
+                Frame::Partial => {
+                    // It can't be a frame if the stream ends before it does.
+                    if !self.fill(reader)? {
+                        self.input.remove(0);
+                        self.resyncing = true;
+                    }
+                }
+
+                // Whatever comes after the sync char may still be a sentence or frame.
+                Frame::Invalid => {
+                    self.input.remove(0);
+                    self.resyncing = true;
+                }
+
+                Frame::Complete(binary, len) => {
+                    self.input.drain(..len);
+
+                    if let Some(e) = self.handle_binary(binary) {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forgets what was read but not decoded yet, e.g as it was read at another baudrate.
+    pub fn discard(&mut self) {
+        self.input.clear();
+        self.resyncing = false;
+    }
+
+    /// Takes the report of the last epoch, if it hasn't been taken yet.
+    pub fn take_report(&mut self) -> Option<Report> {
+        self.report.take()
+    }
+
+    /// Takes the oldest answer to a configuration message that hasn't been taken yet.
+    pub fn take_ack(&mut self) -> Option<Ack> {
+        self.acks.pop_front()
+    }
+
+    /// Takes the versions the receiver last answered a poll with, if they haven't been taken yet.
+    pub fn take_version(&mut self) -> Option<Version> {
+        self.version.take()
+    }
+
+    /// Takes the oldest RTCM3 frame that hasn't been taken yet.
+    pub fn take_rtcm(&mut self) -> Option<Vec<u8>> {
+        self.rtcm.pop_front()
+    }
+
+    // Reads more into `input`, returning false at the end of the stream.
+    fn fill<R: BufRead>(&mut self, reader: &mut R) -> io::Result<bool> {
+        let data = loop {
+            match reader.fill_buf() {
+                Ok(data) => break data,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        let len = data.len();
+        self.input.extend_from_slice(data);
+        reader.consume(len);
+
+        Ok(len > 0)
+    }
+
+    fn read_sentence<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        buffer: &mut String,
+    ) -> io::Result<usize> {
+        let mut searched = 0;
+        let len = loop {
+            if let Some(end) = self.input[searched..].iter().position(|&b| b == b'\n') {
+                break searched + end + 1;
+            }
+            searched = self.input.len();
+            if searched >= MAX_LINE || !self.fill(reader)? {
+                break searched;
+            }
+        };
+
+        let line: Vec<u8> = self.input.drain(..len).collect();
+        match String::from_utf8(line) {
+            Ok(line) => {
+                buffer.push_str(&line);
+
+                Ok(line.len())
+            }
+
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            )),
+        }
+    }
+
+    // Returns the error to pass on for what was decoded, if any.
+    fn handle_binary(&mut self, binary: Binary) -> Option<io::Error> {
+        let reason = match binary {
+            Binary::Rtcm(frame) => {
+                self.rtcm.push_back(frame);
+
+                "RTCM3 frame read"
+            }
+
+            Binary::Sirf(message) => {
+                self.report = Some(self.sirf.handle(message?)?);
+
+                "SiRF navigation data decoded"
+            }
+
+            Binary::Ubx(Some(Message::Ack(ack))) => {
+                self.acks.push_back(ack);
+
+                "UBX acknowledgement decoded"
+            }
+
+            Binary::Ubx(Some(Message::Version(version))) => {
+                self.version = Some(version);
+
+                "UBX versions decoded"
+            }
+
+            Binary::Ubx(message) => {
+                if !self.handle(message?) {
+                    return None;
+                }
+
+                "UBX epoch decoded"
+            }
+        };
+
+        Some(io::Error::new(io::ErrorKind::WouldBlock, reason))
+    }
+
+    // Returns whether an epoch was completed.
+    fn handle(&mut self, message: Message) -> bool {
+        match message {
+            Message::NavPvt(pvt) => {
+                let finished = self.start(pvt.itow);
+                self.epoch.pvt = Some(pvt);
+
+                finished
+            }
+
+            Message::NavTimeUtc(time_utc) => {
+                let finished = self.start(time_utc.itow);
+                self.epoch.time_utc = Some(time_utc);
+
+                finished
+            }
+
+            Message::NavSat(sat) => {
+                let finished = self.start(sat.itow);
+                self.sky.satellites = sat.satellites.iter().filter_map(satellite).collect();
+
+                finished
+            }
+
+            Message::NavEoe { itow } if self.epoch.itow == Some(itow) => self.finish(),
+
+            _ => false,
+        }
+    }
+
+    // Messages are for the epoch of their time of week, so one for a new time ends the current
+    // epoch. That's how we find out when receivers aren't set up to send NAV-EOE.
+    fn start(&mut self, itow: u32) -> bool {
+        let finished = match self.epoch.itow {
+            Some(current) if current != itow => self.finish(),
+            _ => false,
+        };
+        self.epoch.itow = Some(itow);
+
+        finished
+    }
+
+    fn finish(&mut self) -> bool {
+        let epoch = std::mem::take(&mut self.epoch);
+        let mut sky = self.sky.clone();
+        let mut fix = Fix::default();
+
+        if let Some(pvt) = epoch.pvt {
+            if let (Some(date), Some(time)) = (pvt.date, pvt.time) {
+                fix.time = Some(fix::timestamp(&date, &time));
+            }
+            sky.pdop = Some(pvt.pdop);
+            fix.satellites_used = Some(pvt.satellites);
+            set_position(&mut fix, &pvt);
+        }
+
+        if let Some(time_utc) = epoch.time_utc.filter(|t| t.valid) {
+            fix.time = Some(fix::timestamp(&time_utc.date, &time_utc.time));
+        }
+
+        self.report = Some(Report { fix, sky });
+
+        true
+    }
+}
+
+fn set_position(fix: &mut Fix, pvt: &NavPvt) {
+    fix.mode = match pvt.fix_type {
+        _ if !pvt.fix_ok => Mode::NoFix,
+        1 | 2 => Mode::Fix2D,
+        3 | 4 => Mode::Fix3D,
+        _ => Mode::NoFix,
+    };
+    if fix.mode == Mode::NoFix {
+        fix.quality = Some(FixQuality::Invalid);
+
+        return;
+    }
+
+    fix.quality = Some(match pvt.carrier_solution {
+        2 => FixQuality::Rtk,
+        1 => FixQuality::FloatRtk,
+        _ if pvt.differential => FixQuality::Dgps,
+        _ if pvt.fix_type == 1 => FixQuality::Estimated,
+        _ => FixQuality::Gps,
+    });
+    fix.latitude = Some(pvt.latitude);
+    fix.longitude = Some(pvt.longitude);
+    fix.altitude = Some(pvt.height_msl);
+    fix.geoid_separation = Some(pvt.height - pvt.height_msl);
+    fix.speed = Some(pvt.ground_speed);
+    fix.track = Some(pvt.heading);
+    fix.climb = Some(-pvt.velocity_down);
+    // The receiver only gives a horizontal estimate, which holds for either axis.
+    fix.epx = Some(pvt.horizontal_accuracy);
+    fix.epy = Some(pvt.horizontal_accuracy);
+    fix.epv = Some(pvt.vertical_accuracy);
+}
+
+fn satellite(info: &SatInfo) -> Option<Satellite> {
+    let talker = match info.gnss_id {
+        // SBAS satellites are numbered along with GPS ones in NMEA.
+        0 | 1 => Talker::Gps,
+        2 => Talker::Galileo,
+        3 => Talker::BeiDou,
+        5 => Talker::Qzss,
+        6 => Talker::Glonass,
+        _ => return None,
+    };
+
+    Some(Satellite {
+        talker,
+        prn: info.sv_id.into(),
+        elevation: (-90..=90)
+            .contains(&info.elevation)
+            .then_some(info.elevation.into()),
+        azimuth: (0..=360)
+            .contains(&info.azimuth)
+            .then_some(info.azimuth as u16),
+        snr: (info.cno > 0).then_some(info.cno),
+        used: info.used,
+    })
+}
+
+// Whether `byte` can start a sentence (or its tag block) or a frame.
+fn is_start(byte: u8) -> bool {
+    matches!(byte, b'$' | b'!' | b'\\' | rtcm::PREAMBLE)
+        || byte == sirf::START[0]
+        || byte == SYNC[0]
+}
+
+// Parses the frame `data` starts with, from its first sync char. Frames whose message can't be
+// parsed are complete all the same.
+fn parse_frame(data: &[u8]) -> Frame<Option<Message>> {
+    let header = match data.get(1..6) {
+        Some(header) => header,
+        None => return Frame::Partial,
+    };
+    if header[0] != SYNC[1] {
+        return Frame::Invalid;
+    }
+    let (class, id) = (header[1], header[2]);
+    let len = usize::from(u16::from_le_bytes([header[3], header[4]]));
+    if len > MAX_PAYLOAD {
+        return Frame::Invalid;
+    }
+
+    let checked = match data.get(2..6 + len + 2) {
+        Some(checked) => checked,
+        None => return Frame::Partial,
+    };
+    let (data, sum) = checked.split_at(4 + len);
+    if checksum(data) != sum {
+        return Frame::Invalid;
+    }
+
+    Frame::Complete(parse(class, id, &data[4..]), 6 + len + 2)
+}
@@ -0,0 +1,185 @@
+/* vim: set et ts=4 sw=4: */
+/* udp.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Reading NMEA from UDP datagrams, as marine instruments and phone apps broadcast them.
+
+use crate::fix::Report;
+use crate::gps::{GPS, READ_TIMEOUT};
+use crate::ubx;
+use std::io::{self, BufRead, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+
+const SCHEME: &str = "udp://";
+const DEFAULT_PORT: u16 = 10110;
+
+// Datagrams are limited to 64 KiB, though NMEA ones are way smaller.
+const MAX_DATAGRAM: usize = 65_536;
+
+/// Whether `path` stands for a UDP source, `udp://[ADDRESS][:PORT]`.
+pub fn is_udp(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with(SCHEME))
+}
+
+// The address to listen at, any address and the default port being filled in for those left out.
+fn parse_address(path: &Path) -> Result<SocketAddr, String> {
+    let address = path
+        .to_str()
+        .and_then(|path| path.strip_prefix(SCHEME))
+        .ok_or_else(|| format!("{} is not a UDP address", path.display()))?;
+
+    if let Ok(address) = address.parse() {
+        return Ok(address);
+    }
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, DEFAULT_PORT));
+    }
+    let port = match address.strip_prefix(':') {
+        Some(port) => port.parse().ok(),
+        None if address.is_empty() => Some(DEFAULT_PORT),
+        None => None,
+    };
+
+    port.map(|port| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port))
+        .ok_or_else(|| format!("invalid UDP address `{}`", address))
+}
+
+// Datagrams, each read as lines on its own.
+struct Datagrams {
+    socket: UdpSocket,
+    datagram: Vec<u8>,
+    position: usize,
+}
+
+impl Read for Datagrams {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+
+        Ok(len)
+    }
+}
+
+impl BufRead for Datagrams {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position == self.datagram.len() {
+            self.datagram.resize(MAX_DATAGRAM, 0);
+            let len = match self.socket.recv(&mut self.datagram) {
+                Ok(len) => len,
+                Err(e) => {
+                    self.datagram.clear();
+                    self.position = 0;
+
+                    return Err(e);
+                }
+            };
+            self.datagram.truncate(len);
+            self.position = 0;
+
+            // Senders don't always end the last sentence, and it can't go on in the next one.
+            if !self.datagram.ends_with(b"\n") {
+                self.datagram.extend(b"\r\n");
+            }
+        }
+
+        Ok(&self.datagram[self.position..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.datagram.len());
+    }
+}
+
+pub struct Udp {
+    reader: Datagrams,
+    address: PathBuf,
+    ubx: ubx::Decoder,
+}
+
+impl Udp {
+    /// Listens at the address of `path`, joining it if it's a multicast group.
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        let address =
+            parse_address(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let socket = match address.ip() {
+            IpAddr::V4(group) if group.is_multicast() => {
+                let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, address.port()))?;
+                socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+
+                socket
+            }
+            IpAddr::V6(group) if group.is_multicast() => {
+                let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, address.port()))?;
+                socket.join_multicast_v6(&group, 0)?;
+
+                socket
+            }
+            // Broadcasts are received at any address.
+            _ => UdpSocket::bind(address)?,
+        };
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+
+        Ok(Udp {
+            reader: Datagrams {
+                socket,
+                datagram: vec![],
+                position: 0,
+            },
+            address: path.to_path_buf(),
+            ubx: ubx::Decoder::default(),
+        })
+    }
+}
+
+impl GPS for Udp {
+    fn read_line(&mut self, buffer: &mut String) -> io::Result<usize> {
+        self.ubx.read_line(&mut self.reader, buffer)
+    }
+
+    fn kind(&self) -> &'static str {
+        "UDP"
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.address)
+    }
+
+    fn take_report(&mut self) -> Option<Report> {
+        self.ubx.take_report()
+    }
+
+    fn take_ack(&mut self) -> Option<ubx::Ack> {
+        self.ubx.take_ack()
+    }
+
+    fn take_version(&mut self) -> Option<ubx::Version> {
+        self.ubx.take_version()
+    }
+
+    fn take_rtcm(&mut self) -> Option<Vec<u8>> {
+        self.ubx.take_rtcm()
+    }
+}
@@ -0,0 +1,96 @@
+/* vim: set et ts=4 sw=4: */
+/* verify.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Telling GPS receivers apart from other devices, by what they send.
+
+use crate::nmea;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// How many sentences in a row it takes to be taken for a GPS receiver.
+const SENTENCES: usize = 3;
+
+/// NMEA sentences are at most 82 characters, so longer lines are garbage, e.g from the wrong
+/// baudrate.
+pub const MAX_LINE: u64 = 256;
+
+// What GPS receivers send, as opposed to e.g modems answering AT commands.
+const KNOWN_TYPES: &[&str] = &[
+    "GGA", "GLL", "GNS", "GSA", "GST", "GSV", "RMC", "TXT", "VTG", "ZDA",
+];
+
+/// Whether `read_line` gives a few sentences of known types with valid checksums in a row, before
+/// `budget` runs out. Anything else starts the count over, so garbage at power-up is tolerated.
+///
+/// `read_line` appends a line to its buffer, or fails with `TimedOut` if none is coming for now.
+pub fn verify<F>(mut read_line: F, budget: Duration) -> bool
+where
+    F: FnMut(&mut Vec<u8>) -> io::Result<usize>,
+{
+    let deadline = Instant::now() + budget;
+    let mut line = vec![];
+    let mut in_a_row = 0;
+
+    while Instant::now() < deadline {
+        line.clear();
+        match read_line(&mut line) {
+            Ok(0) => return false,
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(_) => return false,
+        }
+
+        match check(&line) {
+            Line::Known => {
+                in_a_row += 1;
+                if in_a_row == SENTENCES {
+                    return true;
+                }
+            }
+            // Vendors have their own sentences, which is no reason to doubt the device.
+            Line::Proprietary => {}
+            Line::Garbage => in_a_row = 0,
+        }
+    }
+
+    false
+}
+
+enum Line {
+    Known,
+    Proprietary,
+    Garbage,
+}
+
+fn check(line: &[u8]) -> Line {
+    let line = match std::str::from_utf8(line) {
+        Ok(line) if line.starts_with('$') && nmea::verify_checksum(line).is_ok() => line,
+        _ => return Line::Garbage,
+    };
+
+    match nmea::sentence_type(line) {
+        Some(t) if KNOWN_TYPES.contains(&t) => Line::Known,
+        Some(t) if t.starts_with('P') => Line::Proprietary,
+        _ => Line::Garbage,
+    }
+}
@@ -0,0 +1,214 @@
+/* vim: set et ts=4 sw=4: */
+/* caster.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+const GGA: &str = "$GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\n";
+// A 1005 message (base station position), without its CRC.
+const RTCM: &[u8] = &[
+    0xd3, 0x00, 0x13, 0x3e, 0xd0, 0x00, 0x03, 0x8a, 0x8b, 0x71, 0x7a, 0x8c, 0x09, 0x9a, 0x43, 0xe2,
+    0x40, 0x4c, 0x21, 0x0a, 0x0e, 0x24,
+];
+
+#[test]
+fn caster() {
+    let (mut child, _stdout) = spawn_gps_share(&[
+        "-p",
+        "9339",
+        "--caster-port",
+        "9340",
+        "--caster-mountpoint",
+        "BASE",
+    ]);
+
+    // Anything but the mountpoint gets the sourcetable.
+    let mut stream = TcpStream::connect(("127.0.0.1", 9340)).unwrap();
+    stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    let mut sourcetable = String::new();
+    stream.read_to_string(&mut sourcetable).unwrap();
+    assert!(sourcetable.starts_with("SOURCETABLE 200 OK\r\n"));
+    assert!(sourcetable.contains("\r\n\r\nSTR;BASE;BASE;RTCM 3;"));
+    assert!(sourcetable.ends_with("ENDSOURCETABLE\r\n"));
+
+    let rover = TcpStream::connect(("127.0.0.1", 9340)).unwrap();
+    rover
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    (&rover)
+        .write_all(b"GET /BASE HTTP/1.1\r\nNtrip-Version: Ntrip/2.0\r\n\r\n")
+        .unwrap();
+    let mut rover = BufReader::new(rover);
+    let response = read_headers(&mut rover);
+    assert_eq!(response[0], "HTTP/1.1 200 OK");
+    assert!(response.contains(&"Content-Type: gnss/data".to_string()));
+
+    let nmea = TcpStream::connect(("127.0.0.1", 9339)).unwrap();
+    nmea.set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    // The frame goes to the rover, and only the sentences to NMEA clients.
+    let frame = frame(RTCM);
+    let stdin = child.stdin.as_mut().unwrap();
+    stdin.write_all(GGA.as_bytes()).unwrap();
+    stdin.write_all(&frame).unwrap();
+    stdin.write_all(GGA.as_bytes()).unwrap();
+    stdin.flush().unwrap();
+
+    let mut received = vec![0; frame.len()];
+    rover.read_exact(&mut received).unwrap();
+    assert_eq!(received, frame);
+
+    let mut nmea = BufReader::new(nmea);
+    for _ in 0..2 {
+        let mut line = String::new();
+        nmea.read_line(&mut line).unwrap();
+        assert_eq!(line, GGA);
+    }
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn hangup() {
+    let (mut child, mut stdout) = spawn_gps_share(&[
+        "-p",
+        "9371",
+        "--caster-port",
+        "9372",
+        "--caster-mountpoint",
+        "BASE",
+    ]);
+
+    let mut rover = TcpStream::connect(("127.0.0.1", 9372)).unwrap();
+    rover.write_all(b"GET /BASE HTTP/1.0\r\n\r\n").unwrap();
+    let mut response = String::new();
+    BufReader::new(&rover).read_line(&mut response).unwrap();
+    assert_eq!(response, "ICY 200 OK\r\n");
+    drop(rover);
+
+    // The next frames find the rover gone, which is nothing to complain about.
+    let mut stdin = child.stdin.take().unwrap();
+    thread::spawn(move || {
+        for _ in 0..50 {
+            if stdin.write_all(&frame(RTCM)).is_err() || stdin.flush().is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    });
+    loop {
+        let mut line = String::new();
+        assert_ne!(stdout.read_line(&mut line).unwrap(), 0);
+        assert!(
+            !line.starts_with("Failed to serve NTRIP client"),
+            "{}",
+            line
+        );
+        if line.starts_with("NTRIP client 127.0.0.1 disconnected") {
+            break;
+        }
+    }
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn bad_mountpoint() {
+    let output = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args([
+            "-a",
+            "--caster-port",
+            "9341",
+            "--caster-mountpoint",
+            "A/B",
+            "-",
+        ])
+        .output()
+        .expect("Failed to run gps-share");
+
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Invalid mountpoint: A/B\n"
+    );
+}
+
+// Returns gps-share once the caster is up, along with what's left of its output.
+fn spawn_gps_share(args: &[&str]) -> (Child, BufReader<ChildStdout>) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .arg("-a")
+        .args(args)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start gps-share");
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    loop {
+        let mut line = String::new();
+        assert_ne!(stdout.read_line(&mut line).unwrap(), 0);
+        if line.starts_with("NTRIP caster on port") {
+            break;
+        }
+    }
+
+    (child, stdout)
+}
+
+fn read_headers<R: BufRead>(reader: &mut R) -> Vec<String> {
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end().to_string();
+        if line.is_empty() {
+            return headers;
+        }
+        headers.push(line);
+    }
+}
+
+// Appends the CRC-24Q to `message`.
+fn frame(message: &[u8]) -> Vec<u8> {
+    let mut crc = 0u32;
+    for &byte in message {
+        crc ^= u32::from(byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x0100_0000 != 0 {
+                crc ^= 0x0186_4cfb;
+            }
+        }
+    }
+
+    let mut frame = message.to_vec();
+    frame.extend_from_slice(&crc.to_be_bytes()[1..]);
+
+    frame
+}
@@ -0,0 +1,147 @@
+/* vim: set et ts=4 sw=4: */
+/* commands.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::{connect, open_pty, spawn_gps_share};
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::fd::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+const GGA: &str = "$GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\r\n";
+const COMMAND: &str = "$PMTK220,200*2C\r\n";
+
+#[test]
+fn rs232() {
+    let (mut receiver, _device, path) = open_pty();
+    let port = 9333;
+    let mut child = spawn_gps_share(&["-p", &port.to_string(), "--client-commands", &path]);
+
+    let mut client = connect(port);
+    client.write_all(COMMAND.as_bytes()).unwrap();
+    // The device is written to in between reads.
+    receiver.write_all(GGA.as_bytes()).unwrap();
+    assert_eq!(read_line(&mut receiver), COMMAND);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn lines() {
+    let (mut receiver, _device, path) = open_pty();
+    let port = 9358;
+    let mut child = spawn_gps_share(&["-p", &port.to_string(), "--client-commands", &path]);
+
+    // Half a command is held back until the rest of its line comes.
+    let mut client = connect(port);
+    let (start, end) = COMMAND.split_at(8);
+    client.write_all(start.as_bytes()).unwrap();
+    receiver.write_all(GGA.as_bytes()).unwrap();
+    thread::sleep(Duration::from_millis(500));
+    receiver.write_all(GGA.as_bytes()).unwrap();
+    assert!(!is_readable(&receiver));
+
+    client
+        .write_all(format!("{}{}", end, COMMAND).as_bytes())
+        .unwrap();
+    receiver.write_all(GGA.as_bytes()).unwrap();
+    assert_eq!(read_line(&mut receiver), COMMAND);
+    assert_eq!(read_line(&mut receiver), COMMAND);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn gnss() {
+    // A FIFO isn't a TTY, so it's read as a kernel GNSS device would be. What gps-share writes to
+    // it comes right back.
+    let path = "/tmp/gps-share-commands.fifo";
+    let _ = fs::remove_file(path);
+    let c_path = CString::new(path).unwrap();
+    // SAFETY: `c_path` is a valid NUL-terminated string.
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+    let port = 9334;
+    let mut child = spawn_gps_share(&["-p", &port.to_string(), "--client-commands", path]);
+    let mut device = OpenOptions::new().write(true).open(path).unwrap();
+    device.write_all(GGA.as_bytes()).unwrap();
+
+    let client = connect(port);
+    let mut reader = BufReader::new(client.try_clone().unwrap());
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, GGA);
+
+    (&client).write_all(COMMAND.as_bytes()).unwrap();
+    device.write_all(GGA.as_bytes()).unwrap();
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, GGA);
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, COMMAND);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    fs::remove_file(path).unwrap();
+}
+
+// Whether there is anything to read from `file` right away.
+fn is_readable(file: &File) -> bool {
+    let mut fd = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `fd` is a single valid pollfd.
+    let ret = unsafe { libc::poll(&mut fd, 1, 0) };
+    assert!(ret >= 0);
+
+    ret > 0
+}
+
+// Returns the receiver's end, the device's end (which has to stay open) and the device's path.
+
+fn read_line(receiver: &mut File) -> String {
+    let mut line = vec![];
+    while line.last() != Some(&b'\n') {
+        let mut fd = libc::pollfd {
+            fd: receiver.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `fd` is a valid pollfd and we pass a count of 1.
+        let ret = unsafe { libc::poll(&mut fd, 1, 15_000) };
+        assert_eq!(ret, 1, "gps-share didn't write to the device");
+
+        let mut byte = [0];
+        receiver.read_exact(&mut byte).unwrap();
+        line.push(byte[0]);
+    }
+
+    String::from_utf8(line).unwrap()
+}
@@ -0,0 +1,125 @@
+/* vim: set et ts=4 sw=4: */
+/* common/mod.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+//! Helpers the integration tests share. Each test crate only uses some of them.
+#![allow(dead_code)]
+
+use std::env;
+use std::ffi::CStr;
+use std::fs::{self, File};
+use std::io::BufRead;
+use std::net::TcpStream;
+use std::os::fd::FromRawFd;
+use std::path::PathBuf;
+use std::process::{self, Child, Command, Stdio};
+use std::ptr;
+use std::thread;
+use std::time::Duration;
+
+/// Starts gps-share without announcing it through Avahi.
+pub fn spawn_gps_share(args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .arg("-a")
+        .args(args)
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share")
+}
+
+/// An empty directory for gps-share to look for devices in instead of /dev, through
+/// `GPS_SHARE_DEV_DIR`.
+pub fn dev_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("gps-share-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+
+    dir
+}
+
+/// Connects to gps-share on `port`, once it's up.
+pub fn connect(port: u16) -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)) {
+            stream
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+
+            return stream;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    panic!("gps-share didn't come up on port {}", port);
+}
+
+pub fn read_line<R: BufRead>(reader: &mut R) -> String {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+
+    line
+}
+
+/// A pty standing in for a serial GPS device: the receiver end, the device end and its path.
+pub fn open_pty() -> (File, File, String) {
+    let (mut receiver, mut device) = (0, 0);
+    // SAFETY: both pointers are to valid local variables, and the others are optional.
+    let ret = unsafe {
+        libc::openpty(
+            &mut receiver,
+            &mut device,
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(),
+        )
+    };
+    assert_eq!(ret, 0);
+    // So that gps-share doesn't get them, keeping the device around after the receiver closes.
+    for fd in [receiver, device] {
+        // SAFETY: `fd` is a valid fd, just opened.
+        assert_eq!(
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) },
+            0
+        );
+    }
+
+    // No echoing back what we send as the receiver, until gps-share sets up the device itself.
+    // SAFETY: `termios` is only used once `tcgetattr` filled it in.
+    unsafe {
+        let mut termios = std::mem::zeroed();
+        assert_eq!(libc::tcgetattr(device, &mut termios), 0);
+        libc::cfmakeraw(&mut termios);
+        assert_eq!(libc::tcsetattr(device, libc::TCSANOW, &termios), 0);
+    }
+
+    // SAFETY: `device` is a valid fd, and `ttyname` returns a NUL-terminated string or NULL.
+    let name = unsafe { libc::ttyname(device) };
+    assert!(!name.is_null());
+    // SAFETY: checked for NULL above.
+    let path = unsafe { CStr::from_ptr(name) }
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    // SAFETY: `openpty` just gave us both fds, which nothing else owns.
+    unsafe { (File::from_raw_fd(receiver), File::from_raw_fd(device), path) }
+}
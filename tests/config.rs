@@ -0,0 +1,182 @@
+/* vim: set et ts=4 sw=4: */
+/* config.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::process::{ChildStdout, Command, Stdio};
+
+#[test]
+fn config_file() {
+    let path = "/tmp/gps-share-test.toml";
+    fs::write(
+        path,
+        "\
+         device = \"-\"\n\
+         disable-announce = true\n\
+         port = 9322\n",
+    )
+    .unwrap();
+
+    assert_eq!(get_port(&["-c", path]), 9322);
+    // The command line overrides the file.
+    assert_eq!(get_port(&["-c", path, "-p", "9323"]), 9323);
+
+    // Flags the file sets can be turned off again.
+    fs::write(
+        path,
+        "device = \"-\"\ndisable-announce = true\nno-tcp = true\n",
+    )
+    .unwrap();
+    assert_eq!(get_port(&["-c", path, "--tcp", "-p", "9373"]), 9373);
+
+    fs::write(path, "port = 9324\nqueue-sise = 16\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-c", path])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("unknown field `queue-sise`"), "{}", stdout);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn allowlist() {
+    let path = "/tmp/gps-share-allowlist.toml";
+    fs::write(
+        path,
+        "\
+         usb-drivers = [\"cp210x\"]\n\
+         usb-ids = [\"1546:01a7\"]\n\
+         udev-properties = [\"ID_MODEL=GPS\"]\n",
+    )
+    .unwrap();
+    let args = ["-c", path, "-a", "-p", "9328", "-"];
+    assert_eq!(get_port(&args), 9328);
+
+    fs::write(path, "usb-ids = [\"1546\"]\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-c", path, "-"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("invalid USB ID `1546`"), "{}", stdout);
+
+    fs::remove_file(path).unwrap();
+}
+
+fn get_port(args: &[&str]) -> u16 {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start gps-share");
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let line = wait_for_line(&mut stdout, "Port: ");
+    let port = line["Port: ".len()..].trim().parse().unwrap();
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    port
+}
+
+#[test]
+fn reload() {
+    let path = "/tmp/gps-share-reload.toml";
+    let socket_path = "/tmp/gps-share-reload.sock";
+    let _ = fs::remove_file(socket_path);
+    fs::write(
+        path,
+        "device = \"-\"\ndisable-announce = true\nport = 9325\n",
+    )
+    .unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-c", path])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start gps-share");
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    wait_for_line(&mut stdout, "Port: ");
+    let mut client = TcpStream::connect(("127.0.0.1", 9325)).unwrap();
+
+    fs::write(
+        path,
+        format!(
+            "device = \"-\"\n\
+             disable-announce = true\n\
+             port = 9325\n\
+             socket-path = \"{}\"\n\
+             bad-checksum = \"forward\"\n",
+            socket_path
+        ),
+    )
+    .unwrap();
+    // SAFETY: sending a signal to our own child has no memory safety implications.
+    unsafe { libc::kill(child.id() as i32, libc::SIGHUP) };
+    wait_for_line(&mut stdout, "Configuration reloaded");
+
+    let mut local = UnixStream::connect(socket_path).unwrap();
+    // This would have been dropped before the reload.
+    let bad = "$GPGSA,A,3,02,12,19,24,,,,,,,,,9.6,6.5,7.3*37\n";
+    child
+        .stdin
+        .as_mut()
+        .unwrap()
+        .write_all(bad.as_bytes())
+        .unwrap();
+
+    // The client from before the reload is still served, with the new configuration.
+    let mut output = vec![0u8; bad.len()];
+    client.read_exact(&mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), bad);
+    let mut output = vec![0u8; bad.len()];
+    local.read_exact(&mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), bad);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    fs::remove_file(path).unwrap();
+    fs::remove_file(socket_path).unwrap();
+}
+
+fn wait_for_line(stdout: &mut BufReader<ChildStdout>, prefix: &str) -> String {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stdout.read_line(&mut line).unwrap() == 0 {
+            panic!("gps-share exited before printing `{}`", prefix);
+        }
+
+        if line.starts_with(prefix) {
+            return line;
+        }
+    }
+}
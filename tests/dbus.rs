@@ -0,0 +1,276 @@
+/* vim: set et ts=4 sw=4: */
+/* dbus.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+
+use location::LocationProxy;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use zbus::blocking::Connection;
+use zbus::proxy;
+
+// The signal mirrors the `Location` object, value for value.
+#[allow(clippy::too_many_arguments)]
+mod location {
+    use zbus::proxy;
+
+    #[proxy(
+        interface = "org.freedesktop.GPSShare.Location",
+        default_service = "org.freedesktop.GPSShare",
+        default_path = "/org/freedesktop/GPSShare/Location",
+        gen_async = false
+    )]
+    pub trait Location {
+        #[zbus(property)]
+        fn latitude(&self) -> zbus::Result<f64>;
+        #[zbus(property)]
+        fn longitude(&self) -> zbus::Result<f64>;
+        #[zbus(property)]
+        fn altitude(&self) -> zbus::Result<f64>;
+        #[zbus(property)]
+        fn accuracy(&self) -> zbus::Result<f64>;
+        #[zbus(property)]
+        fn timestamp(&self) -> zbus::Result<(u64, u64)>;
+
+        #[zbus(signal)]
+        fn location_updated(
+            &self,
+            latitude: f64,
+            longitude: f64,
+            accuracy: f64,
+            altitude: f64,
+            speed: f64,
+            heading: f64,
+            timestamp: (u64, u64),
+        ) -> zbus::Result<()>;
+    }
+}
+
+#[proxy(
+    interface = "org.freedesktop.GPSShare",
+    default_service = "org.freedesktop.GPSShare",
+    default_path = "/org/freedesktop/GPSShare",
+    gen_async = false
+)]
+trait GPSShare {
+    #[zbus(property)]
+    fn device_path(&self) -> zbus::Result<String>;
+    #[zbus(property)]
+    fn source_kind(&self) -> zbus::Result<String>;
+    #[zbus(property)]
+    fn clients(&self) -> zbus::Result<u32>;
+    #[zbus(property)]
+    fn sentence_rate(&self) -> zbus::Result<f64>;
+    #[zbus(property)]
+    fn fix_state(&self) -> zbus::Result<String>;
+    #[zbus(property)]
+    fn dropped_sentences(&self) -> zbus::Result<u64>;
+
+    fn list_clients(&self) -> zbus::Result<Vec<(u32, String, String)>>;
+    fn disconnect_client(&self, id: u32) -> zbus::Result<()>;
+    fn switch_device(&self, path: &str) -> zbus::Result<()>;
+}
+
+/// A private session bus, so that tests don't depend on (or disturb) the user's.
+struct Bus {
+    daemon: Child,
+    address: String,
+}
+
+impl Bus {
+    fn new() -> Self {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start dbus-daemon");
+
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.as_mut().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+
+        Bus {
+            daemon,
+            address: address.trim().to_string(),
+        }
+    }
+
+    fn connect(&self) -> Connection {
+        zbus::blocking::connection::Builder::address(self.address.as_str())
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    fn spawn_gps_share(&self, args: &[&str]) -> Child {
+        Command::new(env!("CARGO_BIN_EXE_gps-share"))
+            .args(["-a", "--dbus", "session", "-"])
+            .args(args)
+            .env("DBUS_SESSION_BUS_ADDRESS", &self.address)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Failed to start gps-share")
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+const EPOCH: &str = "\
+                     $GPGSA,A,3,02,12,19,24,,,,,,,,,9.6,6.5,7.1*37\n\
+                     $GPRMC,122732.000,A,5744.4784,N,01201.6130,E,0.0,0.0,300417,,,A*63\n\
+                     $GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\n";
+
+// Waits for gps-share to show up on the bus.
+fn wait_for_service(proxy: &LocationProxy) {
+    for _ in 0..50 {
+        if proxy.latitude().is_ok() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    panic!("gps-share didn't show up on the bus");
+}
+
+#[test]
+fn location() {
+    let bus = Bus::new();
+    let connection = bus.connect();
+    let mut child = bus.spawn_gps_share(&["-x"]);
+
+    let proxy = LocationProxy::builder(&connection)
+        .cache_properties(zbus::proxy::CacheProperties::No)
+        .build()
+        .unwrap();
+    wait_for_service(&proxy);
+    let mut updates = proxy.receive_location_updated().unwrap();
+
+    let stdin = child.stdin.as_mut().unwrap();
+    stdin.write_all(EPOCH.as_bytes()).unwrap();
+    stdin.write_all(EPOCH.as_bytes()).unwrap();
+
+    let update = updates.next().unwrap();
+    let args = update.args().unwrap();
+    assert!((args.latitude - 57.74130666).abs() < 1e-6);
+    assert!((args.longitude - 12.02688333).abs() < 1e-6);
+    assert_eq!(args.altitude, 61.7);
+    assert_eq!(args.timestamp, (1493555252, 0));
+
+    assert!((proxy.latitude().unwrap() - 57.74130666).abs() < 1e-6);
+    assert!((proxy.longitude().unwrap() - 12.02688333).abs() < 1e-6);
+    assert_eq!(proxy.accuracy().unwrap(), 6.5 * 5.0);
+    assert_eq!(proxy.timestamp().unwrap(), (1493555252, 0));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn control() {
+    let port = 9321;
+    let bus = Bus::new();
+    let connection = bus.connect();
+    let mut child = bus.spawn_gps_share(&["-p", &port.to_string()]);
+
+    let location = LocationProxy::builder(&connection)
+        .cache_properties(zbus::proxy::CacheProperties::No)
+        .build()
+        .unwrap();
+    wait_for_service(&location);
+    let proxy = GPSShareProxy::builder(&connection)
+        .cache_properties(zbus::proxy::CacheProperties::No)
+        .build()
+        .unwrap();
+    assert_eq!(proxy.source_kind().unwrap(), "stdin");
+    assert_eq!(proxy.device_path().unwrap(), "");
+    assert_eq!(proxy.fix_state().unwrap(), "unknown");
+
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let stdin = child.stdin.as_mut().unwrap();
+    stdin.write_all(EPOCH.as_bytes()).unwrap();
+    stdin.write_all(EPOCH.as_bytes()).unwrap();
+    let mut output = vec![0u8; EPOCH.len() * 2];
+    client.read_exact(&mut output).unwrap();
+
+    assert_eq!(proxy.fix_state().unwrap(), "3d");
+    assert!(proxy.sentence_rate().unwrap() > 0.0);
+    assert_eq!(proxy.clients().unwrap(), 1);
+
+    assert_eq!(proxy.dropped_sentences().unwrap(), 0);
+    stdin
+        .write_all(b"$GPGSA,A,3,02,12,19,24,,,,,,,,,9.6,6.5,7.3*37\n")
+        .unwrap();
+    wait_for(|| proxy.dropped_sentences().unwrap() == 1);
+    let clients = proxy.list_clients().unwrap();
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].1, "nmea");
+    assert_eq!(clients[0].2, client.local_addr().unwrap().to_string());
+
+    assert!(proxy.disconnect_client(clients[0].0 + 1).is_err());
+    proxy.disconnect_client(clients[0].0).unwrap();
+    // The server is done with the client as soon as it has something to send.
+    stdin.write_all(EPOCH.as_bytes()).unwrap();
+    assert_eq!(client.read(&mut output).unwrap(), 0);
+    wait_for(|| proxy.clients().unwrap() == 0);
+
+    // Clients hanging up go from the list, without anything to send them.
+    let client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    wait_for(|| proxy.clients().unwrap() == 1);
+    drop(client);
+    wait_for(|| proxy.clients().unwrap() == 0);
+
+    assert!(proxy.switch_device("/nonexistent").is_err());
+    // Regular files aren't TTYs, so this is read as a kernel GNSS device would be.
+    let path = "/tmp/gps-share-switch.nmea";
+    fs::write(path, EPOCH).unwrap();
+    proxy.switch_device(path).unwrap();
+    // The switch happens once the current device sends its next line.
+    stdin.write_all(EPOCH.as_bytes()).unwrap();
+    wait_for(|| proxy.device_path().unwrap() == path);
+    assert_eq!(proxy.source_kind().unwrap(), "GNSS");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    fs::remove_file(path).unwrap();
+}
+
+fn wait_for(condition: impl Fn() -> bool) {
+    for _ in 0..50 {
+        if condition() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    panic!("Timed out waiting for gps-share");
+}
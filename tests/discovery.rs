@@ -0,0 +1,215 @@
+/* vim: set et ts=4 sw=4: */
+/* discovery.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::{connect, read_line};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use zbus::interface;
+use zbus::zvariant::OwnedObjectPath;
+
+const GGA: &str = "$GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\r\n";
+const RMC: &str = "$GPRMC,122733.000,A,5744.4784,N,01201.6130,E,0.0,0.0,300417,,,A*62\r\n";
+const BROWSER: &str = "/Client1/ServiceBrowser1";
+
+type ResolvedService = (
+    i32,
+    i32,
+    String,
+    String,
+    String,
+    String,
+    i32,
+    String,
+    u16,
+    Vec<Vec<u8>>,
+    u32,
+);
+
+/// The part of Avahi gps-share browses with, knowing of an NMEA service on this machine, one of
+/// gps-share on another and one more on another.
+struct MockAvahi {
+    port: u16,
+    gps_share_port: u16,
+    resolved: Arc<Mutex<Vec<String>>>,
+}
+
+#[interface(name = "org.freedesktop.Avahi.Server")]
+impl MockAvahi {
+    // Like Avahi, this reports what it found before the caller even knows about the browser.
+    async fn service_browser_new(
+        &self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        _ifindex: i32,
+        _protocol: i32,
+        service_type: String,
+        _domain: String,
+        _flags: u32,
+    ) -> zbus::fdo::Result<OwnedObjectPath> {
+        let interface = "org.freedesktop.Avahi.ServiceBrowser";
+        for (name, flags) in [("local", 8u32), ("gps-share", 0), ("remote", 0)] {
+            let item = (1i32, 0i32, name, service_type.as_str(), "local", flags);
+            connection
+                .emit_signal(None::<()>, BROWSER, interface, "ItemNew", &item)
+                .await?;
+        }
+        connection
+            .emit_signal(None::<()>, BROWSER, interface, "AllForNow", &())
+            .await?;
+
+        Ok(OwnedObjectPath::try_from(BROWSER).unwrap())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn resolve_service(
+        &self,
+        ifindex: i32,
+        protocol: i32,
+        name: String,
+        service_type: String,
+        domain: String,
+        _aprotocol: i32,
+        _flags: u32,
+    ) -> ResolvedService {
+        self.resolved.lock().unwrap().push(name.clone());
+        let (port, txt) = if name == "gps-share" {
+            (self.gps_share_port, vec![b"gps-share".to_vec()])
+        } else {
+            (self.port, vec![])
+        };
+
+        (
+            ifindex,
+            protocol,
+            name,
+            service_type,
+            domain,
+            "elsewhere.local".to_string(),
+            0,
+            "127.0.0.1".to_string(),
+            port,
+            txt,
+            0,
+        )
+    }
+}
+
+/// A private bus standing in for the system one.
+struct Bus {
+    daemon: Child,
+    address: String,
+}
+
+impl Bus {
+    fn new() -> Self {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start dbus-daemon");
+
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.as_mut().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+
+        Bus {
+            daemon,
+            address: address.trim().to_string(),
+        }
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+// Serves `sentence` on `port` over and over.
+fn serve(port: u16, sentence: &'static str) {
+    let service = TcpListener::bind(("127.0.0.1", port)).unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = service.accept().unwrap();
+        while stream.write_all(sentence.as_bytes()).is_ok() {
+            thread::sleep(Duration::from_millis(200));
+        }
+    });
+}
+
+#[test]
+fn network() {
+    serve(9359, GGA);
+    serve(9381, RMC);
+
+    let bus = Bus::new();
+    let resolved = Arc::new(Mutex::new(vec![]));
+    let avahi = MockAvahi {
+        port: 9359,
+        gps_share_port: 9381,
+        resolved: resolved.clone(),
+    };
+    let _connection = zbus::blocking::connection::Builder::address(bus.address.as_str())
+        .unwrap()
+        .name("org.freedesktop.Avahi")
+        .unwrap()
+        .serve_at("/", avahi)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    // Services on the network are only looked for when asked to.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-c", "/dev/null", "-p", "9360"])
+        .env("DBUS_SYSTEM_BUS_ADDRESS", &bus.address)
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+    thread::sleep(Duration::from_secs(2));
+    assert!(resolved.lock().unwrap().is_empty());
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    // Without any device around, the NMEA service on the other machine is used.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-c", "/dev/null", "-p", "9360", "--discover-network"])
+        .env("DBUS_SYSTEM_BUS_ADDRESS", &bus.address)
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+    let mut nmea = BufReader::new(connect(9360));
+    assert_eq!(read_line(&mut nmea), GGA);
+
+    // Ours is left alone, so that we don't end up reading from ourselves, and so is that of
+    // gps-share on the other machine, so that the two don't read from each other.
+    assert_eq!(*resolved.lock().unwrap(), ["gps-share", "remote"]);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
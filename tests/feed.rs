@@ -0,0 +1,134 @@
+/* vim: set et ts=4 sw=4: */
+/* feed.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::{connect, read_line};
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn continuous_read() {
+    let (mut child, mut device, path) = spawn_gps_share("continuous", 9366, &["--no-replay"]);
+
+    // What the device sends while there are no clients isn't kept for them.
+    device.write_all(epoch("123519").as_bytes()).unwrap();
+    device.write_all(epoch("123520").as_bytes()).unwrap();
+    let mut client = BufReader::new(connect(9366));
+    thread::sleep(Duration::from_millis(500));
+    let gga = gga("123521");
+    device.write_all(gga.as_bytes()).unwrap();
+    assert_eq!(read_line(&mut client), gga);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn replay() {
+    let (mut child, mut device, path) = spawn_gps_share("replay", 9367, &[]);
+
+    // New clients start with the last complete epoch, and what came of the current one so far.
+    let last = epoch("123520");
+    let current = gga("123521");
+    device.write_all(epoch("123519").as_bytes()).unwrap();
+    device.write_all(last.as_bytes()).unwrap();
+    device.write_all(current.as_bytes()).unwrap();
+    thread::sleep(Duration::from_millis(500));
+    let mut client = BufReader::new(connect(9367));
+    for line in last.lines().chain(current.lines()) {
+        assert_eq!(read_line(&mut client).trim_end(), line);
+    }
+
+    // ..followed by what comes next.
+    let next = gga("123522");
+    device.write_all(next.as_bytes()).unwrap();
+    assert_eq!(read_line(&mut client), next);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn quiet_device() {
+    let (mut child, _device, path) = spawn_gps_share("quiet", 9368, &["--client-commands"]);
+
+    // Commands get to the device even while it has nothing to say. What gps-share writes to the
+    // FIFO comes right back.
+    let command = "$PMTK220,200*2C\r\n";
+    let mut client = connect(9368);
+    client.write_all(command.as_bytes()).unwrap();
+    assert_eq!(read_line(&mut BufReader::new(client)), command);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    fs::remove_file(path).unwrap();
+}
+
+// Starts gps-share on a FIFO, which isn't a TTY so it's read as a kernel GNSS device would be.
+// Returns it, the FIFO's writing end and its path.
+fn spawn_gps_share(name: &str, port: u16, args: &[&str]) -> (Child, File, String) {
+    let path = format!("/tmp/gps-share-feed-{}.fifo", name);
+    let _ = fs::remove_file(&path);
+    let c_path = CString::new(path.clone()).unwrap();
+    // SAFETY: `c_path` is a valid NUL-terminated string.
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+    let child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-p", &port.to_string()])
+        .args(args)
+        .arg(&path)
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+    let device = OpenOptions::new().write(true).open(&path).unwrap();
+
+    (child, device, path)
+}
+
+fn epoch(time: &str) -> String {
+    let rmc = sentence(&format!(
+        "GPRMC,{},A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W",
+        time
+    ));
+
+    gga(time) + &rmc
+}
+
+fn gga(time: &str) -> String {
+    sentence(&format!(
+        "GPGGA,{},4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,",
+        time
+    ))
+}
+
+fn sentence(body: &str) -> String {
+    let checksum = body.bytes().fold(0, |checksum, byte| checksum ^ byte);
+
+    format!("${}*{:02X}\r\n", body, checksum)
+}
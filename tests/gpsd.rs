@@ -0,0 +1,199 @@
+/* vim: set et ts=4 sw=4: */
+/* gpsd.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::connect;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+
+#[test]
+fn gpsd() {
+    let port = 9320;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-", "--no-tcp", "--gpsd-port", &port.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+
+    let mut stream = connect(port);
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    let version = read_object(&mut reader);
+    assert_eq!(version["class"], "VERSION");
+    assert_eq!(version["proto_major"], 3);
+
+    stream
+        .write_all(b"?WATCH={\"enable\":true,\"json\":true};\n")
+        .unwrap();
+    let devices = read_object(&mut reader);
+    assert_eq!(devices["class"], "DEVICES");
+    assert_eq!(devices["devices"][0]["path"], "-");
+    let watch = read_object(&mut reader);
+    assert_eq!(watch["class"], "WATCH");
+    assert_eq!(watch["json"], true);
+
+    let epoch = "\
+                 $GPGSV,1,1,04,02,45,090,40,12,30,180,35,19,60,270,42,24,15,045,30*71\n\
+                 $GPGSA,A,3,02,12,19,24,,,,,,,,,9.6,6.5,7.1*37\n\
+                 $GPRMC,122732.000,A,5744.4784,N,01201.6130,E,0.0,0.0,300417,,,A*63\n\
+                 $GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\n";
+    let stdin = child.stdin.as_mut().unwrap();
+    stdin.write_all(epoch.as_bytes()).unwrap();
+    stdin.write_all(epoch.as_bytes()).unwrap();
+
+    let tpv = read_object(&mut reader);
+    assert_eq!(tpv["class"], "TPV");
+    assert_eq!(tpv["mode"], 3);
+    assert_eq!(tpv["time"], "2017-04-30T12:27:32.000Z");
+    assert!((tpv["lat"].as_f64().unwrap() - 57.74130666).abs() < 1e-6);
+    assert!((tpv["lon"].as_f64().unwrap() - 12.02688333).abs() < 1e-6);
+    assert_eq!(tpv["altMSL"], 61.7);
+
+    let sky = read_object(&mut reader);
+    assert_eq!(sky["class"], "SKY");
+    assert_eq!(sky["nSat"], 4);
+    assert_eq!(sky["uSat"], 4);
+    assert_eq!(sky["hdop"], 6.5);
+
+    // By now the end of the epoch is known, so the second one is reported straight away.
+    assert_eq!(read_object(&mut reader)["class"], "TPV");
+    assert_eq!(read_object(&mut reader)["class"], "SKY");
+
+    stream.write_all(b"?POLL;\n").unwrap();
+    let poll = read_object(&mut reader);
+    assert_eq!(poll["class"], "POLL");
+    assert_eq!(poll["tpv"][0]["class"], "TPV");
+
+    stream.write_all(b"?FOO;\n").unwrap();
+    let error = read_object(&mut reader);
+    assert_eq!(error["class"], "ERROR");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn multi_part_epochs() {
+    let port = 9357;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-", "--no-tcp", "--gpsd-port", &port.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+
+    let mut stream = connect(port);
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    assert_eq!(read_object(&mut reader)["class"], "VERSION");
+    stream
+        .write_all(b"?WATCH={\"enable\":true,\"json\":true};\n")
+        .unwrap();
+    assert_eq!(read_object(&mut reader)["class"], "DEVICES");
+    assert_eq!(read_object(&mut reader)["class"], "WATCH");
+
+    // A GNGSA per system and a GSV sequence in three parts, all of them in the same epoch.
+    let epochs = "\
+                  $GNGGA,122732.000,5744.4784,N,01201.6130,E,1,06,1.2,61.7,M,44.5,M,,*7E\n\
+                  $GNGSA,A,3,02,12,19,24,,,,,,,,,2.0,1.2,1.6*25\n\
+                  $GNGSA,A,3,65,72,,,,,,,,,,,2.0,1.2,1.6*2C\n\
+                  $GPGSV,3,1,09,02,45,090,40,12,30,180,35,19,60,270,42,24,15,045,30*7E\n\
+                  $GPGSV,3,2,09,05,10,010,,06,20,020,,07,30,030,,08,40,040,*7D\n\
+                  $GPGSV,3,3,09,09,50,050,*49\n\
+                  $GNRMC,122732.000,A,5744.4784,N,01201.6130,E,0.0,0.0,300417,,,A*7D\n\
+                  $GNGGA,122733.000,5744.4784,N,01201.6130,E,1,06,1.2,61.7,M,44.5,M,,*7F\n\
+                  $GNGSA,A,3,02,12,19,24,,,,,,,,,2.0,1.2,1.6*25\n\
+                  $GNGSA,A,3,65,72,,,,,,,,,,,2.0,1.2,1.6*2C\n\
+                  $GPGSV,3,1,09,02,45,090,40,12,30,180,35,19,60,270,42,24,15,045,30*7E\n\
+                  $GPGSV,3,2,09,05,10,010,,06,20,020,,07,30,030,,08,40,040,*7D\n\
+                  $GPGSV,3,3,09,09,50,050,*49\n\
+                  $GNRMC,122733.000,A,5744.4784,N,01201.6130,E,0.0,0.0,300417,,,A*7C\n";
+    let stdin = child.stdin.as_mut().unwrap();
+    stdin.write_all(epochs.as_bytes()).unwrap();
+
+    for time in ["2017-04-30T12:27:32.000Z", "2017-04-30T12:27:33.000Z"] {
+        let tpv = read_object(&mut reader);
+        assert_eq!(tpv["class"], "TPV");
+        assert_eq!(tpv["mode"], 3);
+        assert_eq!(tpv["time"], time);
+        assert!((tpv["lat"].as_f64().unwrap() - 57.74130666).abs() < 1e-6);
+
+        let sky = read_object(&mut reader);
+        assert_eq!(sky["class"], "SKY");
+        assert_eq!(sky["nSat"], 9);
+        assert_eq!(sky["uSat"], 4);
+    }
+
+    // Nothing more was reported in between.
+    stream.write_all(b"?POLL;\n").unwrap();
+    assert_eq!(read_object(&mut reader)["class"], "POLL");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn simulated() {
+    let port = 9376;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-", "--no-tcp", "--gpsd-port", &port.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+
+    let mut stream = connect(port);
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    assert_eq!(read_object(&mut reader)["class"], "VERSION");
+    stream
+        .write_all(b"?WATCH={\"enable\":true,\"json\":true};\n")
+        .unwrap();
+    assert_eq!(read_object(&mut reader)["class"], "DEVICES");
+    assert_eq!(read_object(&mut reader)["class"], "WATCH");
+
+    // A simulator's fix is status 8 for gpsd, as 7 is a surveyed time.
+    let epoch = "\
+                 $GPGSV,1,1,04,02,45,090,40,12,30,180,35,19,60,270,42,24,15,045,30*71\n\
+                 $GPGSA,A,3,02,12,19,24,,,,,,,,,9.6,6.5,7.1*37\n\
+                 $GPRMC,122732.000,A,5744.4784,N,01201.6130,E,0.0,0.0,300417,,,A*63\n\
+                 $GPGGA,122732.000,5744.4784,N,01201.6130,E,8,04,6.5,61.7,M,44.5,M,,0000*6B\n";
+    let stdin = child.stdin.as_mut().unwrap();
+    stdin.write_all(epoch.as_bytes()).unwrap();
+    stdin.write_all(epoch.as_bytes()).unwrap();
+
+    let tpv = read_object(&mut reader);
+    assert_eq!(tpv["class"], "TPV");
+    assert_eq!(tpv["status"], 8);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+fn read_object(reader: &mut BufReader<TcpStream>) -> Value {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+
+    serde_json::from_str(&line).unwrap()
+}
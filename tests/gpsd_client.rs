@@ -0,0 +1,146 @@
+/* vim: set et ts=4 sw=4: */
+/* gpsd_client.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::{connect, read_line, spawn_gps_share};
+use std::io::{BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+const GGA: &str = "$GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\r\n";
+const VERSION: &str =
+    "{\"class\":\"VERSION\",\"release\":\"3.25\",\"proto_major\":3,\"proto_minor\":15}\r\n";
+
+#[test]
+fn nmea() {
+    let gpsd = TcpListener::bind("127.0.0.1:9352").unwrap();
+    let mut child = spawn_gps_share(&["-p", "9353", "gpsd://127.0.0.1:9352"]);
+
+    let (mut stream, watch) = accept(&gpsd);
+    assert_eq!(watch, "?WATCH={\"enable\":true,\"nmea\":true};\n");
+    let mut nmea = BufReader::new(connect(9353));
+
+    // The JSON gpsd sends along is left out.
+    stream.write_all(VERSION.as_bytes()).unwrap();
+    stream.write_all(GGA.as_bytes()).unwrap();
+    assert_eq!(read_line(&mut nmea), GGA);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn json() {
+    let gpsd = TcpListener::bind("127.0.0.1:9354").unwrap();
+    let mut child = spawn_gps_share(&["-p", "9355", "gpsd://127.0.0.1:9354/json"]);
+
+    let (mut stream, watch) = accept(&gpsd);
+    assert_eq!(watch, "?WATCH={\"enable\":true,\"json\":true};\n");
+    let mut nmea = BufReader::new(connect(9355));
+
+    stream.write_all(VERSION.as_bytes()).unwrap();
+    let sky = "{\"class\":\"SKY\",\"hdop\":1.5,\"satellites\":[\
+               {\"PRN\":7,\"gnssid\":0,\"el\":45.0,\"az\":90.0,\"ss\":40.0,\"used\":true},\
+               {\"PRN\":65,\"gnssid\":6,\"el\":12.0,\"az\":300.0,\"ss\":20.0,\"used\":false}]}\r\n";
+    stream.write_all(sky.as_bytes()).unwrap();
+    let tpv = "{\"class\":\"TPV\",\"mode\":3,\"time\":\"2024-03-01T12:35:19.000Z\",\
+               \"lat\":48.1173,\"lon\":11.516666667,\"altMSL\":545.4,\"geoidSep\":46.6,\
+               \"speed\":1.0,\"track\":84.5,\"climb\":0.5,\"epx\":2.5,\"epy\":2.5,\"epv\":3.0}\r\n";
+    stream.write_all(tpv.as_bytes()).unwrap();
+
+    // NMEA is made up of the reports.
+    let gga = read_line(&mut nmea);
+    assert!(
+        gga.starts_with("$GPGGA,123519.00,4807.03800,N,01131.00000,E,1,01,1.5,545.4,M,46.6,M,,*"),
+        "{}",
+        gga
+    );
+    let gsa = read_line(&mut nmea);
+    assert!(gsa.starts_with("$GPGSA,A,3,07,,,,,,,,,,,,"), "{}", gsa);
+    let gsv = read_line(&mut nmea);
+    assert!(gsv.starts_with("$GPGSV,1,1,01,07,45,090,40*"), "{}", gsv);
+    let gsv = read_line(&mut nmea);
+    assert!(gsv.starts_with("$GLGSV,1,1,01,65,12,300,20*"), "{}", gsv);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn simulated() {
+    let gpsd = TcpListener::bind("127.0.0.1:9377").unwrap();
+    let mut child = spawn_gps_share(&["-p", "9378", "gpsd://127.0.0.1:9377/json"]);
+
+    let (mut stream, _) = accept(&gpsd);
+    let mut nmea = BufReader::new(connect(9378));
+
+    // Status 8 is a simulator's fix, while 7 is only a surveyed time.
+    stream.write_all(VERSION.as_bytes()).unwrap();
+    let sky = "{\"class\":\"SKY\",\"hdop\":1.5,\"satellites\":[\
+               {\"PRN\":7,\"gnssid\":0,\"el\":45.0,\"az\":90.0,\"ss\":40.0,\"used\":true}]}\r\n";
+    stream.write_all(sky.as_bytes()).unwrap();
+    let tpv = "{\"class\":\"TPV\",\"mode\":3,\"status\":8,\"time\":\"2024-03-01T12:35:19.000Z\",\
+               \"lat\":48.1173,\"lon\":11.516666667,\"altMSL\":545.4,\"geoidSep\":46.6}\r\n";
+    stream.write_all(tpv.as_bytes()).unwrap();
+
+    let gga = read_line(&mut nmea);
+    assert!(
+        gga.starts_with("$GPGGA,123519.00,4807.03800,N,01131.00000,E,8,"),
+        "{}",
+        gga
+    );
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn partial_line() {
+    let gpsd = TcpListener::bind("127.0.0.1:9379").unwrap();
+    let mut child = spawn_gps_share(&["-p", "9380", "gpsd://127.0.0.1:9379"]);
+
+    let (mut stream, _) = accept(&gpsd);
+    let mut nmea = BufReader::new(connect(9380));
+
+    // Reads time out in between, which is no reason to lose the start of the line.
+    let (start, end) = GGA.split_at(20);
+    stream.write_all(start.as_bytes()).unwrap();
+    thread::sleep(Duration::from_secs(4));
+    stream.write_all(end.as_bytes()).unwrap();
+    assert_eq!(read_line(&mut nmea), GGA);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+// Returns the connection from gps-share and the command it sent.
+fn accept(gpsd: &TcpListener) -> (TcpStream, String) {
+    let (stream, _) = gpsd.accept().unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    (stream, read_line(&mut reader))
+}
@@ -0,0 +1,138 @@
+/* vim: set et ts=4 sw=4: */
+/* hotplug.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::{connect, dev_dir, open_pty};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::symlink;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn start_without_device() {
+    let dir = dev_dir("start-without-device");
+    let port = 9327;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-c", "/dev/null", "-p", &port.to_string()])
+        .env("GPS_SHARE_DEV_DIR", &dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start gps-share");
+
+    // There's no GPS device in the directory, so we wait for one to be plugged in.
+    let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+    let waiting = stdout
+        .by_ref()
+        .map(|line| line.unwrap())
+        .any(|line| line.ends_with("waiting for one to be plugged in.."));
+    assert!(waiting);
+
+    // ..while serving clients already.
+    connect(port);
+    thread::sleep(Duration::from_secs(2));
+    assert!(child.try_wait().unwrap().is_none());
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    fs::remove_dir(dir).unwrap();
+}
+
+#[test]
+fn rescan() {
+    let dir = dev_dir("rescan");
+    let links = [dir.join("gps-share-test0"), dir.join("gps-share-test1")];
+
+    let (first, _first_device, first_path) = open_pty();
+    let (second, _second_device, second_path) = open_pty();
+    symlink(first_path, &links[0]).unwrap();
+    symlink(second_path, &links[1]).unwrap();
+
+    let unplugged = Arc::new(AtomicBool::new(false));
+    feed(
+        first,
+        "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47",
+        Some(unplugged.clone()),
+    );
+    feed(
+        second,
+        "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A",
+        None,
+    );
+
+    let port = 9361;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-c", "/dev/null", "-p", &port.to_string()])
+        .env("GPS_SHARE_DEV_DIR", &dir)
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+
+    // The first device gets picked..
+    let client = connect(port);
+    let mut lines = BufReader::new(client).lines().map(|line| line.unwrap());
+    assert!(
+        lines
+            .by_ref()
+            .take(20)
+            .any(|line| line.starts_with("$GPGGA"))
+    );
+
+    // ..and once it goes away, the one still around gets found without it being plugged in.
+    unplugged.store(true, Ordering::Relaxed);
+    assert!(
+        lines
+            .by_ref()
+            .take(50)
+            .any(|line| line.contains("GPS device found"))
+    );
+    assert!(lines.take(20).any(|line| line.starts_with("$GPRMC")));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    for link in links {
+        fs::remove_file(link).unwrap();
+    }
+    fs::remove_dir(dir).unwrap();
+}
+
+// Keeps sending `sentence` from the receiver's end, until `unplugged` is set, closing it.
+fn feed(mut receiver: File, sentence: &'static str, unplugged: Option<Arc<AtomicBool>>) {
+    thread::spawn(move || {
+        while !unplugged
+            .as_ref()
+            .is_some_and(|u| u.load(Ordering::Relaxed))
+        {
+            if receiver
+                .write_all(format!("{}\r\n", sentence).as_bytes())
+                .is_err()
+            {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    });
+}
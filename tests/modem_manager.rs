@@ -0,0 +1,183 @@
+/* vim: set et ts=4 sw=4: */
+/* modem_manager.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::{connect, read_line};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use zbus::fdo::ObjectManager;
+use zbus::interface;
+use zbus::zvariant::Value;
+
+const GGA: &str = "$GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\r\n";
+const RMC: &str = "$GPRMC,122733.000,A,5744.4784,N,01201.6130,E,0.0,0.0,300417,,,A*62\r\n";
+const NEXT_GGA: &str =
+    "$GPGGA,122733.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*63\r\n";
+const NEXT_RMC: &str = "$GPRMC,122734.000,A,5744.4784,N,01201.6130,E,0.0,0.0,300417,,,A*65\r\n";
+
+/// The part of ModemManager gps-share talks to, with a modem that has a GNSS.
+struct MockLocation {
+    trace: Mutex<String>,
+    setups: Arc<Mutex<Vec<(u32, bool)>>>,
+}
+
+const MODEM: &str = "/org/freedesktop/ModemManager1/Modem/0";
+
+#[interface(name = "org.freedesktop.ModemManager1.Modem.Location")]
+impl MockLocation {
+    fn setup(&self, sources: u32, signal_location: bool) {
+        self.setups.lock().unwrap().push((sources, signal_location));
+    }
+
+    #[zbus(property)]
+    fn capabilities(&self) -> u32 {
+        // 3GPP LAC/CI, GPS raw and NMEA.
+        1 | 2 | 4
+    }
+
+    #[zbus(property)]
+    fn enabled(&self) -> u32 {
+        1
+    }
+
+    #[zbus(property)]
+    fn signals_location(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn location(&self) -> HashMap<u32, Value<'static>> {
+        let trace = self.trace.lock().unwrap().clone();
+
+        HashMap::from([(4, Value::from(trace))])
+    }
+}
+
+// Has the modem update its location with `trace`, signalling the change.
+fn update(connection: &zbus::blocking::Connection, trace: &str) {
+    let location = connection
+        .object_server()
+        .interface::<_, MockLocation>(MODEM)
+        .unwrap();
+    *location.get().trace.lock().unwrap() = trace.to_string();
+    zbus::block_on(location.get().location_changed(location.signal_emitter())).unwrap();
+}
+
+/// A private bus standing in for the system one.
+struct Bus {
+    daemon: Child,
+    address: String,
+}
+
+impl Bus {
+    fn new() -> Self {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start dbus-daemon");
+
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.as_mut().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+
+        Bus {
+            daemon,
+            address: address.trim().to_string(),
+        }
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+#[test]
+fn modem_manager() {
+    let bus = Bus::new();
+    let setups = Arc::new(Mutex::new(Vec::new()));
+    let location = MockLocation {
+        trace: Mutex::new(String::new()),
+        setups: setups.clone(),
+    };
+    let connection = zbus::blocking::connection::Builder::address(bus.address.as_str())
+        .unwrap()
+        .name("org.freedesktop.ModemManager1")
+        .unwrap()
+        .serve_at("/org/freedesktop/ModemManager1", ObjectManager)
+        .unwrap()
+        .serve_at(MODEM, location)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    // Without a modem index, the one with location support is looked up.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-p", "9356", "modemmanager://"])
+        .env("DBUS_SYSTEM_BUS_ADDRESS", &bus.address)
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+    let mut nmea = BufReader::new(connect(9356));
+
+    // Update until gps-share is seen following the updates, then skip whatever is left of those.
+    nmea.get_ref()
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+    loop {
+        update(&connection, GGA);
+        let mut line = String::new();
+        if nmea.read_line(&mut line).is_ok() && line == GGA {
+            break;
+        }
+    }
+    nmea.get_ref()
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    update(&connection, &format!("{}{}", NEXT_GGA, RMC));
+    while read_line(&mut nmea) != NEXT_GGA {}
+    assert_eq!(read_line(&mut nmea), RMC);
+
+    // The whole trace is sent on with each update, even the sentences that are the same as the
+    // last time.
+    update(&connection, &format!("{}{}", NEXT_GGA, RMC));
+    assert_eq!(read_line(&mut nmea), NEXT_GGA);
+    assert_eq!(read_line(&mut nmea), RMC);
+    update(&connection, &format!("{}{}", NEXT_GGA, NEXT_RMC));
+    assert_eq!(read_line(&mut nmea), NEXT_GGA);
+    assert_eq!(read_line(&mut nmea), NEXT_RMC);
+
+    // The GNSS sources are enabled along with the one already on, and the location signalled.
+    assert_eq!(*setups.lock().unwrap(), [(1 | 2 | 4, true)]);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
@@ -0,0 +1,71 @@
+/* vim: set et ts=4 sw=4: */
+/* network.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::{connect, read_line, spawn_gps_share};
+use std::fs;
+use std::io::{BufReader, Write};
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+
+const GGA: &str = "$GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\r\n";
+const RMC: &str = "$GPRMC,122732.000,A,5744.4784,N,01201.6130,E,0.0,0.0,300417,,,A*63\r\n";
+
+#[test]
+fn tcp() {
+    let upstream = TcpListener::bind("127.0.0.1:9345").unwrap();
+    let mut child = spawn_gps_share(&["-p", "9346", "127.0.0.1:9345"]);
+
+    let (mut feed, _) = upstream.accept().unwrap();
+    let mut nmea = BufReader::new(connect(9346));
+    feed.write_all(GGA.as_bytes()).unwrap();
+    assert_eq!(read_line(&mut nmea), GGA);
+
+    // Once the feed goes away, it's connected to again.
+    drop(feed);
+    assert!(read_line(&mut nmea).contains("GPS device lost"));
+    let (mut feed, _) = upstream.accept().unwrap();
+    assert!(read_line(&mut nmea).contains("GPS device reconnected"));
+    feed.write_all(RMC.as_bytes()).unwrap();
+    assert_eq!(read_line(&mut nmea), RMC);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn unix_socket() {
+    let path = "/tmp/gps-share-network-test.sock";
+    let _ = fs::remove_file(path);
+    let upstream = UnixListener::bind(path).unwrap();
+    let mut child = spawn_gps_share(&["-p", "9347", path]);
+
+    let (mut feed, _) = upstream.accept().unwrap();
+    let mut nmea = BufReader::new(connect(9347));
+    feed.write_all(GGA.as_bytes()).unwrap();
+    assert_eq!(read_line(&mut nmea), GGA);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    fs::remove_file(path).unwrap();
+}
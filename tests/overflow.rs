@@ -0,0 +1,113 @@
+/* vim: set et ts=4 sw=4: */
+/* overflow.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::connect;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+// Way more than fits in the client's queue and socket buffers.
+const SENTENCES: u32 = 100_000;
+
+#[test]
+fn drop_oldest() {
+    let (mut child, client) = flood(9364, "drop-oldest");
+
+    // The client stays connected, getting the latest sentences but not all of them.
+    let mut received = 0;
+    let mut last = String::new();
+    let mut connected = false;
+    for line in BufReader::new(client).lines() {
+        match line {
+            Ok(line) => {
+                received += 1;
+                last = line;
+            }
+
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                connected = true;
+
+                break;
+            }
+
+            Err(e) => panic!("Failed to read from gps-share: {}", e),
+        }
+    }
+    assert!(connected, "gps-share disconnected the client");
+    assert!(received > 0 && received < SENTENCES, "{}", received);
+    assert_eq!(last, sentence(SENTENCES - 1).trim_end());
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn drop_client() {
+    let (mut child, mut client) = flood(9365, "drop-client");
+
+    // The client gets what was already on its way, and is then disconnected.
+    let mut data = vec![];
+    client.read_to_end(&mut data).unwrap();
+    let all: usize = (0..SENTENCES).map(|i| sentence(i).len()).sum();
+    assert!(data.len() < all, "{}", data.len());
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+// Starts gps-share with `policy` for full queues, and sends a client of it more than it can take
+// while it's not reading.
+fn flood(port: u16, policy: &str) -> (Child, TcpStream) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-", "-p", &port.to_string()])
+        .args(["--queue-size", "16", "--overflow-policy", policy])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+
+    let client = connect(port);
+    client
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let trace: String = (0..SENTENCES).map(sentence).collect();
+    let stdin = child.stdin.as_mut().unwrap();
+    stdin.write_all(trace.as_bytes()).unwrap();
+
+    (child, client)
+}
+
+// A GGA sentence, with a time of its own for each `i`.
+fn sentence(i: u32) -> String {
+    let body = format!(
+        "GPGGA,{:02}{:02}{:02},4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,",
+        i / 3600 % 24,
+        i / 60 % 60,
+        i % 60
+    );
+    let checksum = body.bytes().fold(0, |checksum, byte| checksum ^ byte);
+
+    format!("${}*{:02X}\r\n", body, checksum)
+}
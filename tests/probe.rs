@@ -0,0 +1,185 @@
+/* vim: set et ts=4 sw=4: */
+/* probe.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::{connect, dev_dir, open_pty, read_line};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const GGA: &str = "$GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62";
+const RMC: &str = "$GPRMC,122732.000,A,5744.4784,N,01201.6130,E,0.0,0.0,300417,,,A*63";
+// What comes through at the wrong baudrate, and once when the receiver powers up.
+const WRONG_RATE: &[u8] = b"\xe6\x98\x1f\xfe\x80~x\xf8\xe0\x1e\r\n";
+const POWER_UP: &[u8] = b"\x00\xff$GPGGA,1227\r\n";
+
+#[test]
+fn baudrate() {
+    let dir = dev_dir("baudrate");
+    let links = plug(&dir, &[(Some(9600), &[GGA, RMC])]);
+
+    // 38400 baud is tried first, then the others from the lowest up. The line of garbage the
+    // receiver starts with at the right one is no reason to give up on it.
+    let (mut child, mut stdout) = spawn_gps_share(&dir, 9374);
+    let detected = format!(
+        "Detected {} as a GPS device, at 9600 baud",
+        dir.join("gps-share-probe0").display()
+    );
+    wait_for_line(&mut stdout, &detected);
+    let mut nmea = BufReader::new(connect(9374));
+    assert!(read_line(&mut nmea).starts_with("$GP"));
+
+    unplug(&mut child, links);
+    fs::remove_dir(dir).unwrap();
+}
+
+#[test]
+fn modem() {
+    // The odd sentence among AT command replies doesn't make a modem a GPS receiver.
+    let dir = dev_dir("modem");
+    let links = plug(
+        &dir,
+        &[
+            (None, &["AT+CREG?", "+CREG: 0,1", "OK", GGA]),
+            (None, &[GGA, RMC]),
+        ],
+    );
+
+    let (mut child, mut stdout) = spawn_gps_share(&dir, 9375);
+    let line = wait_for_line(&mut stdout, "Detected ");
+    let detected = format!("Detected {} ", dir.join("gps-share-probe1").display());
+    assert!(line.starts_with(&detected), "{}", line);
+
+    unplug(&mut child, links);
+    fs::remove_dir(dir).unwrap();
+}
+
+// Links a pty for each of `receivers` as gps-share-probeN in `dir`, which send their lines over
+// and over, only at the given baudrate if any.
+fn plug(
+    dir: &Path,
+    receivers: &[(Option<u32>, &'static [&'static str])],
+) -> Vec<(PathBuf, Arc<AtomicBool>)> {
+    receivers
+        .iter()
+        .enumerate()
+        .map(|(i, &(baudrate, lines))| {
+            let link = dir.join(format!("gps-share-probe{}", i));
+            let _ = fs::remove_file(&link);
+            let (receiver, device, path) = open_pty();
+            symlink(path, &link).unwrap();
+
+            let unplugged = Arc::new(AtomicBool::new(false));
+            let done = unplugged.clone();
+            thread::spawn(move || {
+                // Kept open so that the receiver end doesn't get hung up on in between.
+                let _device = device;
+                send(receiver, baudrate, lines, done);
+            });
+
+            (link, unplugged)
+        })
+        .collect()
+}
+
+fn send(mut receiver: File, baudrate: Option<u32>, lines: &[&str], unplugged: Arc<AtomicBool>) {
+    let mut lines = lines.iter().cycle();
+    let mut powered = false;
+
+    while !unplugged.load(Ordering::Relaxed) {
+        let talking = baudrate.is_none_or(|baudrate| speed(&receiver) == baudrate);
+        let written = if !talking {
+            powered = false;
+
+            receiver.write_all(WRONG_RATE)
+        } else if !powered {
+            powered = true;
+
+            receiver.write_all(POWER_UP)
+        } else {
+            receiver.write_all(format!("{}\r\n", lines.next().unwrap()).as_bytes())
+        };
+        if written.is_err() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// The baudrate gps-share set the device end to.
+fn speed(receiver: &File) -> u32 {
+    // SAFETY: `termios` is only used once `TCGETS2` filled it in, which on the receiver's end
+    // gives the device's. It's what serialport sets arbitrary baudrates with.
+    unsafe {
+        let mut termios: libc::termios2 = std::mem::zeroed();
+        assert_eq!(
+            libc::ioctl(receiver.as_raw_fd(), libc::TCGETS2, &mut termios),
+            0
+        );
+
+        termios.c_ospeed
+    }
+}
+
+fn unplug(child: &mut Child, links: Vec<(PathBuf, Arc<AtomicBool>)>) {
+    child.kill().unwrap();
+    child.wait().unwrap();
+    for (link, unplugged) in links {
+        unplugged.store(true, Ordering::Relaxed);
+        fs::remove_file(link).unwrap();
+    }
+}
+
+// Starts gps-share looking for a device in `dir`, rather than given one.
+fn spawn_gps_share(dir: &Path, port: u16) -> (Child, BufReader<ChildStdout>) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-c", "/dev/null", "-p", &port.to_string()])
+        .env("GPS_SHARE_DEV_DIR", dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start gps-share");
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+
+    (child, stdout)
+}
+
+fn wait_for_line(stdout: &mut BufReader<ChildStdout>, prefix: &str) -> String {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stdout.read_line(&mut line).unwrap() == 0 {
+            panic!("gps-share exited before printing `{}`", prefix);
+        }
+
+        if line.starts_with(prefix) {
+            return line;
+        }
+    }
+}
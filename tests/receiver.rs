@@ -0,0 +1,258 @@
+/* vim: set et ts=4 sw=4: */
+/* receiver.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::open_pty;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::fd::AsRawFd;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+const GGA: &str = "$GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\r\n";
+
+#[test]
+fn mediatek() {
+    let (mut receiver, _device, path) = open_pty();
+    let (mut child, mut stdout) = spawn_gps_share(&[
+        "-p",
+        "9331",
+        "--receiver",
+        "mediatek",
+        "--update-rate",
+        "5",
+        "--sentences",
+        "GGA,RMC",
+        &path,
+    ]);
+
+    // Receivers are configured once they start talking.
+    receiver.write_all(GGA.as_bytes()).unwrap();
+    assert_eq!(read_line(&mut receiver), "$PMTK220,200*2C\r\n");
+    receiver.write_all(b"$PMTK001,220,3*30\r\n").unwrap();
+    assert_eq!(
+        read_line(&mut receiver),
+        "$PMTK314,0,1,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0*28\r\n"
+    );
+    receiver.write_all(b"$PMTK001,314,3*36\r\n").unwrap();
+    wait_for_line(&mut stdout, "Receiver configured");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn ublox_rejection() {
+    let (mut receiver, _device, path) = open_pty();
+    let (mut child, mut stdout) = spawn_gps_share(&[
+        "-p",
+        "9332",
+        "--receiver",
+        "ublox",
+        "--update-rate",
+        "2",
+        &path,
+    ]);
+
+    receiver.write_all(GGA.as_bytes()).unwrap();
+    answer_version(&mut receiver, "27.11");
+    // CFG-RATE-MEAS, to 500ms.
+    let payload = [0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0x21, 0x30, 0xf4, 0x01];
+    let valset = frame(0x06, 0x8a, &payload);
+    assert_eq!(read_bytes(&mut receiver, valset.len()), valset);
+    receiver
+        .write_all(&frame(0x05, 0x00, &[0x06, 0x8a]))
+        .unwrap();
+    wait_for_line(
+        &mut stdout,
+        "Failed to configure receiver: Receiver rejected UBX-CFG-VALSET",
+    );
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn ublox_8() {
+    let (mut receiver, _device, path) = open_pty();
+    let (mut child, mut stdout) = spawn_gps_share(&[
+        "-p",
+        "9382",
+        "--receiver",
+        "ublox",
+        "--update-rate",
+        "2",
+        &path,
+    ]);
+
+    // Receivers before u-blox 9 don't know of CFG-VALSET, and get CFG-RATE instead.
+    receiver.write_all(GGA.as_bytes()).unwrap();
+    answer_version(&mut receiver, "18.00");
+    let rate = frame(0x06, 0x08, &[0xf4, 0x01, 0x01, 0x00, 0x01, 0x00]);
+    assert_eq!(read_bytes(&mut receiver, rate.len()), rate);
+    receiver
+        .write_all(&frame(0x05, 0x01, &[0x06, 0x08]))
+        .unwrap();
+    wait_for_line(&mut stdout, "Receiver configured");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn sirf_binary() {
+    let (mut receiver, _device, path) = open_pty();
+    let (mut child, mut stdout) =
+        spawn_gps_share(&["-p", "9342", "-b", "4800", "--receiver", "sirf", &path]);
+
+    // Geodetic Navigation Data without a fix, the sentences made up of which get the receiver
+    // configured.
+    let mut navigation = vec![0; 91];
+    navigation[0] = 41;
+    receiver.write_all(&sirf_frame(&navigation)).unwrap();
+    // Back to NMEA, at 4800 baud.
+    let mut payload = vec![
+        0x81, 0x02, 1, 1, 0, 1, 1, 1, 5, 1, 1, 1, 0, 1, 0, 1, 0, 1, 0, 1,
+    ];
+    payload.extend([0x00, 0x00, 0x12, 0xc0]);
+    let switch = sirf_frame(&payload);
+    assert_eq!(read_bytes(&mut receiver, switch.len()), switch);
+    wait_for_line(&mut stdout, "Receiver configured");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn settings_need_receiver() {
+    let output = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "--update-rate", "5", "-"])
+        .output()
+        .expect("Failed to run gps-share");
+
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Configuring the receiver needs --receiver\n"
+    );
+}
+
+// Returns the receiver's end, the device's end (which has to stay open) and the device's path.
+
+fn spawn_gps_share(args: &[&str]) -> (Child, BufReader<ChildStdout>) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .arg("-a")
+        .args(args)
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start gps-share");
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    // The device is open by then.
+    wait_for_line(&mut stdout, "Port: ");
+
+    (child, stdout)
+}
+
+// Reads what gps-share wrote to the device, making sure we don't wait forever for it.
+fn read_bytes(receiver: &mut File, len: usize) -> Vec<u8> {
+    let mut bytes = vec![];
+    while bytes.len() < len {
+        wait_readable(receiver);
+        let mut byte = [0];
+        receiver.read_exact(&mut byte).unwrap();
+        bytes.push(byte[0]);
+    }
+
+    bytes
+}
+
+fn read_line(receiver: &mut File) -> String {
+    let mut line = vec![];
+    while line.last() != Some(&b'\n') {
+        line.extend(read_bytes(receiver, 1));
+    }
+
+    String::from_utf8(line).unwrap()
+}
+
+fn wait_readable(file: &File) {
+    let mut fd = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `fd` is a valid pollfd and we pass a count of 1.
+    let ret = unsafe { libc::poll(&mut fd, 1, 15_000) };
+    assert_eq!(ret, 1, "gps-share didn't write to the device");
+}
+
+fn wait_for_line(stdout: &mut BufReader<ChildStdout>, prefix: &str) -> String {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stdout.read_line(&mut line).unwrap() == 0 {
+            panic!("gps-share exited before printing `{}`", prefix);
+        }
+
+        if line.starts_with(prefix) {
+            return line;
+        }
+    }
+}
+
+// Answers gps-share's poll of the versions, with the `protocol` version.
+fn answer_version(receiver: &mut File, protocol: &str) {
+    let poll = frame(0x0a, 0x04, &[]);
+    assert_eq!(read_bytes(receiver, poll.len()), poll);
+
+    let mut payload = vec![0; 70];
+    payload[..4].copy_from_slice(b"ROM ");
+    payload[30..38].copy_from_slice(b"00080000");
+    let extension = format!("PROTVER={}", protocol);
+    payload[40..40 + extension.len()].copy_from_slice(extension.as_bytes());
+    receiver.write_all(&frame(0x0a, 0x04, &payload)).unwrap();
+}
+
+fn frame(class: u8, id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0xb5, 0x62, class, id];
+    frame.extend((payload.len() as u16).to_le_bytes());
+    frame.extend(payload);
+    let (mut a, mut b) = (0u8, 0u8);
+    for byte in &frame[2..] {
+        a = a.wrapping_add(*byte);
+        b = b.wrapping_add(a);
+    }
+    frame.extend([a, b]);
+
+    frame
+}
+
+fn sirf_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0xa0, 0xa2];
+    frame.extend((payload.len() as u16).to_be_bytes());
+    frame.extend(payload);
+    let checksum = payload.iter().map(|&b| u16::from(b)).sum::<u16>() & 0x7fff;
+    frame.extend(checksum.to_be_bytes());
+    frame.extend([0xb0, 0xb3]);
+
+    frame
+}
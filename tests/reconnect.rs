@@ -0,0 +1,96 @@
+/* vim: set et ts=4 sw=4: */
+/* reconnect.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::{connect, read_line};
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn reconnect() {
+    // A FIFO isn't a TTY, so it's read as a kernel GNSS device would be and every writer closing
+    // it looks like the device going away.
+    let path = "/tmp/gps-share-reconnect.fifo";
+    let _ = fs::remove_file(path);
+    let c_path = CString::new(path).unwrap();
+    // SAFETY: `c_path` is a valid NUL-terminated string.
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+    let port = 9326;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-p", &port.to_string(), path])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start gps-share");
+
+    let gga = "$GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\n";
+    let mut device = OpenOptions::new().write(true).open(path).unwrap();
+    device.write_all(gga.as_bytes()).unwrap();
+    let client = connect(port);
+    let mut client = BufReader::new(client);
+    assert_eq!(read_line(&mut client), gga);
+
+    // Unplug.
+    drop(device);
+    assert_eq!(
+        read_line(&mut client),
+        "$GPTXT,01,01,01,gps-share: GPS device lost*28\r\n"
+    );
+
+    // Plug back in.
+    let mut device = OpenOptions::new().write(true).open(path).unwrap();
+    device.write_all(gga.as_bytes()).unwrap();
+    assert_eq!(
+        read_line(&mut client),
+        "$GPTXT,01,01,02,gps-share: GPS device reconnected*47\r\n"
+    );
+    assert_eq!(read_line(&mut client), gga);
+
+    // Unplug for good, which gets reported once however many attempts at reopening fail.
+    drop(device);
+    fs::remove_file(path).unwrap();
+    assert_eq!(
+        read_line(&mut client),
+        "$GPTXT,01,01,01,gps-share: GPS device lost*28\r\n"
+    );
+    thread::sleep(Duration::from_secs(4));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    let mut output = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    let failures = output
+        .lines()
+        .filter(|line| line.starts_with("Failed to reopen GPS device"))
+        .count();
+    assert_eq!(failures, 1, "{}", output);
+}
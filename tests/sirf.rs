@@ -0,0 +1,143 @@
+/* vim: set et ts=4 sw=4: */
+/* sirf.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::{connect, read_line};
+use serde_json::Value;
+use std::io::{BufReader, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+
+#[test]
+fn sirf() {
+    let port = 9343;
+    let gpsd_port = 9344;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-", "-p", &port.to_string()])
+        .args(["--gpsd-port", &gpsd_port.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+
+    let mut gpsd = connect(gpsd_port);
+    let mut gpsd_reader = BufReader::new(gpsd.try_clone().unwrap());
+    assert_eq!(read_object(&mut gpsd_reader)["class"], "VERSION");
+    gpsd.write_all(b"?WATCH={\"enable\":true,\"json\":true};\n")
+        .unwrap();
+    assert_eq!(read_object(&mut gpsd_reader)["class"], "DEVICES");
+    assert_eq!(read_object(&mut gpsd_reader)["class"], "WATCH");
+    let mut nmea = BufReader::new(connect(port));
+
+    let stdin = child.stdin.as_mut().unwrap();
+    stdin.write_all(&frame(&measured_tracker())).unwrap();
+    stdin.write_all(&frame(&geodetic_navigation())).unwrap();
+
+    let gga = read_line(&mut nmea);
+    assert!(
+        gga.starts_with("$GPGGA,123519.00,4807.03800,N,01131.00000,E,1,09,1.0,545.4,M,46.6,M,,*"),
+        "{}",
+        gga
+    );
+    let gsa = read_line(&mut nmea);
+    assert!(gsa.starts_with("$GPGSA,A,3,07,,,,,,,,,,,,"), "{}", gsa);
+    let gsv = read_line(&mut nmea);
+    assert!(
+        gsv.starts_with("$GPGSV,1,1,02,07,45,090,40,09,12,300,20*"),
+        "{}",
+        gsv
+    );
+
+    let tpv = read_object(&mut gpsd_reader);
+    assert_eq!(tpv["class"], "TPV");
+    assert_eq!(tpv["mode"], 3);
+    assert_eq!(tpv["time"], "2024-03-01T12:35:19.000Z");
+    assert!((tpv["lat"].as_f64().unwrap() - 48.1173).abs() < 1e-7);
+    assert!((tpv["lon"].as_f64().unwrap() - 11.5166667).abs() < 1e-7);
+    assert_eq!(tpv["altMSL"], 545.4);
+    assert_eq!(tpv["climb"], 0.5);
+    assert_eq!(tpv["epv"], 3.0);
+
+    let sky = read_object(&mut gpsd_reader);
+    assert_eq!(sky["class"], "SKY");
+    assert_eq!(sky["nSat"], 2);
+    assert_eq!(sky["uSat"], 1);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+fn geodetic_navigation() -> Vec<u8> {
+    let mut payload = vec![0; 91];
+    let mut put = |offset: usize, bytes: &[u8]| {
+        payload[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    put(0, &[41]);
+    // More than 3 satellites in the Kalman filter.
+    put(3, &4u16.to_be_bytes());
+    put(11, &2024u16.to_be_bytes());
+    // Month, day, hour and minute.
+    put(13, &[3, 1, 12, 35]);
+    put(17, &19_000u16.to_be_bytes());
+    // Satellite 7 used.
+    put(19, &0x40u32.to_be_bytes());
+    put(23, &481_173_000i32.to_be_bytes());
+    put(27, &115_166_667i32.to_be_bytes());
+    put(31, &59_200i32.to_be_bytes());
+    put(35, &54_540i32.to_be_bytes());
+    put(40, &100u16.to_be_bytes());
+    put(42, &8_450u16.to_be_bytes());
+    put(46, &50i16.to_be_bytes());
+    put(50, &250u32.to_be_bytes());
+    put(54, &300u32.to_be_bytes());
+    // 9 satellites, HDOP of 1.
+    put(88, &[9, 5]);
+
+    payload
+}
+
+fn measured_tracker() -> Vec<u8> {
+    let mut payload = vec![4, 0x08, 0x00, 0, 0, 0, 0, 2];
+    // Azimuth is in 2/3 degrees and elevation in 1/2 degrees.
+    for (prn, azimuth, elevation, cno) in [(7u8, 60u8, 90u8, 40u8), (9, 200, 24, 20)] {
+        payload.extend([prn, azimuth, elevation, 0x00, 0xbf]);
+        payload.extend([cno; 10]);
+    }
+
+    payload
+}
+
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0xa0, 0xa2];
+    frame.extend((payload.len() as u16).to_be_bytes());
+    frame.extend(payload);
+    let checksum = payload.iter().map(|&b| u16::from(b)).sum::<u16>() & 0x7fff;
+    frame.extend(checksum.to_be_bytes());
+    frame.extend([0xb0, 0xb3]);
+
+    frame
+}
+
+fn read_object(reader: &mut BufReader<TcpStream>) -> Value {
+    serde_json::from_str(&read_line(reader)).unwrap()
+}
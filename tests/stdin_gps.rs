@@ -20,6 +20,14 @@
  *
  * Author: Zeeshan Ali <zeeshanak@gnome.org>
  */
+// The original test predates these lints, and is kept as it was written.
+#![allow(
+    clippy::from_str_radix_10,
+    clippy::match_like_matches_macro,
+    clippy::needless_borrow,
+    clippy::needless_borrows_for_generic_args,
+    clippy::unused_io_amount
+)]
 use std::fs;
 use std::io::Read;
 use std::io::Write;
@@ -118,6 +126,48 @@ fn test_stdin_gps(tcp_port: Option<u16>, net_iface: Option<&str>, local_socket:
     }
 }
 
+#[test]
+fn bad_checksum() {
+    // The checksum is optional, so sentences without one are always passed on.
+    let nmea_trace = "\
+                      $GPVTG,0.0,T,,M,0.0,N,0.0,K,A*0D\n\
+                      $GPGSA,A,3,02,12,19,24,,,,,,,,,9.6,6.5,7.3*37\n\
+                      $GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\n\
+                      $GPVTG,0.0,T,,M,0.0,N,0.0,K,A\n";
+
+    let dropped = "\
+                   $GPVTG,0.0,T,,M,0.0,N,0.0,K,A*0D\n\
+                   $GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\n\
+                   $GPVTG,0.0,T,,M,0.0,N,0.0,K,A\n";
+    test_bad_checksum(9316, "drop", nmea_trace, dropped);
+
+    test_bad_checksum(9317, "forward", nmea_trace, nmea_trace);
+
+    let repaired = "\
+                    $GPVTG,0.0,T,,M,0.0,N,0.0,K,A*0D\n\
+                    \\t:repaired*52\\$GPGSA,A,3,02,12,19,24,,,,,,,,,9.6,6.5,7.3*35\r\n\
+                    $GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\n\
+                    $GPVTG,0.0,T,,M,0.0,N,0.0,K,A\n";
+    test_bad_checksum(9318, "repair", nmea_trace, repaired);
+}
+
+fn test_bad_checksum(port: u16, policy: &str, nmea_trace: &str, expected: &str) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-", "-p", &port.to_string(), "--bad-checksum", policy])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start gps-share");
+
+    assert_eq!(get_port_from_child(&mut child), Some(port));
+    write_nmea_to_child(&mut child, nmea_trace);
+
+    let trace = get_nmea_from_port(port, expected.len());
+    assert_eq!(trace, expected);
+
+    child.kill().unwrap();
+}
+
 fn write_nmea_to_child(child: &mut Child, nmea_trace: &str) {
     if let Some(ref mut stdin) = child.stdin {
         let len = stdin.write(nmea_trace.as_ref()).unwrap();
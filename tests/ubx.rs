@@ -0,0 +1,243 @@
+/* vim: set et ts=4 sw=4: */
+/* ubx.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::{connect, open_pty, read_line};
+use serde_json::Value;
+use std::io::{BufReader, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn ubx() {
+    let port = 9329;
+    let gpsd_port = 9330;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-", "-p", &port.to_string()])
+        .args(["--gpsd-port", &gpsd_port.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+
+    let mut gpsd = connect(gpsd_port);
+    let mut gpsd_reader = BufReader::new(gpsd.try_clone().unwrap());
+    assert_eq!(read_object(&mut gpsd_reader)["class"], "VERSION");
+    gpsd.write_all(b"?WATCH={\"enable\":true,\"json\":true};\n")
+        .unwrap();
+    assert_eq!(read_object(&mut gpsd_reader)["class"], "DEVICES");
+    assert_eq!(read_object(&mut gpsd_reader)["class"], "WATCH");
+    let mut nmea = BufReader::new(connect(port));
+
+    // UBX and NMEA on the same stream, as u-blox receivers can send.
+    let txt = "$GPTXT,01,01,02,u-blox ag - www.u-blox.com*50\r\n";
+    let stdin = child.stdin.as_mut().unwrap();
+    stdin.write_all(txt.as_bytes()).unwrap();
+    stdin.write_all(&frame(0x01, 0x07, &nav_pvt(1000))).unwrap();
+    stdin.write_all(&frame(0x01, 0x35, &nav_sat(1000))).unwrap();
+    stdin
+        .write_all(&frame(0x01, 0x61, &1000u32.to_le_bytes()))
+        .unwrap();
+
+    assert_eq!(read_line(&mut nmea), txt);
+    let gga = read_line(&mut nmea);
+    assert!(
+        gga.starts_with("$GPGGA,123519.00,4807.03800,N,01131.00000,E,1,09,,545.4,M,46.6,M,,*"),
+        "{}",
+        gga
+    );
+    let gsa = read_line(&mut nmea);
+    assert!(
+        gsa.starts_with("$GPGSA,A,3,07,,,,,,,,,,,,1.5,,*"),
+        "{}",
+        gsa
+    );
+    let gsv = read_line(&mut nmea);
+    assert!(gsv.starts_with("$GPGSV,1,1,01,07,45,090,40*"), "{}", gsv);
+    let gsv = read_line(&mut nmea);
+    assert!(gsv.starts_with("$GLGSV,1,1,01,65,12,300,20*"), "{}", gsv);
+    let rmc = read_line(&mut nmea);
+    assert!(
+        rmc.starts_with("$GPRMC,123519.00,A,4807.03800,N,01131.00000,E,1.944,84.50,010324,,,A*"),
+        "{}",
+        rmc
+    );
+    let gst = read_line(&mut nmea);
+    assert!(
+        gst.starts_with("$GPGST,123519.00,,,,,2.5,2.5,3.0*"),
+        "{}",
+        gst
+    );
+
+    let tpv = read_object(&mut gpsd_reader);
+    assert_eq!(tpv["class"], "TPV");
+    assert_eq!(tpv["mode"], 3);
+    assert_eq!(tpv["time"], "2024-03-01T12:35:19.000Z");
+    assert!((tpv["lat"].as_f64().unwrap() - 48.1173).abs() < 1e-7);
+    assert!((tpv["lon"].as_f64().unwrap() - 11.5166667).abs() < 1e-7);
+    assert_eq!(tpv["altMSL"], 545.4);
+    assert_eq!(tpv["climb"], 0.5);
+    assert_eq!(tpv["epv"], 3.0);
+
+    let sky = read_object(&mut gpsd_reader);
+    assert_eq!(sky["class"], "SKY");
+    assert_eq!(sky["nSat"], 2);
+    assert_eq!(sky["uSat"], 1);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn resync() {
+    let port = 9362;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-", "-p", &port.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+    let mut nmea = BufReader::new(connect(port));
+
+    // Stray bytes that look like the start of a frame, and frames that don't check out, don't
+    // take the sentences after them along.
+    let mut bad_ubx = frame(0x01, 0x99, &[0; 8]);
+    *bad_ubx.last_mut().unwrap() ^= 0xff;
+    let bad_rtcm = [0xd3, 0x00, 0x04, 1, 2, 3, 4, 0, 0, 0];
+    let garbage: [&[u8]; 6] = [
+        &[0xb5],
+        &[0xd3],
+        &[0xa0],
+        &[0xa0, 0xa2, 0x00, 0x02],
+        &bad_ubx,
+        &bad_rtcm,
+    ];
+    let stdin = child.stdin.as_mut().unwrap();
+    let mut sentences = vec![];
+    for (i, garbage) in garbage.iter().enumerate() {
+        let txt = sentence(&format!("GPTXT,01,01,02,after garbage {}", i));
+        stdin.write_all(garbage).unwrap();
+        stdin.write_all(txt.as_bytes()).unwrap();
+        sentences.push(txt);
+    }
+
+    for txt in sentences {
+        assert_eq!(read_line(&mut nmea), txt);
+    }
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn partial_line() {
+    let (mut receiver, _device, path) = open_pty();
+    let port = 9363;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gps-share"))
+        .args(["-a", "-p", &port.to_string(), &path])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start gps-share");
+    let mut nmea = BufReader::new(connect(port));
+
+    // The device's read timeout passing in the middle of a sentence doesn't cut it.
+    let gga = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n";
+    let (start, end) = gga.split_at(20);
+    receiver.write_all(start.as_bytes()).unwrap();
+    thread::sleep(Duration::from_millis(3500));
+    receiver.write_all(end.as_bytes()).unwrap();
+    assert_eq!(read_line(&mut nmea), gga);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+fn sentence(body: &str) -> String {
+    let checksum = body.bytes().fold(0, |checksum, byte| checksum ^ byte);
+
+    format!("${}*{:02X}\r\n", body, checksum)
+}
+
+fn nav_pvt(itow: u32) -> Vec<u8> {
+    let mut payload = vec![0; 92];
+    let mut put = |offset: usize, bytes: &[u8]| {
+        payload[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    put(0, &itow.to_le_bytes());
+    put(4, &2024u16.to_le_bytes());
+    // Month, day, hour, minute, second, and valid date and time.
+    put(6, &[3, 1, 12, 35, 19, 0x03]);
+    // 3D fix, fix OK, 9 satellites.
+    put(20, &[3, 0x01, 0, 9]);
+    put(24, &115_166_667i32.to_le_bytes());
+    put(28, &481_173_000i32.to_le_bytes());
+    put(32, &592_000i32.to_le_bytes());
+    put(36, &545_400i32.to_le_bytes());
+    put(40, &2_500u32.to_le_bytes());
+    put(44, &3_000u32.to_le_bytes());
+    put(56, &(-500i32).to_le_bytes());
+    put(60, &1_000i32.to_le_bytes());
+    put(64, &8_450_000i32.to_le_bytes());
+    put(76, &150u16.to_le_bytes());
+
+    payload
+}
+
+fn nav_sat(itow: u32) -> Vec<u8> {
+    let mut payload = itow.to_le_bytes().to_vec();
+    payload.extend([1, 2, 0, 0]);
+    // GPS 7, used, and GLONASS 65, not used.
+    for (gnss, sv, cno, elevation, azimuth, flags) in [
+        (0u8, 7u8, 40u8, 45i8, 90i16, 0x08u32),
+        (6, 65, 20, 12, 300, 0),
+    ] {
+        payload.extend([gnss, sv, cno, elevation as u8]);
+        payload.extend(azimuth.to_le_bytes());
+        payload.extend(0i16.to_le_bytes());
+        payload.extend(flags.to_le_bytes());
+    }
+
+    payload
+}
+
+fn frame(class: u8, id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0xb5, 0x62, class, id];
+    frame.extend((payload.len() as u16).to_le_bytes());
+    frame.extend(payload);
+    let (mut a, mut b) = (0u8, 0u8);
+    for byte in &frame[2..] {
+        a = a.wrapping_add(*byte);
+        b = b.wrapping_add(a);
+    }
+    frame.extend([a, b]);
+
+    frame
+}
+
+fn read_object(reader: &mut BufReader<TcpStream>) -> Value {
+    serde_json::from_str(&read_line(reader)).unwrap()
+}
+
+// Returns the receiver's end, the device's end (which has to stay open) and the device's path.
@@ -0,0 +1,66 @@
+/* vim: set et ts=4 sw=4: */
+/* udp.rs
+ *
+ * Copyright (C) 2017 Pelagicore AB.
+ * Copyright (C) 2017 Zeeshan Ali.
+ *
+ * GPSShare is free software; you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation; either version 2 of the License, or (at your option)
+ * any later version.
+ *
+ * GPSShare is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with GPSShare; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin St, Fifth Floor, Boston, MA  02110-1301  USA
+ *
+ * Author: Zeeshan Ali <zeeshanak@gnome.org>
+ */
+mod common;
+
+use common::{connect, read_line, spawn_gps_share};
+use std::io::BufReader;
+use std::net::UdpSocket;
+
+const GGA: &str = "$GPGGA,122732.000,5744.4784,N,01201.6130,E,1,04,6.5,61.7,M,44.5,M,,0000*62\r\n";
+const RMC: &str = "$GPRMC,122732.000,A,5744.4784,N,01201.6130,E,0.0,0.0,300417,,,A*63\r\n";
+
+#[test]
+fn unicast() {
+    let mut child = spawn_gps_share(&["-p", "9348", "udp://127.0.0.1:9349"]);
+    let mut nmea = BufReader::new(connect(9348));
+
+    // Several sentences in a datagram, the last one left unterminated.
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let datagram = format!("{}{}", GGA, RMC.trim_end());
+    sender
+        .send_to(datagram.as_bytes(), "127.0.0.1:9349")
+        .unwrap();
+    assert_eq!(read_line(&mut nmea), GGA);
+    assert_eq!(read_line(&mut nmea), RMC);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn multicast() {
+    let mut child = spawn_gps_share(&["-p", "9350", "udp://239.192.0.1:9351"]);
+    let mut nmea = BufReader::new(connect(9350));
+
+    // Sent out of the interface of the default route, the one the group is joined on, and looped
+    // back to us.
+    let sender = UdpSocket::bind("0.0.0.0:0").unwrap();
+    sender.set_multicast_loop_v4(true).unwrap();
+    sender.send_to(GGA.as_bytes(), "239.192.0.1:9351").unwrap();
+    assert_eq!(read_line(&mut nmea), GGA);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+// The socket is bound before the TCP service starts, so datagrams sent from then on get through.